@@ -1,16 +1,14 @@
-///! G-Code generator for cutting fluted mill tools
-
-///! For an example of where I use this, see http://www.helicron.net/workshop/gearcutting/gear_cutter/
-///! We don't do the actual tooth cutting here (yet), that still needs to be done on a lathe. This just turns the round
-///! hobber into a tool with sharp teeth and back relief behind the teeth.
-use gcode::{
-    g0, g1, gcode_comment, inv_feed_g93, preamble, standard_feed_g94, trailer, xyza, zaf, zf,
-};
-use std::f64::consts::PI;
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Result, Write};
-use std::path::PathBuf;
-use structopt::StructOpt;
+//! G-Code generator for cutting fluted mill tools
+//!
+//! For an example of where I use this, see http://www.helicron.net/workshop/gearcutting/gear_cutter/
+//! We don't do the actual tooth cutting here (yet), that still needs to be done on a lathe. This just turns the round
+//! hobber into a tool with sharp teeth and back relief behind the teeth.
+use gcode::cli::{self, CommonOpts};
+use gcode::flutes::{self, FluteCut, ToolType};
+use gcode::svg::Stock;
+use gcode::{job_header, trailer, JobInfo, Milling};
+use std::io::Result;
+use structopt::{clap, StructOpt};
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -22,15 +20,20 @@ struct Opt {
     #[structopt(long)]
     flutes: u32,
 
-    /// Max depth of each flute, in mm
-    #[structopt(long)]
+    /// Max depth of each flute, at the right-hand (X0) end
+    #[structopt(long, alias = "depth-start")]
     depth: f64,
 
-    /// Length of the cutter we're creating, in mm
+    /// Max depth of each flute at the far (-X) end, for flutes that get deeper or shallower along the length
+    /// [default: the same as --depth]
+    #[structopt(long)]
+    depth_end: Option<f64>,
+
+    /// Length of the cutter we're creating
     #[structopt(long, default_value = "20")]
     len: f64,
 
-    /// Diameter of cutter we're creating, in mm
+    /// Diameter of cutter we're creating
     #[structopt(long)]
     dia: f64,
 
@@ -38,23 +41,24 @@ struct Opt {
     #[structopt(long, default_value = "4500")]
     rpm: f64,
 
-    /// Feed rate, in mm/min
+    /// Feed rate, in units/min
     #[structopt(long, default_value = "220")]
     feed: f64,
 
-    /// Name for the job
-    #[structopt(short, long)]
-    name: Option<String>,
-
     /// Tool number for the cut
     #[structopt(long, default_value = "1")]
     tool: u32,
 
-    /// Diameter of tool, in mm
+    /// Diameter of tool
     #[structopt(long, default_value = "3.175")]
     tool_dia: f64,
 
-    /// Max cutting stepdown, per pass, in mm
+    /// Shape of the end of the tool: ball or flat. The ends of the passes are backed off by how far the tool reaches
+    /// at each depth.
+    #[structopt(long, default_value = "ball")]
+    tool_type: ToolType,
+
+    /// Max cutting stepdown, per pass
     #[structopt(long, default_value = "3")]
     max_stepdown: f64,
 
@@ -66,131 +70,205 @@ struct Opt {
     #[structopt(long, default_value = "25")]
     spiral_angle: f64,
 
-    /// Output file for the resulting G code
-    #[structopt(short, long, parse(from_os_str))]
-    output: PathBuf,
+    /// Number of starts, for a multi-start hob. The flutes are split evenly between the starts, and each one's helix
+    /// advances this many times as far along the axis per turn as a single-start helix at --spiral-angle.
+    #[structopt(long, default_value = "1")]
+    starts: u32,
 
+    /// Relief angle (degrees) behind each tooth, for a constant-relief (logarithmic) back that keeps its clearance as
+    /// the teeth are resharpened. Without this the back is a straight ramp down to the full depth.
     #[structopt(long)]
-    coolant: bool,
-}
+    relief_angle: Option<f64>,
 
-/// Calculate the feed rate we need to tell the machine to get a real surface feed rate of `target_feed`, in units of
-/// 1/minutes (for G93 inverse feed rate mode)
-/// LinuxCNC says this about the way feed rate is interpreted during simultaneous multi-axis:
-///   "If any of XYZ are moving, F is in units per minute in the XYZ cartesian system, and all
-///    other axes (ABCUVW) move so as to start and stop in coordinated fashion."
-/// So we have to correct the feed rate we get from the machine to get the right actual feed at the tip of the tool. Doing
-///  that in a way that machines agree on seems hard, so instead we use G93 mode and let the machine figure out the
-///  XYZ and ABC feed rates.
-fn calc_machine_feedrate(opt: &Opt, a_start: f64, a_end: f64, target_feed: f64) -> f64 {
-    let delta_z = opt.max_stepdown;
-    let delta_a_along_surface = (a_end - a_start).abs() / 360.0 * (opt.dia * PI);
-    let path_length = (delta_z * delta_z + delta_a_along_surface * delta_a_along_surface).sqrt();
-    let completion_minutes = path_length / target_feed;
-    1.0 / completion_minutes
-}
+    /// Vary the spacing between flutes by up to this fraction of the even spacing, pseudo-randomly, to break up
+    /// chatter. 0 spaces them evenly.
+    #[structopt(long, default_value = "0")]
+    spacing_variation: f64,
 
-fn pass_at_depth(
-    opt: &Opt,
-    file: &mut dyn Write,
-    x_pos: f64,
-    max_depth: f64,
-    a_start: f64,
-    a_end: f64,
-) -> Result<()> {
-    // Clearance (in mm) away from the stock where we move at feed rate
-    let clearance = 4.0;
-
-    // All ops happen along the "top" of the stock, minus some Z depth, moving in A and -Z simultaneously
-    let y_pos = 0.0;
-
-    let z_start = opt.dia / 2.0;
-    let z_end = z_start - max_depth;
-    // Cutting feed rate, in inverse minutes
-    let cutting_feed = calc_machine_feedrate(opt, a_start, a_end, opt.feed);
-    let in_out_feed = opt.feed;
-
-    gcode_comment(file, &format!("Pass at depth {}", max_depth))?;
-    // Rapid to some distance above the start of the work
-    g0(file, xyza(x_pos, y_pos, z_start + clearance, a_start))?;
-    // Feed in to the starting Z at feed rate (this shouldn't plunge the tool, but we're just being cautious by not making this rapid)
-    g1(file, zf(z_start, in_out_feed))?;
-    // Now simultaneously feed in the Z and A axes
-    inv_feed_g93(file)?;
-    g1(file, zaf(z_end, a_end, cutting_feed))?;
-    standard_feed_g94(file)?;
-    // Then feed out back to the Z clearance point
-    g1(file, zf(z_start, in_out_feed))?;
-
-    Ok(())
-}
+    /// Seed for the pseudo-random flute spacing. The same seed always gives the same spacing.
+    #[structopt(long, default_value = "1")]
+    seed: u64,
 
-fn cut_flute(opt: &Opt, file: &mut dyn Write, angle: f64) -> Result<()> {
-    // Start x so that the tool is barely touching the work
-    let mut x = opt.tool_dia / 2.0;
-    // Take passes until we've consumed the whole X distance
-    while x > -opt.len {
-        let angle_on_spiral =
-            angle + 360.0 * x * opt.spiral_angle.to_radians().tan() / (PI * opt.dia);
-
-        let angle_end = angle_on_spiral + 360.0 / opt.flutes as f64
-            - 360.0 * (opt.tool_dia / 2.0) / (PI * opt.dia);
-        let mut depth = 0.0;
-        // Take passes until we've consumed the whole target depth
-        while depth < opt.depth {
-            depth = (depth + opt.max_stepdown).clamp(0.0, opt.depth);
-            pass_at_depth(opt, file, x, depth, angle_on_spiral, angle_end)?;
-        }
-        // Move up the x axis by our stepover value
-        x -= opt.tool_dia * opt.max_stepover;
-    }
+    /// Climb mill: cut so the teeth meet the work along with the cutter's travel, for a better finish on a rigid
+    /// machine. Takes the spindle to run clockwise (M3) unless --ccw is given, and turns the cut round if it is.
+    #[structopt(long, conflicts_with = "conventional")]
+    climb: bool,
 
-    // Go home between teeth
-    write!(file, "G30\n\n")?;
+    /// Conventional mill: cut so the teeth meet the work against the cutter's travel, which is kinder to a machine
+    /// with backlash. Takes the spindle to run clockwise (M3) unless --ccw is given, and turns the cut round if it is.
+    #[structopt(long)]
+    conventional: bool,
 
-    Ok(())
+    /// Resume a job that stopped part way through, starting at this flute, as numbered in the program's "Flute N of M"
+    /// comments. Leave the work where it is, and don't re-zero any axis (A included).
+    #[structopt(long)]
+    start_flute: Option<u32>,
+
+    #[structopt(flatten)]
+    common: CommonOpts,
 }
 
-fn cut_flutes(opt: &Opt, file: &mut dyn Write) -> Result<()> {
-    let flute_angle = 360.0 / opt.flutes as f64;
+/// Stepping out from the right face, with A turning positive (right-handed about +X) and the spindle clockwise, the
+/// side of the tool meets the uncut stock conventionally
+fn from_far_end(opt: &Opt) -> bool {
+    Milling::from_flags(opt.climb, opt.conventional)
+        .is_some_and(|milling| milling.reverses(Milling::Conventional, opt.common.spindle_dir()))
+}
 
-    for i in 0..opt.flutes {
-        gcode_comment(file, &format!("Flute {} of {}", i + 1, opt.flutes))?;
-        cut_flute(opt, file, i as f64 * flute_angle)?;
+fn flute_cut(opt: &Opt) -> FluteCut {
+    FluteCut {
+        flutes: opt.flutes,
+        starts: opt.starts,
+        depth: opt.depth,
+        depth_end: opt.depth_end.unwrap_or(opt.depth),
+        len: opt.len,
+        dia: opt.dia,
+        feed: opt.feed,
+        tool_dia: opt.tool_dia,
+        tool_type: opt.tool_type,
+        max_stepdown: opt.max_stepdown,
+        max_stepover: opt.max_stepover,
+        spiral_angle: opt.spiral_angle,
+        relief_angle: opt.relief_angle,
+        spacing_variation: opt.spacing_variation,
+        seed: opt.seed,
+        from_far_end: from_far_end(opt),
+        first_flute: opt.start_flute.map_or(0, |flute| flute - 1),
+        home: opt.common.home(),
+        units: opt.common.units,
+        dialect: opt.common.dialect,
     }
-
-    Ok(())
 }
 
 fn help_text(opt: &Opt) {
-    println!(
+    eprintln!(
         "Before cut:
-        - Create stock with OD {}mm
+        - Create stock with OD {}{}
         - Set home to center of right face of stock",
-        opt.dia
+        opt.dia,
+        opt.common.units.abbrev()
     )
 }
 
 fn main() -> Result<()> {
-    let opt = Opt::from_args();
+    let mut opt: Opt = cli::from_args();
+    if !(0.0..1.0).contains(&opt.spacing_variation) {
+        clap::Error::with_description(
+            "--spacing-variation must be at least 0, and less than 1",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt.starts == 0 || !opt.flutes.is_multiple_of(opt.starts) {
+        clap::Error::with_description(
+            "--flutes must be a multiple of --starts",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt
+        .start_flute
+        .is_some_and(|flute| flute == 0 || flute > opt.flutes)
+    {
+        clap::Error::with_description(
+            &format!("--start-flute must be from 1 to {}", opt.flutes),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
     help_text(&opt);
-    let mut file = BufWriter::new(
-        OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&opt.output)?,
-    );
-
-    preamble(
-        &opt.name,
+    let tool = opt.common.tool(
         opt.tool,
-        &format!("T{} D={} ball mill", opt.tool, opt.tool_dia),
-        opt.rpm,
-        opt.coolant,
+        format!(
+            "T{} D={}{} {}",
+            opt.tool,
+            opt.tool_dia,
+            opt.common.units.abbrev(),
+            opt.tool_type.name()
+        ),
+        opt.common.rpm(opt.rpm, Some(opt.tool_dia)),
+    );
+    opt.feed = opt.common.feed(opt.feed, tool.rpm, Some(opt.tool_dia), 2);
+    let mut file = opt.common.open_output(&tool)?;
+    let mut notes = vec![];
+    if opt.spacing_variation > 0.0 {
+        notes.push(format!(
+            "Unequal spacing, varied by up to {}% with seed {}",
+            opt.spacing_variation * 100.0,
+            opt.seed
+        ));
+    }
+    if let Some(milling) = Milling::from_flags(opt.climb, opt.conventional) {
+        notes.push(format!(
+            "{} milling, stepping along X from the {}",
+            milling.name(),
+            if from_far_end(&opt) {
+                "far end"
+            } else {
+                "right face"
+            }
+        ));
+    }
+    if let Some(flute) = opt.start_flute {
+        notes.push(format!(
+            "Resuming at flute {} of {}: leave the work in place, and don't re-zero any axis",
+            flute, opt.flutes
+        ));
+    }
+    job_header(
         &mut file,
+        &JobInfo {
+            stock: format!(
+                "OD {}{u}, at least {}{u} long",
+                opt.dia,
+                opt.len,
+                u = opt.common.units.abbrev()
+            ),
+            datum: "center of right face of stock".to_string(),
+            tools: vec![(&tool, opt.feed)],
+            notes,
+            units: opt.common.units,
+            dialect: opt.common.dialect,
+        },
     )?;
-    cut_flutes(&opt, &mut file)?;
-    trailer(&mut file)?;
+    flutes::cut_flutes(&mut file, &flute_cut(&opt))?;
+    trailer(&mut file, opt.common.home(), opt.common.dialect)?;
+
+    opt.common.finish(
+        file,
+        Some(Stock::Rect {
+            min: (-opt.len, -opt.dia / 2.0),
+            max: (0.0, opt.dia / 2.0),
+        }),
+    )
+}
 
-    file.flush()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flutes(args: &[&str]) -> String {
+        let common = [
+            "flute_gen",
+            "--flutes=8",
+            "--depth=2",
+            "--dia=20",
+            "--output=flutes.nc",
+        ];
+        let opt = Opt::from_iter(common.iter().chain(args));
+        let mut file = vec![];
+        flutes::cut_flutes(&mut file, &flute_cut(&opt)).unwrap();
+        String::from_utf8(file).unwrap()
+    }
+
+    #[test]
+    fn start_flute_leaves_out_the_flutes_before_it() {
+        let full = flutes(&[]);
+        let resumed = flutes(&["--start-flute=5"]);
+        // Flutes 1 to 4 get no passes, and the rest are cut just as they are in the whole job
+        let from = full.find("(Flute 5 of 8)").unwrap();
+        assert!(from > 0);
+        assert_eq!(resumed, full[from..]);
+        assert_eq!(resumed.matches("(Flute ").count(), 4);
+    }
 }
@@ -1,22 +1,40 @@
-///! G-Code generator for cutting simple spur gears on a 4th axis, using an involute gear cutter
-use gcode::{gcode_comment, preamble, trailer};
-use std::fs::{File, OpenOptions};
+//! G-Code generator for cutting simple spur gears on a 4th axis, using an involute gear cutter, or a ball end mill
+//! for cycloidal teeth
+use gcode::cli::{self, CommonOpts};
+use gcode::gears::{self, DepthStandard};
+use gcode::profile::{self, BallCut};
+use gcode::svg::Stock;
+use gcode::{
+    a, call_subprogram, g0, g1, g2r, g3r, gcode_comment, inv_feed_g93, job_header, operator_stop,
+    standard_feed_g94, start_tool, subprogram_end, subprogram_start, tool_change, trailer, x, xa,
+    xaf, xf, xy, xya, xyrf, y, yf, z, zf, Dialect, JobInfo, Milling, Tool,
+};
+use std::f64::consts::PI;
 use std::io::{Result, Write};
-use std::path::PathBuf;
-use structopt::StructOpt;
+use std::str::FromStr;
+use structopt::{clap, StructOpt};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "gear_gen", about = "A simple spur gear generator")]
+#[structopt(group = clap::ArgGroup::with_name("size"))]
 struct Opt {
-    /// Gear module, must match cutter module
-    #[structopt(short, long, default_value = "1")]
+    /// Gear module, in mm whatever the --units, must match cutter module [default: 1]
+    #[structopt(short = "m", long = "module", group = "size")]
+    module_arg: Option<f64>,
+
+    /// Diametral pitch, for imperial gears, as an alternative to --module
+    #[structopt(long, group = "size")]
+    dp: Option<f64>,
+
+    /// Gear module in mm, from either --module or --dp
+    #[structopt(skip)]
     module: f64,
 
     /// Number of gear teeth
     #[structopt(short, long)]
     teeth: u32,
 
-    /// Diameter of cutter, in mm
+    /// Diameter of cutter
     #[structopt(long, default_value = "50")]
     cutter_dia: f64,
 
@@ -24,135 +42,1536 @@ struct Opt {
     #[structopt(long, default_value = "650")]
     rpm: f64,
 
-    /// Feed rate, in mm/min
+    /// Feed rate, in units/min
     #[structopt(long, default_value = "60")]
     feed: f64,
 
-    /// Name for the job
-    #[structopt(short, long)]
-    name: Option<String>,
-
     /// Tool number for the cut
     #[structopt(long, default_value = "1")]
     tool: u32,
 
+    /// Brown & Sharpe number of the loaded cutter, to check it against the number of teeth
+    #[structopt(long)]
+    cutter_number: Option<u32>,
+
     /// Width of the gear to cut
     #[structopt(short, long)]
     width: f64,
 
-    /// Max depth to cut, in mm
+    /// Cut the gear in two setups, for one wider than the cutter can reach from the free end of the blank: this much of
+    /// the width first, then the rest after stopping for the blank to be turned end for end. Only for straight teeth.
+    #[structopt(long)]
+    split: Option<f64>,
+
+    /// Max depth to cut
     #[structopt(long, default_value = "0.5")]
     max_depth: f64,
 
-    /// Output file for the resulting G code
-    #[structopt(short, long, parse(from_os_str))]
-    output: PathBuf,
+    /// Emit the passes for a tooth once, as an M98/M99 subprogram, rather than repeating them for every tooth.
+    /// Not supported for helical gears.
+    #[structopt(long)]
+    subprograms: bool,
+
+    /// Helix angle, in degrees, for cutting helical gears. 0 cuts straight spur teeth.
+    #[structopt(long, default_value = "0")]
+    helix_angle: f64,
+
+    /// Alternate the direction of the cut from tooth to tooth, to even out deflection and the load on the rotary.
+    /// Every other tooth is cut towards +X, which makes it a climb cut, and the start of those cuts needs the stock to
+    /// stick out of the chuck further.
+    #[structopt(long)]
+    alternate_direction: bool,
+
+    /// Climb mill: cut so the teeth meet the work along with the cutter's travel, for a better finish on a rigid
+    /// machine. Takes the spindle to run clockwise (M3) unless --ccw is given, and turns the cut round if it is.
+    #[structopt(long, conflicts_with = "conventional")]
+    climb: bool,
+
+    /// Conventional mill: cut so the teeth meet the work against the cutter's travel, which is kinder to a machine
+    /// with backlash. Takes the spindle to run clockwise (M3) unless --ccw is given, and turns the cut round if it is.
+    #[structopt(long)]
+    conventional: bool,
+
+    /// Cut a herringbone (double helical) gear: the second half of the width gets the opposite hand of helix to the
+    /// first. Needs a --helix-angle, and a relief groove turned in the middle of the stock.
+    #[structopt(long)]
+    herringbone: bool,
+
+    /// Profile shift coefficient. Positive values move the cutter out (avoiding undercut on small pinions), negative
+    /// values move it in. Clamped to -0.5 to 1.0.
+    #[structopt(long, default_value = "0", allow_hyphen_values = true)]
+    profile_shift: f64,
+
+    /// Backlash allowance, measured along the pitch circle. The teeth are thinned by cutting deeper, so this gear
+    /// takes up all of it.
+    #[structopt(long, default_value = "0")]
+    backlash: f64,
+
+    /// Tooth proportions: full-depth, stub, or a custom total depth as a multiple of the module
+    #[structopt(long, default_value = "full-depth")]
+    depth_standard: DepthStandard,
+
+    /// Radius of the arc the cutter eases out of the work on at the end of each pass [default: 2mm].
+    /// Limited to the clearance, and to how far the cutter reaches ahead of its center. 0 feeds straight out, as
+    /// a helical gear always does.
+    #[structopt(long)]
+    feed_out_radius: Option<f64>,
+
+    /// Extra passes to repeat at the final depth, to clean up after the cutter deflects
+    #[structopt(long, default_value = "0")]
+    spring_passes: u32,
+
+    /// Tool number for a finishing cutter. When set, every tooth is roughed out with --tool first, leaving the finish
+    /// allowance, then finished to full depth with this tool. It must be the same diameter as the roughing cutter.
+    #[structopt(long)]
+    finish_tool: Option<u32>,
+
+    /// Finishing cutter RPM [default: the same as the cutter]
+    #[structopt(long)]
+    finish_rpm: Option<f64>,
+
+    /// Depth left on each tooth for the finishing cutter to take, in a single pass [default: 0.2mm]
+    #[structopt(long)]
+    finish_allowance: Option<f64>,
+
+    /// Tool number for a 90 degree chamfer tool. When set, the top edges of every tooth are broken with it, in a
+    /// single light pass along each edge after the teeth are cut.
+    #[structopt(long)]
+    chamfer_tool: Option<u32>,
+
+    /// Chamfer tool RPM [default: the same as the cutter]
+    #[structopt(long)]
+    chamfer_rpm: Option<f64>,
+
+    /// How far the tip of the chamfer tool goes below the top of the teeth [default: 0.2mm]
+    #[structopt(long)]
+    chamfer_depth: Option<f64>,
+
+    /// Tooth form: involute, cut with a form cutter, or cycloidal, as clocks use, cut by following the flank with a
+    /// ball end mill
+    #[structopt(long, default_value = "involute")]
+    tooth_form: ToothForm,
+
+    /// Diameter of the circle that rolls round the pitch circle to generate cycloidal flanks [default: half the pitch
+    /// diameter, which makes the flanks below the pitch circle straight and radial]
+    #[structopt(long)]
+    generating_dia: Option<f64>,
 
+    /// Diameter of the ball end mill for cycloidal teeth. It has to fit in the bottom of the tooth spaces.
     #[structopt(long)]
-    coolant: bool,
+    ball_dia: Option<f64>,
+
+    /// Distance between finishing passes, along a cycloidal flank [default: 0.1mm]
+    #[structopt(long)]
+    finish_step: Option<f64>,
+
+    /// Pressure angle of the cutter, in degrees
+    #[structopt(long, default_value = "20")]
+    pressure_angle: f64,
+
+    /// Diameter of the pins to give the expected measurement over pins for [default: 1.728 * module]
+    #[structopt(long)]
+    pin_dia: Option<f64>,
+
+    /// Number of teeth to give the expected base tangent span over [default: the number that puts the caliper jaws
+    /// near the pitch circle]
+    #[structopt(long)]
+    span_teeth: Option<u32>,
+
+    /// Resume a job that stopped part way through, starting at this tooth, as numbered in the program's "Tooth N of M"
+    /// comments. Leave the work where it is, and don't re-zero any axis (A included). With a --finish-tool or
+    /// --chamfer-tool only the roughing resumes part way, so every tooth is still finished and chamfered.
+    #[structopt(long)]
+    start_tooth: Option<u32>,
+
+    #[structopt(flatten)]
+    common: CommonOpts,
+}
+
+/// Form of the teeth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ToothForm {
+    /// Involute flanks, plunged with a form cutter
+    Involute,
+    /// Epicycloid flanks above the pitch circle, and hypocycloid ones below it, traced with a ball end mill
+    Cycloidal,
 }
 
-fn pass_at_depth(opt: &Opt, file: &mut File, depth: f64) -> Result<()> {
-    // Clearance (in mm) away from the stock where we move at feed rate
-    let clearance = 4.0;
+impl FromStr for ToothForm {
+    type Err = String;
 
-    let clearance_theta = (1.0 - 2.0 * clearance / opt.cutter_dia).acos();
-    let x_clearance = (opt.cutter_dia / 2.0) * clearance_theta.tan();
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "involute" => Ok(ToothForm::Involute),
+            "cycloidal" => Ok(ToothForm::Cycloidal),
+            _ => Err(format!(
+                "Unknown tooth form {}, expected involute or cycloidal",
+                s
+            )),
+        }
+    }
+}
+
+/// O-word for the per-tooth subprogram
+const TOOTH_SUBPROGRAM: u32 = 1000;
+
+/// O-word for the per-tooth finishing subprogram, when roughing and finishing with separate tools
+const FINISH_SUBPROGRAM: u32 = 1001;
+
+/// Which part of the tooth depth a run over all the teeth cuts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// The whole depth, with a single tool
+    Full,
+    /// Down to the finish allowance, with the roughing tool
+    Rough,
+    /// The finish allowance, with the finishing tool
+    Finish,
+}
+
+impl Stage {
+    fn subprogram(self) -> u32 {
+        match self {
+            Stage::Full | Stage::Rough => TOOTH_SUBPROGRAM,
+            Stage::Finish => FINISH_SUBPROGRAM,
+        }
+    }
+}
+
+/// Stock outside diameter, in the job's units
+fn stock_dia(opt: &Opt) -> f64 {
+    opt.common.units.from_mm(
+        gears::helical_outside_diameter(opt.module, opt.teeth, opt.helix_angle)
+            + 2.0 * gears::profile_shift_offset(opt.module, opt.profile_shift),
+    )
+}
+
+/// Total depth of each tooth space, in the job's units
+fn total_depth(opt: &Opt) -> f64 {
+    // Checked in main, so this can't fail
+    opt.common.units.from_mm(
+        gears::standard_total_depth(opt.module, opt.depth_standard, opt.pressure_angle).unwrap(),
+    ) + gears::backlash_extra_depth(opt.backlash, opt.pressure_angle)
+}
 
-    let y_pos = (opt.teeth as f64 + 2.0) * opt.module / 2.0 // Stock radius
+/// Depth left for the finishing tool, in the job's units
+fn finish_allowance(opt: &Opt) -> f64 {
+    opt.finish_allowance
+        .unwrap_or_else(|| opt.common.units.from_mm(0.2))
+}
+
+/// How far (in degrees of A) each top edge of a tooth is from the middle of the tooth, from the width of its top land.
+/// This works in the plane of the gear's face, which on a helical gear is across the teeth at the helix angle.
+fn top_edge_angle(opt: &Opt) -> f64 {
+    let helix = opt.helix_angle.to_radians();
+    let u = opt.common.units;
+    let thickness = gears::tooth_thickness(opt.module, opt.pressure_angle, opt.profile_shift)
+        - u.to_mm(opt.backlash);
+    let pressure_angle = (opt.pressure_angle.to_radians().tan() / helix.cos())
+        .atan()
+        .to_degrees();
+    gears::tooth_angle_at(
+        opt.module / helix.cos(),
+        opt.teeth,
+        pressure_angle,
+        thickness / helix.cos(),
+        u.to_mm(stock_dia(opt)),
+    ) / 2.0
+}
+
+/// Diameter of the circle generating cycloidal flanks, in mm
+fn generating_dia(opt: &Opt) -> f64 {
+    opt.generating_dia.map_or_else(
+        || gears::pitch_diameter(opt.module, opt.teeth) / 2.0,
+        |dia| opt.common.units.to_mm(dia),
+    )
+}
+
+/// Path of the center of a `ball_dia` ball end mill along the +Y flank of a cycloidal tooth space, in the job's units
+fn ball_path(opt: &Opt, ball_dia: f64) -> std::result::Result<Vec<(f64, f64)>, String> {
+    let u = opt.common.units;
+    let step = opt.finish_step.unwrap_or_else(|| u.from_mm(0.1));
+    // The fillet in the bottom corners is the ball's own radius, so it takes the ball right into them
+    let flank = gears::cycloidal_gap_flank(
+        opt.module,
+        opt.teeth,
+        generating_dia(opt),
+        u.to_mm(total_depth(opt)) - opt.module,
+        u.to_mm(ball_dia) / 2.0,
+        u.to_mm(step),
+    )?;
+    let mut path = profile::ball_path(&flank, ball_dia, u);
+    // All the way round the fillet, the ball's center stays in the one place
+    path.dedup_by(|a, b| (a.0 - b.0).hypot(a.1 - b.1) < 1e-6);
+    Ok(path)
+}
+
+/// Describe the Brown & Sharpe cutter for this gear, like "#5, for 21-25 teeth".
+/// This goes in a G-code comment, so it can't have parentheses of its own.
+fn cutter_description(opt: &Opt) -> Option<String> {
+    let teeth = gears::equivalent_teeth(opt.teeth, opt.helix_angle);
+    gears::cutter_number(teeth).map(|(number, range)| {
+        if *range.end() == u32::MAX {
+            format!("#{}, for {} teeth to a rack", number, range.start())
+        } else {
+            format!("#{}, for {}-{} teeth", number, range.start(), range.end())
+        }
+    })
+}
+
+/// Warning about undercut flanks, if there are too few teeth for the profile shift
+fn undercut_warning(opt: &Opt) -> Option<String> {
+    let addendum = opt.depth_standard.addendum_coefficient();
+    let min_shift = gears::min_profile_shift(opt.teeth, opt.pressure_angle, addendum);
+    if opt.profile_shift >= min_shift {
+        return None;
+    }
+    Some(format!(
+        "WARNING: {} teeth at {} degrees is fewer than the {:.1} needed to avoid undercut. Use --profile-shift {:.2} or more to avoid it.",
+        opt.teeth,
+        opt.pressure_angle,
+        gears::min_teeth_without_undercut(opt.pressure_angle, addendum),
+        min_shift
+    ))
+}
+
+/// The expected measurements over pins and across a span of teeth, for inspecting a spur gear
+fn inspection_notes(opt: &Opt) -> Vec<String> {
+    let u = opt.common.units;
+    // Backlash is taken up by thinning this gear's teeth
+    let thickness = gears::tooth_thickness(opt.module, opt.pressure_angle, opt.profile_shift)
+        - u.to_mm(opt.backlash);
+
+    let pin_dia = opt.pin_dia.unwrap_or_else(|| u.from_mm(1.728 * opt.module));
+    let over_pins = gears::measurement_over_pins(
+        opt.module,
+        opt.teeth,
+        opt.pressure_angle,
+        thickness,
+        u.to_mm(pin_dia),
+    );
+    let pins = format!(
+        "Measurement over {}{u} pins {:.4}{u}",
+        pin_dia,
+        u.from_mm(over_pins),
+        u = u.abbrev()
+    );
+
+    let span = opt
+        .span_teeth
+        .unwrap_or_else(|| gears::span_teeth(opt.teeth, opt.pressure_angle));
+    let span_len =
+        gears::base_tangent_span(opt.module, opt.teeth, opt.pressure_angle, thickness, span);
+    let span = format!(
+        "Base tangent span over {} teeth {:.4}{}",
+        span,
+        u.from_mm(span_len),
+        u.abbrev()
+    );
+    vec![pins, span]
+}
+
+/// How far the A axis turns (in degrees) for each unit of X travel, for the teeth to follow the helix
+fn helix_a_per_x(opt: &Opt) -> f64 {
+    let pitch_dia = gears::helical_pitch_diameter(opt.module, opt.teeth, opt.helix_angle);
+    360.0
+        / opt
+            .common
+            .units
+            .from_mm(gears::helix_lead(pitch_dia, opt.helix_angle))
+}
+
+/// Where the A axis is, for the cutter to follow the helix when it's at `x`, where `angle` is the A position at X0.
+/// On a herringbone gear, the helix changes hand in the middle of the width.
+fn helix_a(opt: &Opt, angle: f64, x: f64) -> f64 {
+    if opt.herringbone && x < -opt.width / 2.0 {
+        // Mirror the first half's helix about the middle
+        angle - (x + opt.width) * helix_a_per_x(opt)
+    } else {
+        angle + x * helix_a_per_x(opt)
+    }
+}
+
+/// Width of the relief groove a herringbone gear needs in the middle of the stock, where the cutter can't reach
+/// without crossing into the other half's helix
+fn relief_groove_width(opt: &Opt) -> f64 {
+    2.0 * gears::cutter_half_chord(opt.cutter_dia, total_depth(opt))
+}
+
+/// Feed the cutter along X to `x_end`, rotating the A axis to follow the helix if this is a helical gear.
+/// `angle` is where the A axis is when the cutter is at X0.
+fn cut_along_x(
+    opt: &Opt,
+    file: &mut dyn Write,
+    x_start: f64,
+    x_end: f64,
+    angle: f64,
+) -> Result<()> {
+    let u = opt.common.units;
+    if opt.helix_angle == 0.0 {
+        return g1(file, xf(x_end, opt.feed).units(u));
+    }
+    let a_end = helix_a(opt, angle, x_end);
+    // X and A move together, so use inverse time feed to get the right feed rate along the helix, measured at the
+    // pitch circle
+    let pitch_dia = u.from_mm(gears::helical_pitch_diameter(
+        opt.module,
+        opt.teeth,
+        opt.helix_angle,
+    ));
+    let x_len = (x_end - x_start).abs();
+    let surface_len = x_len * helix_a_per_x(opt) / 360.0 * PI * pitch_dia;
+    let path_len = x_len.hypot(surface_len);
+    inv_feed_g93(file)?;
+    g1(file, xaf(x_end, a_end, opt.feed / path_len).units(u))?;
+    standard_feed_g94(file)
+}
+
+/// A pass along the tooth at `depth`. Normally this cuts right to left, towards -X; with `reverse` it's mirrored about
+/// the middle of the width, to cut left to right.
+fn pass_at_depth(
+    opt: &Opt,
+    file: &mut dyn Write,
+    depth: f64,
+    angle: f64,
+    reverse: bool,
+) -> Result<()> {
+    if opt.herringbone {
+        return herringbone_pass(opt, file, depth, angle);
+    }
+    let u = opt.common.units;
+    // Clearance (4mm) away from the stock where we move at feed rate
+    let clearance = u.from_mm(4.0);
+    let mirror = |x: f64| if reverse { -opt.width - x } else { x };
+
+    let x_clearance = mirror(gears::cutter_start_x(opt.cutter_dia, depth, clearance));
+
+    let y_pos = stock_dia(opt) / 2.0 // Stock radius
         + opt.cutter_dia / 2.0 // Plus cutter radius
         - depth; // Minus depth of cut
-    gcode_comment(file, &format!("Pass at depth {}", depth))?;
-    // Rapid to our starting point, to the right of the stock
-    writeln!(file, "G0 X{:.4} Y{}", x_clearance, y_pos)?;
-    writeln!(file, "G0 Z0.")?;
+    gcode_comment(
+        file,
+        &format!("Pass at depth {}{}", depth, u.abbrev()),
+        opt.common.dialect,
+    )?;
+    // Rapid to our starting point, off the end of the stock, turning to meet the helix if there is one
+    let a_start = helix_a(opt, angle, x_clearance);
+    if opt.helix_angle == 0.0 {
+        g0(file, xy(x_clearance, y_pos).units(u))?;
+    } else {
+        g0(file, xya(x_clearance, y_pos, a_start).units(u))?;
+    }
+    g0(file, z(0.0).units(u))?;
+
+    // The feed-out arc starts before the end of the stock, but as it's smaller than the reach of the cutter ahead of
+    // its center, the cutting edge still gets all the way to the end at full depth. A helical gear feeds straight out
+    // instead, as the arc is still in the tooth space, where A would have to keep following the helix.
+    let feed_out_radius = if opt.helix_angle == 0.0 {
+        opt.feed_out_radius
+            .unwrap_or_else(|| u.from_mm(2.0))
+            .min(clearance)
+            .min(gears::cutter_half_chord(opt.cutter_dia, depth))
+    } else {
+        0.0
+    };
+    let x_end = mirror(-opt.width + feed_out_radius);
 
     // Feed into the stock, cutting as we go
-    writeln!(file, "G1 X{} F{}", -opt.width, opt.feed)?;
+    cut_along_x(opt, file, x_clearance, x_end, angle)?;
+
+    // Ease out of the stock on a tangent arc, so there's no sudden change in direction to leave a witness mark from
+    // backlash in the rotary. Mirroring the cut turns the arc the other way.
+    if feed_out_radius > 0.0 {
+        let arc = xyrf(
+            mirror(-opt.width),
+            y_pos + feed_out_radius,
+            feed_out_radius,
+            opt.feed,
+        )
+        .units(u);
+        if reverse {
+            g3r(file, (x_end, y_pos), arc)?;
+        } else {
+            g2r(file, (x_end, y_pos), arc)?;
+        }
+    }
+    // Then feed the rest of the way out of the stock, moving in Y
+    g1(file, yf(y_pos + clearance, opt.feed).units(u))?;
+    // Then rapid a little bit straight out before we do the cross move
+    g0(file, y(y_pos + clearance + u.from_mm(10.0)).units(u))?;
+
+    // Go back to where we started, in two moves, first X then Y to make sure we have enough clearance
+    if opt.helix_angle == 0.0 {
+        g0(file, x(x_clearance).units(u))?;
+    } else {
+        g0(file, xa(x_clearance, a_start).units(u))?;
+    }
+    g0(file, y(y_pos).units(u))?;
+
+    Ok(())
+}
+
+/// A pass for a herringbone gear. Each half of the width is cut with its own hand of helix, stopping where the cutter's
+/// edge reaches the middle, so it never cuts across into the other half.
+fn herringbone_pass(opt: &Opt, file: &mut dyn Write, depth: f64, angle: f64) -> Result<()> {
+    let u = opt.common.units;
+    // Clearance (4mm) away from the stock where we move at feed rate
+    let clearance = u.from_mm(4.0);
 
-    // Feed out of the stock, moving in Y
-    // TODO: This feed-out should probably be radiused, to avoid backlash issues
-    writeln!(file, "G1 Y{} F{}", y_pos + clearance, opt.feed)?;
+    let x_clearance = gears::cutter_start_x(opt.cutter_dia, depth, clearance);
+    let reach = gears::cutter_half_chord(opt.cutter_dia, depth);
+    let middle = -opt.width / 2.0;
+
+    let y_pos = stock_dia(opt) / 2.0 // Stock radius
+        + opt.cutter_dia / 2.0 // Plus cutter radius
+        - depth; // Minus depth of cut
+
+    // Far enough out for the cutter to clear the stock anywhere along it
+    let y_out = y_pos + depth + clearance;
+    gcode_comment(
+        file,
+        &format!("Pass at depth {}{}", depth, u.abbrev()),
+        opt.common.dialect,
+    )?;
+
+    // First half: in from the end of the stock, like any other helical pass, stopping short of the middle
+    let a_start = helix_a(opt, angle, x_clearance);
+    g0(file, xya(x_clearance, y_pos, a_start).units(u))?;
+    g0(file, z(0.0).units(u))?;
+    cut_along_x(opt, file, x_clearance, middle + reach, angle)?;
+    // Then straight out of the tooth space
+    g1(file, yf(y_out, opt.feed).units(u))?;
+
+    // Second half: feed straight in just past the middle, then on out to the end of the stock with the helix mirrored
+    let x_start = middle - reach;
+    g0(file, xa(x_start, helix_a(opt, angle, x_start)).units(u))?;
+    g1(file, yf(y_pos, opt.feed).units(u))?;
+    cut_along_x(opt, file, x_start, -opt.width, angle)?;
+    g1(file, yf(y_out, opt.feed).units(u))?;
     // Then rapid a little bit straight out before we do the cross move
-    writeln!(file, "G0 Y{}", y_pos + clearance + 10.0)?;
+    g0(file, y(y_out + u.from_mm(10.0)).units(u))?;
 
     // Go back to where we started, in two moves, first X then Y to make sure we have enough clearance
-    writeln!(file, "G0 X{:.4}", x_clearance)?;
-    writeln!(file, "G0 Y{}", y_pos)?;
+    g0(file, xa(x_clearance, a_start).units(u))?;
+    g0(file, y(y_pos).units(u))?;
 
     Ok(())
 }
 
-fn cut_tooth(opt: &Opt, file: &mut File, angle: f64) -> Result<()> {
+/// Whether to cut every tooth towards +X. Towards -X, with the spindle clockwise, the cutter meets the stock
+/// conventionally.
+fn reverse_all(opt: &Opt) -> bool {
+    Milling::from_flags(opt.climb, opt.conventional)
+        .is_some_and(|milling| milling.reverses(Milling::Conventional, opt.common.spindle_dir()))
+}
+
+fn cut_tooth(
+    opt: &Opt,
+    file: &mut dyn Write,
+    angle: f64,
+    stage: Stage,
+    reverse: bool,
+) -> Result<()> {
+    if opt.alternate_direction || reverse_all(opt) {
+        // This tooth may start from the other end of the stock to the last one (or to where the cutter was left), so
+        // go out and around it rather than through it
+        let u = opt.common.units;
+        let x_start = gears::cutter_start_x(opt.cutter_dia, total_depth(opt), u.from_mm(4.0));
+        g0(
+            file,
+            y(stock_dia(opt) / 2.0 + opt.cutter_dia / 2.0 + u.from_mm(10.0)).units(u),
+        )?;
+        g0(
+            file,
+            x(if reverse {
+                -opt.width - x_start
+            } else {
+                x_start
+            })
+            .units(u),
+        )?;
+    }
+
     // First, turn the rotary axis to the right angle, rapid
-    writeln!(file, "G0 A{:.4}", angle)?;
+    g0(file, a(angle).units(opt.common.units))?;
+
+    if opt.subprograms {
+        call_subprogram(file, stage.subprogram())
+    } else {
+        tooth_passes(opt, file, angle, stage, reverse)
+    }
+}
 
-    // Total depth varies from source to source.
-    // Here, I'm using the formula from the Machinery's Handbook, 31st Edition, "Module System Gear Design"
-    let total_depth = 2.157 * opt.module;
+/// All the passes to cut a single tooth for `stage`, with the A axis already in position.
+/// `angle` is the A position of the tooth at X0, and `reverse` cuts it left to right.
+fn tooth_passes(
+    opt: &Opt,
+    file: &mut dyn Write,
+    angle: f64,
+    stage: Stage,
+    reverse: bool,
+) -> Result<()> {
+    let total_depth = total_depth(opt);
+
+    if stage == Stage::Finish {
+        // The roughing tool has already left us just the allowance
+        pass_at_depth(opt, file, total_depth, angle, reverse)?;
+        return spring_passes(opt, file, total_depth, angle, reverse);
+    }
 
+    let final_depth = match stage {
+        Stage::Rough => total_depth - finish_allowance(opt),
+        _ => total_depth,
+    };
     let mut depth = 0.0;
 
     // Take passes until we've consumed the whole depth.
-    while depth < total_depth {
-        let remaining = total_depth - depth;
+    while depth < final_depth {
+        let remaining = final_depth - depth;
         if remaining > 2.0 * opt.max_depth {
             // Make max_depth passes until we're within 2*max_depth of the final depth
             depth += opt.max_depth;
-            pass_at_depth(opt, file, depth)?;
+            pass_at_depth(opt, file, depth, angle, reverse)?;
         } else {
             // Then finish off with two equal passes of the remaining depth
             depth += remaining / 2.0;
-            pass_at_depth(opt, file, depth)?;
-            depth += remaining / 2.0;
-            pass_at_depth(opt, file, total_depth)?;
+            pass_at_depth(opt, file, depth, angle, reverse)?;
+            depth = final_depth;
+            pass_at_depth(opt, file, final_depth, angle, reverse)?;
         }
     }
 
+    if stage == Stage::Full {
+        spring_passes(opt, file, total_depth, angle, reverse)?;
+    }
+
     Ok(())
 }
 
-fn cut_teeth(opt: &Opt, file: &mut File) -> Result<()> {
-    let tooth_angle = 360.0 / opt.teeth as f64;
+/// Spring passes take off whatever the cutter left behind by deflecting on the way down
+fn spring_passes(
+    opt: &Opt,
+    file: &mut dyn Write,
+    total_depth: f64,
+    angle: f64,
+    reverse: bool,
+) -> Result<()> {
+    for _ in 0..opt.spring_passes {
+        pass_at_depth(opt, file, total_depth, angle, reverse)?;
+    }
 
-    for i in 0..opt.teeth {
-        gcode_comment(file, &format!("Tooth {} of {}", i + 1, opt.teeth))?;
-        cut_tooth(opt, file, i as f64 * tooth_angle)?;
+    Ok(())
+}
+
+/// Index of the first tooth to cut, when resuming with `--start-tooth`
+fn first_tooth(opt: &Opt) -> u32 {
+    opt.start_tooth.map_or(0, |tooth| tooth - 1)
+}
+
+fn cut_teeth(opt: &Opt, file: &mut dyn Write, stage: Stage) -> Result<()> {
+    let tooth_angle = gears::tooth_angle(opt.teeth);
+    // A resumed job picks up in its first run over the teeth, and the later runs go over them all
+    let first = match stage {
+        Stage::Finish => 0,
+        _ => first_tooth(opt),
+    };
+
+    for i in first..opt.teeth {
+        gcode_comment(
+            file,
+            &format!("Tooth {} of {}", i + 1, opt.teeth),
+            opt.common.dialect,
+        )?;
+        // Odd teeth are cut the other way, if we're alternating
+        let reverse = reverse_all(opt) || (opt.alternate_direction && i % 2 == 1);
+        cut_tooth(opt, file, i as f64 * tooth_angle, stage, reverse)?;
     }
 
-    // Go home at the end
-    write!(file, "G30\n\n")?;
+    Ok(())
+}
+
+/// Cut the tooth spaces of a cycloidal gear with a ball end mill, whose center follows `path` (from `ball_path`). Each
+/// space is turned up to the top (+Z) in turn, roughed out in layers, then finished down each flank.
+fn cut_cycloidal_teeth(
+    opt: &Opt,
+    file: &mut dyn Write,
+    ball_dia: f64,
+    path: &[(f64, f64)],
+) -> Result<()> {
+    let tooth_angle = gears::tooth_angle(opt.teeth);
+    let cut = BallCut {
+        tool_dia: ball_dia,
+        width: opt.width,
+        max_depth: opt.max_depth,
+        feed: opt.feed,
+        stock_dia: stock_dia(opt),
+        units: opt.common.units,
+        dialect: opt.common.dialect,
+    };
+
+    for i in first_tooth(opt)..opt.teeth {
+        gcode_comment(
+            file,
+            &format!("Tooth {} of {}", i + 1, opt.teeth),
+            opt.common.dialect,
+        )?;
+        g0(file, a(i as f64 * tooth_angle).units(opt.common.units))?;
+        profile::cut_gap(file, &cut, path)?;
+    }
+
+    Ok(())
+}
+
+fn tooth_subprogram(opt: &Opt, file: &mut dyn Write, stage: Stage) -> Result<()> {
+    writeln!(file)?;
+    subprogram_start(file, stage.subprogram())?;
+    gcode_comment(
+        file,
+        match stage {
+            Stage::Full => "Cut one tooth",
+            Stage::Rough => "Rough one tooth",
+            Stage::Finish => "Finish one tooth",
+        },
+        opt.common.dialect,
+    )?;
+    tooth_passes(opt, file, 0.0, stage, reverse_all(opt))?;
+    subprogram_end(file)
+}
+
+/// Feed the chamfer tool along one top edge of a tooth, from `x_start` to `x_end`. `angle` is where the A axis puts
+/// the edge at the top at X0.
+fn chamfer_edge(
+    opt: &Opt,
+    file: &mut dyn Write,
+    x_start: f64,
+    x_end: f64,
+    angle: f64,
+) -> Result<()> {
+    let u = opt.common.units;
+    let z_top = stock_dia(opt) / 2.0;
+    let depth = opt.chamfer_depth.unwrap_or_else(|| u.from_mm(0.2));
+    g0(
+        file,
+        xya(x_start, 0.0, helix_a(opt, angle, x_start)).units(u),
+    )?;
+    g1(file, zf(z_top - depth, opt.feed).units(u))?;
+    let x_from = if opt.herringbone {
+        // Each half has its own hand of helix, so change over in the middle
+        cut_along_x(opt, file, x_start, -opt.width / 2.0, angle)?;
+        -opt.width / 2.0
+    } else {
+        x_start
+    };
+    cut_along_x(opt, file, x_from, x_end, angle)?;
+    g0(file, z(z_top + u.from_mm(4.0)).units(u))
+}
+
+/// Break both top edges of every tooth with the chamfer tool, which is on the spindle above the stock. Each edge is
+/// turned up to the top (+Z) in turn, and the tool feeds along it, down one edge and back along the other.
+/// The tooth centers sit half way between the spaces, which were cut at +Y. Standard rotary axes turn
+/// counter-clockwise looking from +X, which carries +Y up towards +Z, so the tops are a quarter turn further on.
+fn chamfer_teeth(opt: &Opt, file: &mut dyn Write) -> Result<()> {
+    let u = opt.common.units;
+    let tooth_angle = gears::tooth_angle(opt.teeth);
+    let edge = top_edge_angle(opt);
+    let x_right = u.from_mm(4.0);
+    let x_left = -opt.width - u.from_mm(4.0);
+
+    g0(file, z(stock_dia(opt) / 2.0 + u.from_mm(4.0)).units(u))?;
+    for i in 0..opt.teeth {
+        gcode_comment(
+            file,
+            &format!("Chamfer tooth {} of {}", i + 1, opt.teeth),
+            opt.common.dialect,
+        )?;
+        let middle = (i as f64 + 0.5) * tooth_angle + 90.0;
+        chamfer_edge(opt, file, x_right, x_left, middle - edge)?;
+        chamfer_edge(opt, file, x_left, x_right, middle + edge)?;
+    }
 
     Ok(())
 }
 
 fn help_text(opt: &Opt) {
-    println!(
+    eprintln!(
         "Before cut:
-        - Create stock with OD {}mm
+        - Create stock with OD {:.4}{}
         - Set home to center of right face of stock",
-        (opt.teeth + 2) as f64 * opt.module
+        stock_dia(opt),
+        opt.common.units.abbrev()
+    );
+    if let Some(ball_dia) = opt.ball_dia {
+        eprintln!(
+            "        - Load a {}{} ball end mill, with its length measured to the tip",
+            ball_dia,
+            opt.common.units.abbrev()
+        );
+    } else if let Some(warning) = undercut_warning(opt) {
+        eprintln!("{}", warning);
+    }
+    if opt.alternate_direction || reverse_all(opt) {
+        // The start of the cuts from the left, at full depth, and the cutter's radius beyond its center
+        let stickout = gears::cutter_start_x(
+            opt.cutter_dia,
+            total_depth(opt),
+            opt.common.units.from_mm(4.0),
+        ) + opt.cutter_dia / 2.0;
+        eprintln!(
+            "        - Leave at least {:.4}{} of stock sticking out of the chuck, for the cuts that start from the left",
+            stickout,
+            opt.common.units.abbrev()
+        );
+    }
+    if opt.herringbone {
+        eprintln!(
+            "        - Turn a relief groove {:.4}{} wide in the middle of the stock, down to the root of the teeth",
+            relief_groove_width(opt),
+            opt.common.units.abbrev()
+        );
+    }
+    if let Some(first) = opt.split {
+        eprintln!(
+            "        - The gear is cut in two setups: leave the first {:.4}{u}, and room for the cutter to run out past it,
+          sticking out of the chuck, and at the stop turn the blank end for end to cut the other {:.4}{u}, with A0 where a tooth space faces +Y",
+            first,
+            opt.width - first,
+            u = opt.common.units.abbrev()
+        );
+    }
+    if let Some(number) = opt.chamfer_tool {
+        eprintln!(
+            "        - Measure the length of the chamfer tool, T{}, to its tip",
+            number
+        );
+    }
+    if let Some(number) = opt
+        .cutter_number
+        .filter(|_| opt.tooth_form == ToothForm::Involute)
+    {
+        let teeth = gears::equivalent_teeth(opt.teeth, opt.helix_angle);
+        if gears::cutter_number(teeth).map(|(n, _)| n) != Some(number) {
+            eprintln!(
+                "Warning: cutter #{} doesn't match {} teeth, use {}",
+                number,
+                teeth,
+                cutter_description(opt).unwrap_or_else(|| "a special cutter".to_string())
+            );
+        }
+    }
+    if opt.profile_shift != 0.0 {
+        eprintln!(
+            "Profile shift {}: meshed with an unshifted gear, the center distance changes by about {:+.4}{}",
+            opt.profile_shift,
+            opt.common.units
+                .from_mm(gears::profile_shift_offset(opt.module, opt.profile_shift)),
+            opt.common.units.abbrev()
+        );
+    }
+}
+
+/// What the operator does between the setups of a split gear, before cutting the `opt.width` left. This goes in a
+/// G-code comment, so it can't have parentheses of its own.
+fn flip_instructions(opt: &Opt) -> String {
+    format!(
+        "Turn the blank end for end and chuck it by the cut teeth, leaving the other {:.4}{} to cut. Set home to the center of its new right face, with A0 where a tooth space faces +Y. Then cycle start.",
+        opt.width,
+        opt.common.units.abbrev()
     )
 }
 
+/// Cut every tooth across `opt.width`, with the tool already loaded: roughing and finishing if there's a finishing
+/// tool, then chamfering if there's a chamfer tool. This leaves the last of those tools loaded.
+fn cut_setup(
+    opt: &Opt,
+    file: &mut dyn Write,
+    finish_tool: &Option<Tool>,
+    chamfer_tool: &Option<Tool>,
+) -> Result<()> {
+    match finish_tool {
+        None => {
+            cut_teeth(opt, file, Stage::Full)?;
+        }
+        Some(finish_tool) => {
+            gcode_comment(
+                file,
+                &format!(
+                    "Roughing with T{}, leaving {}{} for finishing with T{}",
+                    opt.tool,
+                    finish_allowance(opt),
+                    opt.common.units.abbrev(),
+                    finish_tool.number
+                ),
+                opt.common.dialect,
+            )?;
+            cut_teeth(opt, file, Stage::Rough)?;
+            opt.common
+                .optional_stop(file, "Check the roughed teeth before finishing")?;
+            gcode_comment(file, &finish_tool.comment, opt.common.dialect)?;
+            tool_change(file, finish_tool, opt.common.home(), opt.common.dialect)?;
+            cut_teeth(opt, file, Stage::Finish)?;
+        }
+    }
+    if let Some(chamfer_tool) = chamfer_tool {
+        opt.common
+            .optional_stop(file, "Check the teeth before chamfering them")?;
+        gcode_comment(file, &chamfer_tool.comment, opt.common.dialect)?;
+        tool_change(file, chamfer_tool, opt.common.home(), opt.common.dialect)?;
+        chamfer_teeth(opt, file)?;
+    }
+    Ok(())
+}
+
+/// Cut a gear in two setups, the first `first` of the width and then the rest, with `tool` loaded for the first and
+/// stopping between them for the blank to be turned end for end
+fn cut_split(
+    opt: &mut Opt,
+    file: &mut dyn Write,
+    first: f64,
+    tool: &Tool,
+    finish_tool: &Option<Tool>,
+    chamfer_tool: &Option<Tool>,
+) -> Result<()> {
+    // Each setup cuts its own part of the width from its own X0, so to the rest of the job that's the width
+    let total_width = opt.width;
+    opt.width = first;
+    gcode_comment(file, "Setup 1 of 2", opt.common.dialect)?;
+    cut_setup(opt, file, finish_tool, chamfer_tool)?;
+    opt.width = total_width - first;
+    operator_stop(
+        file,
+        opt.common.home(),
+        &flip_instructions(opt),
+        opt.common.dialect,
+    )?;
+    gcode_comment(file, "Setup 2 of 2", opt.common.dialect)?;
+    if finish_tool.is_some() || chamfer_tool.is_some() {
+        gcode_comment(file, &tool.comment, opt.common.dialect)?;
+        tool_change(file, tool, opt.common.home(), opt.common.dialect)?;
+    } else {
+        start_tool(file, tool, opt.common.dialect)?;
+    }
+    cut_setup(opt, file, finish_tool, chamfer_tool)?;
+    opt.width = total_width;
+    Ok(())
+}
+
 fn main() -> Result<()> {
-    let opt = Opt::from_args();
+    let mut opt: Opt = cli::from_args();
+    opt.module = match opt.dp {
+        Some(dp) => gears::module_from_dp(dp),
+        None => opt.module_arg.unwrap_or(1.0),
+    };
+    if let Err(e) = opt.depth_standard.depth_coefficient(opt.pressure_angle) {
+        clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+    }
+    let (min_shift, max_shift) = gears::PROFILE_SHIFT_RANGE;
+    if !(min_shift..=max_shift).contains(&opt.profile_shift) {
+        let clamped = opt.profile_shift.clamp(min_shift, max_shift);
+        eprintln!(
+            "Profile shift {} is out of range, using {}",
+            opt.profile_shift, clamped
+        );
+        opt.profile_shift = clamped;
+    }
+    if opt.subprograms && opt.helix_angle != 0.0 {
+        clap::Error::with_description(
+            "--subprograms can't be used with --helix-angle, each helical tooth needs its own A positions",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    if opt.subprograms && opt.common.dialect == Dialect::Grbl {
+        clap::Error::with_description(
+            "--subprograms can't be used with --dialect grbl, which has no subprograms",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    if opt.finish_tool.is_some() {
+        let allowance = finish_allowance(&opt);
+        if allowance <= 0.0 || allowance >= total_depth(&opt) {
+            clap::Error::with_description(
+                "--finish-allowance must be more than 0, and less than the total depth of the teeth",
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit();
+        }
+    } else if opt.finish_rpm.is_some() || opt.finish_allowance.is_some() {
+        clap::Error::with_description(
+            "--finish-rpm and --finish-allowance need a --finish-tool",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+    if let Some(first) = opt.split {
+        if opt.helix_angle != 0.0 || opt.subprograms {
+            clap::Error::with_description(
+                "--split can't be used with --helix-angle, as turning the blank round reverses the helix, or with --subprograms",
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit();
+        }
+        if first <= 0.0 || first >= opt.width {
+            clap::Error::with_description(
+                "--split must be more than 0, and less than the --width",
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit();
+        }
+    }
+    if opt.chamfer_tool.is_none() && (opt.chamfer_rpm.is_some() || opt.chamfer_depth.is_some()) {
+        clap::Error::with_description(
+            "--chamfer-rpm and --chamfer-depth need a --chamfer-tool",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+    if opt.alternate_direction && (opt.subprograms || opt.herringbone) {
+        clap::Error::with_description(
+            "--alternate-direction can't be used with --subprograms or --herringbone",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    if (opt.climb || opt.conventional) && (opt.alternate_direction || opt.herringbone) {
+        clap::Error::with_description(
+            "--climb and --conventional can't be used with --alternate-direction or --herringbone",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    if opt.herringbone && opt.helix_angle == 0.0 {
+        clap::Error::with_description(
+            "--herringbone needs a --helix-angle",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+    if opt
+        .start_tooth
+        .is_some_and(|tooth| tooth == 0 || tooth > opt.teeth)
+    {
+        clap::Error::with_description(
+            &format!("--start-tooth must be from 1 to {}", opt.teeth),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt.start_tooth.is_some() && opt.split.is_some() {
+        clap::Error::with_description(
+            "--start-tooth can't be used with --split, which cuts every tooth in each setup",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    if opt.herringbone && relief_groove_width(&opt) >= opt.width {
+        clap::Error::with_description(
+            &format!(
+                "A herringbone gear cut with this cutter needs a {:.4}{} relief groove, which leaves nothing of a gear this wide",
+                relief_groove_width(&opt),
+                opt.common.units.abbrev()
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    let cycloidal = match opt.tooth_form {
+        ToothForm::Involute => {
+            if opt.generating_dia.is_some() || opt.ball_dia.is_some() || opt.finish_step.is_some() {
+                clap::Error::with_description(
+                    "--generating-dia, --ball-dia, and --finish-step are only for --tooth-form cycloidal",
+                    clap::ErrorKind::ArgumentConflict,
+                )
+                .exit();
+            }
+            None
+        }
+        ToothForm::Cycloidal => {
+            if opt.helix_angle != 0.0
+                || opt.herringbone
+                || opt.subprograms
+                || opt.split.is_some()
+                || opt.finish_tool.is_some()
+                || opt.chamfer_tool.is_some()
+                || opt.alternate_direction
+                || opt.climb
+                || opt.conventional
+                || opt.profile_shift != 0.0
+                || opt.backlash != 0.0
+                || opt.spring_passes > 0
+            {
+                clap::Error::with_description(
+                    "--tooth-form cycloidal only cuts straight teeth with a single tool, so it can't be used with --helix-angle, --herringbone, --subprograms, --split, --finish-tool, --chamfer-tool, --alternate-direction, --climb, --conventional, --profile-shift, --backlash, or --spring-passes",
+                    clap::ErrorKind::ArgumentConflict,
+                )
+                .exit();
+            }
+            let ball_dia = match opt.ball_dia {
+                Some(ball_dia) => ball_dia,
+                None => clap::Error::with_description(
+                    "--tooth-form cycloidal needs a --ball-dia",
+                    clap::ErrorKind::MissingRequiredArgument,
+                )
+                .exit(),
+            };
+            match ball_path(&opt, ball_dia) {
+                Ok(path) => Some((ball_dia, path)),
+                Err(e) => clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit(),
+            }
+        }
+    };
     help_text(&opt);
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&opt.output)?;
-
-    preamble(
-        &opt.name,
-        opt.tool,
-        &format!("T{} D={} - gear mill", opt.tool, opt.cutter_dia),
-        opt.rpm,
-        opt.coolant,
+    let tool = match cycloidal {
+        Some((ball_dia, _)) => opt.common.tool(
+            opt.tool,
+            format!(
+                "T{} D={}{} - ball end mill",
+                opt.tool,
+                ball_dia,
+                opt.common.units.abbrev()
+            ),
+            opt.common.rpm(opt.rpm, Some(ball_dia)),
+        ),
+        None => opt.common.tool(
+            opt.tool,
+            format!(
+                "T{} D={}{} - gear mill",
+                opt.tool,
+                opt.cutter_dia,
+                opt.common.units.abbrev()
+            ),
+            opt.common.rpm(opt.rpm, Some(opt.cutter_dia)),
+        ),
+    };
+    opt.feed = match cycloidal {
+        Some((ball_dia, _)) => opt.common.feed(opt.feed, tool.rpm, Some(ball_dia), 2),
+        None => opt
+            .common
+            .feed(opt.feed, tool.rpm, Some(opt.cutter_dia), 12),
+    };
+    let finish_tool = opt.finish_tool.map(|number| Tool {
+        number,
+        comment: format!(
+            "T{} D={}{} - finishing gear mill",
+            number,
+            opt.cutter_dia,
+            opt.common.units.abbrev()
+        ),
+        rpm: opt
+            .finish_rpm
+            .map_or(tool.rpm, |rpm| opt.common.clamp_rpm(rpm)),
+        direction: tool.direction,
+        coolant: tool.coolant,
+        spindle_dwell: tool.spindle_dwell,
+    });
+    let chamfer_tool = opt.chamfer_tool.map(|number| {
+        opt.common.tool(
+            number,
+            format!("T{} - 90 degree chamfer tool", number),
+            opt.chamfer_rpm
+                .map_or(tool.rpm, |rpm| opt.common.clamp_rpm(rpm)),
+        )
+    });
+    let mut file = opt.common.open_output(&tool)?;
+    let mut notes = vec![];
+    if let Some(dp) = opt.dp {
+        notes.push(format!(
+            "Diametral pitch {}, module {:.4}mm",
+            dp, opt.module
+        ));
+    }
+    if cycloidal.is_some() {
+        notes.push(format!(
+            "Cycloidal teeth, with a {:.4}mm generating circle",
+            generating_dia(&opt)
+        ));
+    } else if let Some(warning) = undercut_warning(&opt) {
+        notes.push(warning);
+    }
+    if opt.alternate_direction {
+        notes.push(
+            "Alternating cut direction: even teeth cut towards -X, odd teeth towards +X (climb)"
+                .to_string(),
+        );
+    }
+    if let Some(milling) = Milling::from_flags(opt.climb, opt.conventional) {
+        notes.push(format!(
+            "{} milling, cutting towards {}X",
+            milling.name(),
+            if reverse_all(&opt) { "+" } else { "-" }
+        ));
+    }
+    if let Some(first) = opt.split {
+        notes.push(format!(
+            "Split into two setups: {}{u} from the right face, then the blank is turned end for end for the other {}{u}",
+            first,
+            opt.width - first,
+            u = opt.common.units.abbrev()
+        ));
+    }
+    if let Some(number) = opt.chamfer_tool {
+        notes.push(format!(
+            "Chamfering the top edges of the teeth {}{} deep with T{}, {:.2} degrees either side of each tooth's middle",
+            opt.chamfer_depth
+                .unwrap_or_else(|| opt.common.units.from_mm(0.2)),
+            opt.common.units.abbrev(),
+            number,
+            top_edge_angle(&opt)
+        ));
+    }
+    if opt.herringbone {
+        notes.push(format!(
+            "Herringbone, with a {:.4}{} relief groove in the middle",
+            relief_groove_width(&opt),
+            opt.common.units.abbrev()
+        ));
+    }
+    if cycloidal.is_none() {
+        notes.push(match cutter_description(&opt) {
+            Some(cutter) => format!("Use cutter {}", cutter),
+            None => "No standard cutter for this few teeth, use a special cutter".to_string(),
+        });
+    }
+    if opt.backlash != 0.0 {
+        notes.push(format!(
+            "Backlash allowance {}{u}, cut {:.4}{u} deeper",
+            opt.backlash,
+            gears::backlash_extra_depth(opt.backlash, opt.pressure_angle),
+            u = opt.common.units.abbrev()
+        ));
+    }
+    // The measurements are only worked out for straight involute teeth
+    if opt.helix_angle == 0.0 && cycloidal.is_none() {
+        notes.extend(inspection_notes(&opt));
+    }
+    if opt.spring_passes > 0 {
+        notes.push(format!(
+            "{} spring passes at final depth",
+            opt.spring_passes
+        ));
+    }
+    if let Some(tooth) = opt.start_tooth {
+        notes.push(format!(
+            "Resuming at tooth {} of {}: leave the work in place, and don't re-zero any axis",
+            tooth, opt.teeth
+        ));
+    }
+    let mut tools = vec![(&tool, opt.feed)];
+    tools.extend(finish_tool.iter().map(|tool| (tool, opt.feed)));
+    tools.extend(chamfer_tool.iter().map(|tool| (tool, opt.feed)));
+    job_header(
         &mut file,
+        &JobInfo {
+            stock: format!(
+                "OD {:.4}{u}, at least {}{u} long",
+                stock_dia(&opt),
+                opt.width,
+                u = opt.common.units.abbrev()
+            ),
+            datum: "center of right face of stock".to_string(),
+            tools,
+            notes,
+            units: opt.common.units,
+            dialect: opt.common.dialect,
+        },
     )?;
-    cut_teeth(&opt, &mut file)?;
-    trailer(&mut file)?;
+    if let Some((ball_dia, path)) = &cycloidal {
+        cut_cycloidal_teeth(&opt, &mut file, *ball_dia, path)?;
+    } else {
+        match opt.split {
+            None => cut_setup(&opt, &mut file, &finish_tool, &chamfer_tool)?,
+            Some(first) => cut_split(
+                &mut opt,
+                &mut file,
+                first,
+                &tool,
+                &finish_tool,
+                &chamfer_tool,
+            )?,
+        }
+    }
+    trailer(&mut file, opt.common.home(), opt.common.dialect)?;
+    if opt.subprograms {
+        if finish_tool.is_some() {
+            tooth_subprogram(&opt, &mut file, Stage::Rough)?;
+            tooth_subprogram(&opt, &mut file, Stage::Finish)?;
+        } else {
+            tooth_subprogram(&opt, &mut file, Stage::Full)?;
+        }
+    }
 
-    Ok(())
+    opt.common.finish(
+        file,
+        Some(Stock::Rect {
+            min: (-opt.width, -stock_dia(&opt) / 2.0),
+            max: (0.0, stock_dia(&opt) / 2.0),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(args: &[&str]) -> Opt {
+        let common = ["gear_gen", "--teeth=20", "--width=10", "--output=gear.nc"];
+        let mut opt = Opt::from_iter(common.iter().chain(args));
+        opt.module = opt.module_arg.unwrap_or(1.0);
+        opt
+    }
+
+    /// The value of the `letter` word on a `line` of G code
+    fn word(line: &str, letter: char) -> Option<f64> {
+        line.split_whitespace()
+            .find_map(|w| w.strip_prefix(letter))
+            .and_then(|v| v.parse().ok())
+    }
+
+    fn pass(opt: &Opt, angle: f64, reverse: bool) -> String {
+        let mut file = vec![];
+        pass_at_depth(opt, &mut file, total_depth(opt), angle, reverse).unwrap();
+        String::from_utf8(file).unwrap()
+    }
+
+    fn teeth(opt: &Opt, stage: Stage) -> String {
+        let mut file = vec![];
+        cut_teeth(opt, &mut file, stage).unwrap();
+        String::from_utf8(file).unwrap()
+    }
+
+    #[test]
+    fn helical_pass_follows_the_helix_to_the_end_of_the_stock() {
+        let helical = opt(&["--helix-angle=30"]);
+        for reverse in [false, true] {
+            let text = pass(&helical, 10.0, reverse);
+            // No feed-out arc, where A would stop following the helix
+            assert!(!text
+                .lines()
+                .any(|l| l.starts_with("G2 ") || l.starts_with("G3 ")));
+            let last = text
+                .lines()
+                .rev()
+                .find(|l| l.starts_with("G1 X") && l.contains(" A"))
+                .unwrap();
+            let x_end = if reverse { 0.0 } else { -10.0 };
+            assert_eq!(word(last, 'X'), Some(x_end));
+            assert!((word(last, 'A').unwrap() - helix_a(&helical, 10.0, x_end)).abs() < 1e-4);
+        }
+        // A spur gear still eases out on an arc
+        assert!(pass(&opt(&[]), 10.0, false).contains("\nG2 "));
+    }
+
+    /// The motion of a program, written back out (without its comments) the same way whether it came from
+    /// subprograms or not
+    fn motion(program: &str) -> String {
+        let mut text = vec![];
+        gcode::emit(&gcode::parse_moves(program), &mut text, Dialect::default()).unwrap();
+        String::from_utf8(text)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('('))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn subprograms_write_the_tooth_passes_once() {
+        let opt_subprograms = opt(&["--subprograms"]);
+        let mut file = vec![];
+        cut_teeth(&opt_subprograms, &mut file, Stage::Full).unwrap();
+        writeln!(file, "M30").unwrap();
+        tooth_subprogram(&opt_subprograms, &mut file, Stage::Full).unwrap();
+        let program = String::from_utf8(file).unwrap();
+
+        let mut body = vec![];
+        tooth_passes(&opt_subprograms, &mut body, 0.0, Stage::Full, false).unwrap();
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.contains("Pass at depth"));
+        assert_eq!(program.matches(&body).count(), 1);
+        assert_eq!(program.matches("M98 P").count(), 20);
+        // Calling the body for every tooth moves the cutter just as writing it out for every tooth does
+        let inline = teeth(&opt(&[]), Stage::Full) + "M30\n";
+        assert!(program.len() < inline.len() / 5);
+        assert_eq!(motion(&program), motion(&inline));
+    }
+
+    #[test]
+    fn start_tooth_leaves_out_the_teeth_before_it() {
+        for args in [&[][..], &["--subprograms"]] {
+            let full = teeth(&opt(args), Stage::Full);
+            let resumed = teeth(&opt(&[args, &["--start-tooth=5"]].concat()), Stage::Full);
+            // Teeth 1 to 4 get no passes, and the rest are cut just as they are in the whole job
+            let from = full.find("(Tooth 5 of 20)").unwrap();
+            assert!(from > 0);
+            assert_eq!(resumed, full[from..]);
+            assert_eq!(resumed.matches("(Tooth ").count(), 16);
+        }
+
+        // Only the first run over the teeth resumes, so every tooth is still finished
+        assert_eq!(
+            teeth(&opt(&["--start-tooth=5"]), Stage::Finish),
+            teeth(&opt(&[]), Stage::Finish)
+        );
+    }
+
+    #[test]
+    fn undercut_warning_for_the_addendum() {
+        // 15 full-depth teeth at 20 degrees are undercut, but the shorter addendum of stub teeth clears them
+        let mut full = opt(&[]);
+        full.teeth = 15;
+        let warning = undercut_warning(&full).unwrap();
+        assert!(warning.contains("fewer than the 17.1 needed"));
+        assert!(warning.contains("--profile-shift 0.12 or more"));
+
+        let mut stub = opt(&["--depth-standard=stub"]);
+        stub.teeth = 15;
+        assert_eq!(undercut_warning(&stub), None);
+        stub.teeth = 13;
+        assert!(undercut_warning(&stub)
+            .unwrap()
+            .contains("fewer than the 13.7 needed"));
+    }
+
+    #[test]
+    fn no_backlash_cuts_the_same_gear() {
+        for args in [&[][..], &["--helix-angle=30"], &["--units=imperial"]] {
+            let plain = opt(args);
+            let zero = opt(&[args, &["--backlash=0"]].concat());
+            assert_eq!(total_depth(&zero), total_depth(&plain));
+            assert_eq!(top_edge_angle(&zero), top_edge_angle(&plain));
+            assert_eq!(inspection_notes(&zero), inspection_notes(&plain));
+            for stage in [Stage::Full, Stage::Finish] {
+                assert_eq!(teeth(&zero, stage), teeth(&plain, stage));
+            }
+        }
+        // And any backlash cuts deeper
+        assert!(total_depth(&opt(&["--backlash=0.05"])) > total_depth(&opt(&[])));
+    }
+
+    #[test]
+    fn module_and_dp_conflict() {
+        let common = ["gear_gen", "--teeth=20", "--width=10", "--output=gear.nc"];
+        let both = common.iter().chain(&["--module=1", "--dp=24"]);
+        let err = Opt::from_iter_safe(both).unwrap_err();
+        assert_eq!(err.kind, clap::ErrorKind::ArgumentConflict);
+        assert!(Opt::from_iter_safe(common.iter().chain(&["--dp=24"])).is_ok());
+    }
+
+    fn setup(opt: &Opt, chamfer_tool: Option<u32>) -> String {
+        let chamfer_tool = chamfer_tool.map(|number| {
+            opt.common.tool(
+                number,
+                format!("T{} - 90 degree chamfer tool", number),
+                650.0,
+            )
+        });
+        let mut file = vec![];
+        cut_setup(opt, &mut file, &None, &chamfer_tool).unwrap();
+        String::from_utf8(file).unwrap()
+    }
+
+    #[test]
+    fn chamfer_every_tooth_after_cutting_them() {
+        for teeth in [13, 20] {
+            let mut chamfered = opt(&["--chamfer-tool=5"]);
+            chamfered.teeth = teeth as u32;
+            let text = setup(&chamfered, chamfered.chamfer_tool);
+            // The chamfer tool goes in once, after all the teeth are cut
+            assert_eq!(text.matches("T5 G43 H5 M6").count(), 1);
+            let (cut, chamfer) = text.split_once("T5 G43 H5 M6").unwrap();
+            assert_eq!(cut.matches("(Tooth ").count(), teeth);
+            assert!(!cut.contains("Chamfer"));
+            // Then both top edges of each tooth, going down to the chamfer depth on each
+            assert_eq!(chamfer.matches("(Chamfer tooth ").count(), teeth);
+            assert!(chamfer.contains(&format!("(Chamfer tooth {} of {})", teeth, teeth)));
+            let plunges: Vec<f64> = chamfer
+                .lines()
+                .filter(|l| l.starts_with("G1 Z"))
+                .map(|l| word(l, 'Z').unwrap())
+                .collect();
+            assert_eq!(plunges.len(), 2 * teeth);
+            let z_top = stock_dia(&chamfered) / 2.0;
+            assert!(plunges.iter().all(|&z| (z - (z_top - 0.2)).abs() < 1e-4));
+        }
+        // Without a chamfer tool there's nothing after the teeth
+        assert!(!setup(&opt(&[]), None).contains("Chamfer"));
+    }
+
+    #[test]
+    fn split_setups_make_up_the_width() {
+        for (first, args) in [
+            (4.0, &[][..]),
+            (7.5, &["--finish-tool=2"]),
+            (2.0, &["--chamfer-tool=5"]),
+        ] {
+            let mut split = opt(&[args, &[&format!("--split={}", first)]].concat());
+            let tool = split.common.tool(1, "T1 - gear mill".to_string(), 650.0);
+            let finish_tool = split
+                .finish_tool
+                .map(|number| split.common.tool(number, format!("T{}", number), 650.0));
+            let chamfer_tool = split
+                .chamfer_tool
+                .map(|number| split.common.tool(number, format!("T{}", number), 650.0));
+            let mut file = vec![];
+            cut_split(
+                &mut split,
+                &mut file,
+                first,
+                &tool,
+                &finish_tool,
+                &chamfer_tool,
+            )
+            .unwrap();
+            assert_eq!(split.width, 10.0);
+            let text = String::from_utf8(file).unwrap();
+
+            // Each setup's gear cutter passes run from its own right face out to the end of its part of the width, and
+            // the two add up to the whole
+            let (one, two) = text.split_once("(Setup 2 of 2)").unwrap();
+            let cut_width = |setup: &str| {
+                -setup[setup.find("(Tooth 1 of").unwrap()..]
+                    .split(" M6")
+                    .next()
+                    .unwrap()
+                    .lines()
+                    .filter(|l| l.starts_with("G1 ") || l.starts_with("G2 "))
+                    .filter_map(|l| word(l, 'X'))
+                    .fold(0.0, f64::min)
+            };
+            assert_eq!(cut_width(one), first);
+            assert_eq!(cut_width(two), 10.0 - first);
+            assert_eq!(cut_width(one) + cut_width(two), 10.0);
+            assert!(one.contains(&format!("leaving the other {:.4}mm", 10.0 - first)));
+        }
+    }
+
+    #[test]
+    fn herringbone_helix_is_mirrored_about_the_middle() {
+        let herringbone = opt(&["--helix-angle=30", "--herringbone"]);
+        let middle = -5.0;
+        for d in [0.5, 2.0, 5.0] {
+            let (right, left) = (
+                helix_a(&herringbone, 10.0, middle + d),
+                helix_a(&herringbone, 10.0, middle - d),
+            );
+            assert!((right - left).abs() < 1e-9);
+            // Both ends of the stock are at the A of X0
+            assert_eq!(right == 10.0, d == 5.0);
+        }
+
+        // The pass stops the same distance either side of the middle, at the same A, and finishes across the far end
+        // of the stock at the A it started from at X0
+        let mut file = vec![];
+        herringbone_pass(&herringbone, &mut file, total_depth(&herringbone), 10.0).unwrap();
+        let text = String::from_utf8(file).unwrap();
+        let moves: Vec<(f64, f64)> = text
+            .lines()
+            .filter_map(|l| Some((word(l, 'X')?, word(l, 'A')?)))
+            .collect();
+        let (out, back_in, end) = (moves[1], moves[2], moves[3]);
+        assert!(((out.0 - middle) - (middle - back_in.0)).abs() < 1e-4);
+        assert!(out.0 > middle && back_in.0 < middle);
+        assert!((out.1 - back_in.1).abs() < 1e-4);
+        assert_eq!(end, (-10.0, 10.0));
+    }
 }
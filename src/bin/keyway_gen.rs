@@ -0,0 +1,413 @@
+//! G-Code generator for finishing the bore of a gear blank, and shaping a keyway in it, in the same setup.
+//! The blank lies face up with its bore on Z. The bore is milled with a helix, then the keyway is shaped along the +X
+//! side of it with the spindle stopped: a tool ground to cut on its end is stroked down through the blank, stepping out
+//! a little further in +X for each stroke, like a broach.
+use gcode::cli::{self, CommonOpts};
+use gcode::{
+    g0, g1, g2r, g3r, gcode_comment, job_header, tool_change, trailer, xf, xy, xyf, xyzrf, z, zf,
+    JobInfo, Milling,
+};
+use std::io::{Result, Write};
+use structopt::{clap, StructOpt};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "keyway_gen", about = "A bore and keyway generator")]
+struct Opt {
+    /// Diameter of the finished bore
+    #[structopt(long)]
+    bore_dia: f64,
+
+    /// Thickness of the blank, from its top face at Z0
+    #[structopt(short, long)]
+    thickness: f64,
+
+    /// Diameter of the end mill for the bore
+    #[structopt(long, default_value = "6")]
+    tool_dia: f64,
+
+    /// End mill RPM
+    #[structopt(long, default_value = "3000")]
+    rpm: f64,
+
+    /// Feed rate of the end mill, in units/min
+    #[structopt(long, default_value = "200")]
+    feed: f64,
+
+    /// Tool number of the end mill
+    #[structopt(long, default_value = "1")]
+    tool: u32,
+
+    /// How far the helix goes down for each turn round the bore [default: 0.5mm]
+    #[structopt(long)]
+    helix_pitch: Option<f64>,
+
+    /// The bore is already finished, so only shape the keyway
+    #[structopt(long)]
+    skip_bore: bool,
+
+    /// Width of the keyway. Leave unset to only cut the bore.
+    #[structopt(long, requires = "key-depth")]
+    key_width: Option<f64>,
+
+    /// Depth of the keyway, out from the bore on the keyway's center line
+    #[structopt(long, requires = "key-width")]
+    key_depth: Option<f64>,
+
+    /// Width of the shaping tool's cutting edge [default: the --key-width]. A narrower tool takes several strokes side
+    /// by side at each depth.
+    #[structopt(long)]
+    shaper_width: Option<f64>,
+
+    /// Tool number of the shaping tool
+    #[structopt(long, default_value = "2")]
+    shaper_tool: u32,
+
+    /// How much deeper each stroke of the shaping tool cuts [default: 0.05mm]
+    #[structopt(long)]
+    stroke_step: Option<f64>,
+
+    /// Feed rate of each stroke, in units/min
+    #[structopt(long, default_value = "500")]
+    stroke_feed: f64,
+
+    #[structopt(flatten)]
+    common: CommonOpts,
+}
+
+/// Radius of the path of the end mill's center round the bore
+fn helix_radius(opt: &Opt) -> f64 {
+    (opt.bore_dia - opt.tool_dia) / 2.0
+}
+
+/// How far the tools go past the bottom face of the blank, to be sure of cutting all the way through
+fn overtravel(opt: &Opt) -> f64 {
+    opt.common.units.from_mm(0.5)
+}
+
+/// X positions of the shaping tool's edge for each stroke, stepping out from where the corners of the keyway meet the
+/// bore to the full depth
+fn stroke_depths(opt: &Opt, key_width: f64, key_depth: f64) -> Vec<f64> {
+    let u = opt.common.units;
+    let step = opt.stroke_step.unwrap_or_else(|| u.from_mm(0.05));
+    let r = opt.bore_dia / 2.0;
+    let start = (r * r - key_width * key_width / 4.0).sqrt();
+    let end = r + key_depth;
+    let mut depths = vec![];
+    let mut at = start;
+    while at < end {
+        at = (at + step).min(end);
+        depths.push(at);
+    }
+    depths
+}
+
+/// Y positions of the middle of the shaping tool, side by side across the keyway, overlapping evenly if it's narrower
+fn stroke_ys(key_width: f64, shaper_width: f64) -> Vec<f64> {
+    let spread = key_width - shaper_width;
+    let strokes = (spread / shaper_width).ceil() as usize + 1;
+    if strokes == 1 {
+        vec![0.0]
+    } else {
+        (0..strokes)
+            .map(|i| -spread / 2.0 + spread * i as f64 / (strokes - 1) as f64)
+            .collect()
+    }
+}
+
+/// Mill the bore with a helix down through the blank, then go once round at the bottom to leave the wall straight.
+/// It's cut conventionally for whichever way the spindle turns.
+fn cut_bore(opt: &Opt, file: &mut dyn Write) -> Result<()> {
+    let u = opt.common.units;
+    let r = helix_radius(opt);
+    let pitch = opt.helix_pitch.unwrap_or_else(|| u.from_mm(0.5));
+    let z_bottom = -opt.thickness - overtravel(opt);
+    // Round the inside of the bore, a clockwise path is conventional with a clockwise spindle
+    let ccw = Milling::Conventional.reverses(Milling::Conventional, opt.common.spindle_dir());
+    let arc = |file: &mut dyn Write, from: (f64, f64), to| {
+        if ccw {
+            g3r(file, from, to)
+        } else {
+            g2r(file, from, to)
+        }
+    };
+    let turn = if ccw { 1.0 } else { -1.0 };
+
+    gcode_comment(
+        file,
+        &format!("Bore {}{}", opt.bore_dia, u.abbrev()),
+        opt.common.dialect,
+    )?;
+    g0(file, z(u.from_mm(4.0)).units(u))?;
+    g0(file, xy(r, 0.0).units(u))?;
+    g1(file, zf(0.0, opt.feed).units(u))?;
+
+    // Quarter turns, as a whole circle is ambiguous in radius form, each going down by the same amount
+    let quarters = (-z_bottom / (pitch / 4.0)).ceil() as u32;
+    let mut at = (r, 0.0);
+    for i in 1..=quarters + 4 {
+        let angle = turn * i as f64 * std::f64::consts::FRAC_PI_2;
+        let to = (r * angle.cos(), r * angle.sin());
+        let z_to = z_bottom * (i.min(quarters) as f64 / quarters as f64);
+        arc(file, at, xyzrf(to.0, to.1, z_to, r, opt.feed).units(u))?;
+        at = to;
+    }
+    // Ease in off the wall before coming up, so the rapid doesn't drag along it
+    let ease = (r - u.from_mm(0.2)).max(0.0) / r;
+    g1(file, xyf(at.0 * ease, at.1 * ease, opt.feed).units(u))?;
+    g0(file, z(u.from_mm(4.0)).units(u))?;
+
+    Ok(())
+}
+
+/// One downward stroke of the shaping tool, from clear above the blank, with its edge at `x_edge` and its middle at
+/// `y_mid`. It comes back up backed off from the cut, so it doesn't rub on the return.
+fn stroke(opt: &Opt, file: &mut dyn Write, x_edge: f64, y_mid: f64) -> Result<()> {
+    let u = opt.common.units;
+    let back_off = u.from_mm(0.5);
+    g0(file, xy(x_edge - back_off, y_mid).units(u))?;
+    g1(file, xf(x_edge, opt.stroke_feed).units(u))?;
+    g1(
+        file,
+        zf(-opt.thickness - overtravel(opt), opt.stroke_feed).units(u),
+    )?;
+    g1(file, xf(x_edge - back_off, opt.stroke_feed).units(u))?;
+    g0(file, z(u.from_mm(4.0)).units(u))
+}
+
+fn cut_keyway(opt: &Opt, file: &mut dyn Write, key_width: f64, key_depth: f64) -> Result<()> {
+    let u = opt.common.units;
+    let ys = stroke_ys(key_width, opt.shaper_width.unwrap_or(key_width));
+    let depths = stroke_depths(opt, key_width, key_depth);
+    gcode_comment(
+        file,
+        &format!(
+            "Keyway {}{u} wide and {}{u} deep, in {} strokes",
+            key_width,
+            key_depth,
+            depths.len() * ys.len(),
+            u = u.abbrev()
+        ),
+        opt.common.dialect,
+    )?;
+    g0(file, z(u.from_mm(4.0)).units(u))?;
+    for x_edge in depths {
+        for &y_mid in &ys {
+            stroke(opt, file, x_edge, y_mid)?;
+        }
+    }
+    Ok(())
+}
+
+fn help_text(opt: &Opt) {
+    let u = opt.common.units;
+    eprintln!(
+        "Before cut:
+        - Clamp the blank face up, raised at least {:.4}{u} clear of the table
+        - Set home to the center of the bore, on the top face of the blank",
+        overtravel(opt),
+        u = u.abbrev()
+    );
+    if !opt.skip_bore && helix_radius(opt) > opt.tool_dia / 2.0 {
+        eprintln!("        - The middle of the bore comes out as a slug at the end of the helix, so support it");
+    }
+    if opt.key_width.is_some() {
+        eprintln!(
+            "        - Grind the shaping tool to cut on its end, facing +X, and set it up with the spindle locked and the
+          middle of its edge on the spindle's axis"
+        );
+    }
+}
+
+fn main() -> Result<()> {
+    let mut opt: Opt = cli::from_args();
+    let u = opt.common.units;
+    if opt.skip_bore && opt.key_width.is_none() {
+        clap::Error::with_description(
+            "--skip-bore needs a --key-width and --key-depth, or there's nothing to cut",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+    if !opt.skip_bore && opt.tool_dia >= opt.bore_dia {
+        clap::Error::with_description(
+            "--tool-dia must be smaller than the --bore-dia",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if let Some(key_width) = opt.key_width {
+        if key_width >= opt.bore_dia {
+            clap::Error::with_description(
+                "--key-width must be narrower than the --bore-dia",
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit();
+        }
+        if opt.shaper_width.is_some_and(|width| width > key_width) {
+            clap::Error::with_description(
+                "--shaper-width can't be wider than the --key-width",
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit();
+        }
+    }
+    help_text(&opt);
+
+    let mill = opt.common.tool(
+        opt.tool,
+        format!("T{} D={}{} - end mill", opt.tool, opt.tool_dia, u.abbrev()),
+        opt.common.rpm(opt.rpm, Some(opt.tool_dia)),
+    );
+    opt.feed = opt.common.feed(opt.feed, mill.rpm, Some(opt.tool_dia), 2);
+    let shaper = opt.common.tool(
+        opt.shaper_tool,
+        format!("T{} shaping tool", opt.shaper_tool),
+        0.0,
+    );
+    let mut tools = vec![];
+    if !opt.skip_bore {
+        tools.push((&mill, opt.feed));
+    }
+    if opt.key_width.is_some() {
+        tools.push((&shaper, opt.stroke_feed));
+    }
+    let info = JobInfo {
+        stock: format!(
+            "gear blank {}{u} thick, {} a {}{u} bore",
+            opt.thickness,
+            if opt.skip_bore {
+                "with"
+            } else {
+                "to finish to"
+            },
+            opt.bore_dia,
+            u = u.abbrev()
+        ),
+        datum: "center of the bore, on the top face of the blank".to_string(),
+        tools,
+        notes: vec![],
+        units: u,
+        dialect: opt.common.dialect,
+    };
+    let mut file = if opt.skip_bore {
+        let mut file = opt.common.open_output(&shaper)?;
+        job_header(&mut file, &info)?;
+        file
+    } else {
+        let mut file = opt.common.open_output(&mill)?;
+        job_header(&mut file, &info)?;
+        cut_bore(&opt, &mut file)?;
+        file
+    };
+    if let (Some(key_width), Some(key_depth)) = (opt.key_width, opt.key_depth) {
+        if !opt.skip_bore {
+            opt.common
+                .optional_stop(&mut file, "Check the bore before shaping the keyway")?;
+            gcode_comment(&mut file, &shaper.comment, opt.common.dialect)?;
+            tool_change(&mut file, &shaper, opt.common.home(), opt.common.dialect)?;
+        }
+        cut_keyway(&opt, &mut file, key_width, key_depth)?;
+    }
+    trailer(&mut file, opt.common.home(), opt.common.dialect)?;
+
+    opt.common.finish(file, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(args: &[&str]) -> Opt {
+        let common = [
+            "keyway_gen",
+            "--bore-dia=20",
+            "--thickness=8",
+            "--output=bore.nc",
+        ];
+        Opt::from_iter(common.iter().chain(args))
+    }
+
+    /// The value of the `letter` word on a `line` of G code
+    fn word(line: &str, letter: char) -> Option<f64> {
+        line.split_whitespace()
+            .find_map(|w| w.strip_prefix(letter))
+            .and_then(|v| v.parse().ok())
+    }
+
+    fn keyway(opt: &Opt) -> String {
+        let mut file = vec![];
+        cut_keyway(
+            opt,
+            &mut file,
+            opt.key_width.unwrap(),
+            opt.key_depth.unwrap(),
+        )
+        .unwrap();
+        String::from_utf8(file).unwrap()
+    }
+
+    #[test]
+    fn keyway_strokes() {
+        // The corners of a 6mm keyway meet a 20mm bore sqrt(10^2 - 3^2) out, and the keyway goes out to 12.5, so
+        // 0.05 deeper each stroke takes ceil(2.961 / 0.05) strokes
+        let steps = ((10.0 + 2.5 - 91f64.sqrt()) / 0.05f64).ceil() as usize;
+        assert_eq!(steps, 60);
+        let text = keyway(&opt(&["--key-width=6", "--key-depth=2.5"]));
+        let downs: Vec<&str> = text.lines().filter(|l| l.starts_with("G1 Z")).collect();
+        assert_eq!(downs.len(), steps);
+        // Each one all the way through the blank, and the last out to the full depth
+        assert!(downs.iter().all(|l| word(l, 'Z') == Some(-8.5)));
+        let edges: Vec<f64> = text
+            .lines()
+            .filter(|l| l.starts_with("G1 X"))
+            .step_by(2)
+            .map(|l| word(l, 'X').unwrap())
+            .collect();
+        assert_eq!(edges.len(), steps);
+        assert!(edges.windows(2).all(|pair| pair[1] > pair[0]));
+        assert_eq!(edges[steps - 1], 12.5);
+        assert!(text.contains(&format!("in {} strokes", steps)));
+
+        // A shaper a third of the width takes three strokes side by side at each depth
+        let narrow = keyway(&opt(&[
+            "--key-width=6",
+            "--key-depth=2.5",
+            "--shaper-width=2",
+        ]));
+        assert_eq!(
+            narrow.lines().filter(|l| l.starts_with("G1 Z")).count(),
+            3 * steps
+        );
+        let text = keyway(&opt(&[
+            "--key-width=6",
+            "--key-depth=2.5",
+            "--stroke-step=0.5",
+        ]));
+        assert_eq!(text.lines().filter(|l| l.starts_with("G1 Z")).count(), 6);
+    }
+
+    #[test]
+    fn bore_helix_reaches_the_bottom() {
+        for args in [&[][..], &["--ccw"], &["--helix-pitch=1.1"]] {
+            let opt = opt(args);
+            let mut file = vec![];
+            cut_bore(&opt, &mut file).unwrap();
+            let text = String::from_utf8(file).unwrap();
+            let arcs: Vec<&str> = text
+                .lines()
+                .filter(|l| l.starts_with("G2 ") || l.starts_with("G3 "))
+                .collect();
+            let zs: Vec<f64> = arcs.iter().map(|l| word(l, 'Z').unwrap()).collect();
+            // Down a little each quarter turn, no more than a quarter of the pitch, to the overtravel below the blank
+            assert!(zs.windows(2).all(|pair| pair[1] <= pair[0]));
+            let pitch = opt.helix_pitch.unwrap_or(0.5);
+            assert!(zs[0] < 0.0 && zs[0] >= -pitch / 4.0 - 1e-4);
+            // Then once round at the bottom
+            assert!(zs[zs.len() - 5..].iter().all(|&z| z == -8.5));
+            assert!(zs[zs.len() - 6] > -8.5);
+            for arc in arcs {
+                assert_eq!(word(arc, 'R'), Some(7.0));
+            }
+        }
+    }
+}
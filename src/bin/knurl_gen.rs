@@ -1,14 +1,16 @@
-///! G-Code generator for cutting knurling tools on a rotational axis
-///! This is designed for cutting with engraving or chamfering tools: a mill with a sharp end.
-///! The included angle (and depth) of the teeth depends on the included angle of the tool.
+//! G-Code generator for cutting knurling tools on a rotational axis
+//! This is designed for cutting with engraving or chamfering tools: a mill with a sharp end.
+//! The included angle (and depth) of the teeth depends on the included angle of the tool.
+use gcode::cli::{self, CommonOpts};
+use gcode::svg::Stock;
 use gcode::{
-    g0, g1, gcode_comment, inv_feed_g93, preamble, standard_feed_g94, trailer, xaf, xf, xyza, zf, z,
+    g0, g1, gcode_comment, index_angles, inv_feed_g93, inverse_feed, job_header, standard_feed_g94,
+    trailer, xaf, xf, xyza, xzaf, z, zf, JobInfo,
 };
 use std::f64::consts::PI;
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Result, Write};
-use std::path::PathBuf;
-use structopt::StructOpt;
+use std::io::{Result, Write};
+use std::str::FromStr;
+use structopt::{clap, StructOpt};
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -16,27 +18,27 @@ use structopt::StructOpt;
     about = "Generates tool paths to create knurling tools"
 )]
 struct Opt {
-    /// Length (along A axis) of the knurler we're creating, in mm
+    /// Length (along A axis) of the knurler we're creating
     #[structopt(long, default_value = "10")]
     len: f64,
 
-    /// Diameter of knurler we're creating, in mm
-    #[structopt(long)]
+    /// Diameter of knurler we're creating, at its right-hand (X0) end
+    #[structopt(long, alias = "dia-start")]
     dia: f64,
 
+    /// Diameter at the far (-X) end, for a tapered knurler [default: the same as --dia]
+    #[structopt(long)]
+    dia_end: Option<f64>,
+
     /// Tool RPM
     // Feed and speed defaults for 1/4" carbide in annealed W1
     #[structopt(long, default_value = "9500")]
     rpm: f64,
 
-    /// Feed rate, in mm/min
+    /// Feed rate, in units/min
     #[structopt(long, default_value = "180")]
     feed: f64,
 
-    /// Name for the job
-    #[structopt(short, long)]
-    name: Option<String>,
-
     /// Tool number for the cut
     #[structopt(long, default_value = "17")]
     tool: u32,
@@ -45,135 +47,332 @@ struct Opt {
     #[structopt(long, default_value = "40")]
     tool_inc_angle: f64,
 
-    /// Knurling tool pitch (in mm per tooth). Typical tools vary from 1.6 (15tpi) to 0.75 (33tpi).
+    /// Knurling tool pitch (in units per tooth). Typical tools vary from 1.6mm (15tpi) to 0.75mm (33tpi).
     #[structopt(long, default_value = "1")]
     pitch: f64,
 
-    /// Max cutting stepdown, per pass, in mm
+    /// Max cutting stepdown, per pass
     #[structopt(long, default_value = "0.25")]
     max_stepdown: f64,
 
-    /// Spiral angle (degrees). 0 for straight-cut knurler, 45 for diamond.
+    /// Vary the spacing between teeth by up to this fraction of the even spacing, pseudo-randomly, to break up chatter.
+    /// 0 spaces them evenly.
+    #[structopt(long, default_value = "0")]
+    spacing_variation: f64,
+
+    /// Seed for the pseudo-random tooth spacing. The same seed always gives the same spacing.
+    #[structopt(long, default_value = "1")]
+    seed: u64,
+
+    /// Knurl pattern: straight, left-spiral, right-spiral, or diamond (both spirals, crossing)
+    #[structopt(long, default_value = "right-spiral")]
+    pattern: Pattern,
+
+    /// Spiral angle (degrees) of the teeth, for the spiral and diamond patterns. 45 makes a square diamond.
     #[structopt(long, default_value = "45")]
     spiral_angle: f64,
 
-    /// Spiral the other way
-    #[structopt(long)]
-    reverse_spiral: bool,
+    /// Resume a job that stopped part way through, starting at this pass, as numbered (from 0) in the program's "Pass N
+    /// of M" comments. Leave the work where it is, and don't re-zero any axis (A included).
+    #[structopt(long, default_value = "0")]
+    start_pass: usize,
 
-    /// Output file for the resulting G code
-    #[structopt(short, long, parse(from_os_str))]
-    output: PathBuf,
+    /// Resume at this tooth of the --start-pass, as numbered (from 0) in the program's "Tooth N of M" comments
+    #[structopt(long, default_value = "0")]
+    start_tooth: usize,
 
+    /// Resume in the right-hand spiral teeth of a diamond knurl, the left-hand ones being finished
     #[structopt(long)]
-    coolant: bool,
+    start_right_hand: bool,
+
+    #[structopt(flatten)]
+    common: CommonOpts,
+}
+
+/// Pattern of teeth on the knurler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    /// Teeth parallel to the axis
+    Straight,
+    /// Left-hand spiral teeth
+    LeftSpiral,
+    /// Right-hand spiral teeth
+    RightSpiral,
+    /// A full set of left-hand spiral teeth, then a full set of right-hand ones across them
+    Diamond,
+}
+
+impl Pattern {
+    /// The spirals to cut, in order, as whether each turns the A axis backwards (a left-hand spiral) while cutting
+    fn spirals(self) -> &'static [bool] {
+        match self {
+            Pattern::Straight | Pattern::RightSpiral => &[false],
+            Pattern::LeftSpiral => &[true],
+            Pattern::Diamond => &[true, false],
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "straight" => Ok(Pattern::Straight),
+            "left-spiral" | "left" => Ok(Pattern::LeftSpiral),
+            "right-spiral" | "right" => Ok(Pattern::RightSpiral),
+            "diamond" => Ok(Pattern::Diamond),
+            _ => Err(format!(
+                "Unknown pattern {}, expected straight, left-spiral, right-spiral, or diamond",
+                s
+            )),
+        }
+    }
+}
+
+/// Spiral angle (degrees) of the teeth, which is 0 for straight teeth whatever --spiral-angle says
+fn spiral_angle(opt: &Opt) -> f64 {
+    if opt.pattern == Pattern::Straight {
+        0.0
+    } else {
+        opt.spiral_angle
+    }
+}
+
+/// Diameter of the stock `dist` along from its right-hand face. A tapered knurler changes diameter linearly.
+fn dia_at(opt: &Opt, dist: f64) -> f64 {
+    match opt.dia_end {
+        Some(dia_end) => opt.dia + (dia_end - opt.dia) * dist / opt.len,
+        None => opt.dia,
+    }
+}
+
+/// Diameter halfway along, which we space the teeth and work out their depth for
+fn mean_dia(opt: &Opt) -> f64 {
+    (dia_at(opt, 0.0) + dia_at(opt, opt.len)) / 2.0
+}
 
+/// How far (in degrees) the A axis turns while a spiral tooth runs `dist` along the stock. On a taper the tooth keeps
+/// its angle to the axis, so it turns faster where the stock is narrower.
+fn spiral_lead(opt: &Opt, dist: f64) -> f64 {
+    let tan = spiral_angle(opt).to_radians().tan();
+    let (dia_start, dia_end) = (dia_at(opt, 0.0), dia_at(opt, opt.len));
+    if dia_start == dia_end {
+        360.0 * dist * tan / (PI * dia_start)
+    } else {
+        // The integral of 1/dia along the length
+        360.0 * tan * opt.len * (dia_at(opt, dist) / dia_start).ln() / (PI * (dia_end - dia_start))
+    }
+}
 
+/// Z of the bottom of a tooth, `dist` along the stock. `cut_depth` is the depth at the mean diameter, and the depth
+/// grows with the diameter along a taper, so the teeth keep the same shape as they get bigger.
+fn tooth_bottom_z(opt: &Opt, dist: f64, cut_depth: f64) -> f64 {
+    let dia = dia_at(opt, dist);
+    dia / 2.0 - cut_depth * dia / mean_dia(opt)
 }
 
 fn help_text(opt: &Opt) {
-    println!(
-        "Before cut:
-        - Create stock with OD {}mm
+    match opt.dia_end {
+        Some(dia_end) => eprintln!(
+            "Before cut:
+        - Create stock tapering from OD {}{u} at the right face to {}{u}, {}{u} along
         - Set home to center of right face of stock",
-        opt.dia
-    )
+            opt.dia,
+            dia_end,
+            opt.len,
+            u = opt.common.units.abbrev()
+        ),
+        None => eprintln!(
+            "Before cut:
+        - Create stock with OD {}{}
+        - Set home to center of right face of stock",
+            opt.dia,
+            opt.common.units.abbrev()
+        ),
+    }
 }
 
-/// Calculate the feed rate we need to tell the machine to get a real surface feed rate of `target_feed`, in units of
-/// 1/minutes (for G93 inverse feed rate mode)
-/// LinuxCNC says this about the way feed rate is interpreted during simultaneous multi-axis:
-///   "If any of XYZ are moving, F is in units per minute in the XYZ cartesian system, and all
-///    other axes (ABCUVW) move so as to start and stop in coordinated fashion."
-/// So we have to correct the feed rate we get from the machine to get the right actual feed at the tip of the tool. Doing
-///  that in a way that machines agree on seems hard, so instead we use G93 mode and let the machine figure out the
-///  XYZ and ABC feed rates.
-fn calc_feed_g93(opt: &Opt) -> f64 {
-    // How much we adjust the feed to compensate for simultaneous rotary motion
-    let cutting_path_length = opt.len / opt.spiral_angle.to_radians().cos();
-    // Feed in units of 1/minute
-    opt.feed / cutting_path_length
+/// Inverse time feed rate (for G93 mode) to cut a tooth `len` along the axis at our feed rate
+fn calc_feed_g93(opt: &Opt, len: f64) -> f64 {
+    // The tooth runs around the stock as it goes, making the path along the surface longer
+    let cutting_path_length = len / spiral_angle(opt).to_radians().cos();
+    inverse_feed(cutting_path_length, opt.feed)
 }
 
-// Cut a single pass of a single tooth
+// Cut a single pass of a single tooth, turning the A axis backwards while cutting if `reverse_spiral`
 fn cut_tooth(
     opt: &Opt,
     file: &mut dyn Write,
     a_start: f64,
-    stock_top_z: f64,
     cut_depth: f64,
+    reverse_spiral: bool,
 ) -> Result<()> {
+    let u = opt.common.units;
     // How far away we want to keep the tool from the work when not cutting
-    let clearance = 3.0;
+    let clearance = u.from_mm(3.0);
 
     // We're always cutting along the X axis at y=0
     let tool_y = 0.0;
 
-    // Calculate the ending angle for the spiral, in degrees. This is how much we turn the A axis
-    // while cutting
-    let a_move = 360.0 * opt.len * opt.spiral_angle.to_radians().tan() / (PI * opt.dia);
-    let a_end = if opt.reverse_spiral {
-        a_start - a_move
-    } else {
-        a_start + a_move
+    // The angle for the spiral, in degrees, `dist` along the cut. This is how much we turn the A axis while cutting.
+    let a_at = |dist: f64| {
+        if reverse_spiral {
+            a_start - spiral_lead(opt, dist)
+        } else {
+            a_start + spiral_lead(opt, dist)
+        }
     };
 
-    let cutting_feed = calc_feed_g93(opt);
+    let tapered = opt.dia_end.is_some_and(|dia_end| dia_end != opt.dia);
+    let stock_top_z = dia_at(opt, 0.0) / 2.0;
+    let end_z = tooth_bottom_z(opt, opt.len, cut_depth);
+    // High enough to clear the biggest end of the stock
+    let safe_z = dia_at(opt, 0.0).max(dia_at(opt, opt.len)) / 2.0 + clearance;
 
     g0(
         file,
-        xyza(clearance, tool_y, stock_top_z + clearance, a_start),
+        xyza(clearance, tool_y, stock_top_z + clearance, a_start).units(u),
     )?;
     // Plunge the tool to z depth. Shouldn't be cutting yet, but we're being a bit careful
-    g1(file, zf(stock_top_z - cut_depth, opt.feed))?;
+    g1(
+        file,
+        zf(tooth_bottom_z(opt, 0.0, cut_depth), opt.feed).units(u),
+    )?;
     // Feed in along the x axis until the tool is about to make contact
-    g1(file, xf(0.1, opt.feed))?;
+    g1(file, xf(u.from_mm(0.1), opt.feed).units(u))?;
 
-    // Simultaneously move in X and A, cutting the actual tooth
+    // Simultaneously move in X and A, cutting the actual tooth. On a taper, we also follow the stock down (or up) in
+    // Z, in short (0.5mm) steps so the A axis can keep up with the changing lead.
     inv_feed_g93(file)?;
-    g1(file, xaf(-opt.len, a_end, cutting_feed))?;
+    if tapered {
+        let steps = (opt.len / u.from_mm(0.5)).ceil() as u32;
+        let step_len = opt.len / steps as f64;
+        let mut last_z = tooth_bottom_z(opt, 0.0, cut_depth);
+        for i in 1..=steps {
+            let dist = step_len * i as f64;
+            let z_pos = tooth_bottom_z(opt, dist, cut_depth);
+            let feed = calc_feed_g93(opt, step_len.hypot(z_pos - last_z));
+            g1(file, xzaf(-dist, z_pos, a_at(dist), feed).units(u))?;
+            last_z = z_pos;
+        }
+    } else {
+        g1(
+            file,
+            xaf(-opt.len, a_at(opt.len), calc_feed_g93(opt, opt.len)).units(u),
+        )?;
+    }
     standard_feed_g94(file)?;
 
     // Move out of the work in X first, then Z, at the feed rate a short way, then rapid to clearance height
-    g1(file, xf(-(opt.len + 0.5), opt.feed))?;
-    g1(file, zf(stock_top_z - cut_depth + 0.5, opt.feed))?;
-    g0(file, z(stock_top_z + clearance))?;
+    g1(file, xf(-(opt.len + u.from_mm(0.5)), opt.feed).units(u))?;
+    g1(file, zf(end_z + u.from_mm(0.5), opt.feed).units(u))?;
+    g0(file, z(safe_z).units(u))?;
     // And rapid back to where we started
-    g0(
-        file,
-        xyza(clearance, tool_y, stock_top_z + clearance, a_start),
-    )?;
+    g0(file, xyza(clearance, tool_y, safe_z, a_start).units(u))?;
 
     Ok(())
 }
 
+/// Number of teeth to cut, as a whole number round the mean diameter at the --pitch
+fn teeth(opt: &Opt) -> usize {
+    (PI * mean_dia(opt) / opt.pitch).floor() as usize
+}
+
+/// Depth of each tooth, for the tool's included angle to meet the pitch the teeth are really cut at
+fn tooth_depth(opt: &Opt) -> f64 {
+    let actual_tooth_width = PI * mean_dia(opt) / (teeth(opt) as f64);
+    (actual_tooth_width / 2.0) / (opt.tool_inc_angle.to_radians().tan())
+}
+
+/// Number of passes down to the full tooth depth
+fn passes(opt: &Opt) -> usize {
+    (tooth_depth(opt) / opt.max_stepdown).ceil() as usize
+}
+
+/// Whether any of the --start options resume the job part way through
+fn resuming(opt: &Opt) -> bool {
+    opt.start_pass > 0 || opt.start_tooth > 0 || opt.start_right_hand
+}
+
 /// Cut the teeth. The overall strategy is to cut all teeth at each depth, before moving on to the next depth.
 ///  This minimizes the amount of burr that is raised on the edge of the teeth, and seems to give a cleaner
-///  edge when we get to final depth.
+///  edge when we get to final depth. A diamond knurl does this twice, once for each hand of spiral.
 fn cut_knurls(opt: &Opt, file: &mut dyn Write) -> Result<()> {
-    let circumference = PI * opt.dia;
-    let teeth = (circumference / opt.pitch).floor() as usize;
-    println!(
+    let circumference = PI * mean_dia(opt);
+    let teeth = teeth(opt);
+    eprintln!(
         "Requested {} teeth, actually cutting {}",
         circumference / opt.pitch,
         teeth
     );
-    // A rotation per tooth
-    let a_step = 360.0 / teeth as f64;
-
-    let stock_top_z = opt.dia / 2.0;
-    let actual_tooth_width = (PI * opt.dia) / (teeth as f64);
-    let tooth_depth = (actual_tooth_width / 2.0) / (opt.tool_inc_angle.to_radians().tan());
+    // Where each tooth starts around A
+    let angles = index_angles(teeth as u32, opt.spacing_variation, opt.seed);
+
+    let actual_tooth_width = circumference / (teeth as f64);
+    let tooth_depth = tooth_depth(opt);
+    // Rounding to a whole number of teeth stretches the pitch, so report what we'll really get
+    let u = opt.common.units;
+    eprintln!(
+        "Actual pitch {:.4}{u} ({:.2} TPI), tooth depth {:.4}{u}, tooth included angle {} degrees",
+        actual_tooth_width,
+        25.4 / u.to_mm(actual_tooth_width),
+        tooth_depth,
+        opt.tool_inc_angle,
+        u = u.abbrev()
+    );
+    if opt.pattern == Pattern::Diamond {
+        eprintln!(
+            "Left and right-hand teeth cross at {} degrees",
+            2.0 * spiral_angle(opt)
+        );
+    }
 
-    let passes = (tooth_depth / opt.max_stepdown).ceil() as usize;
+    let passes = passes(opt);
     let actual_stepdown = tooth_depth / passes as f64;
 
-    for pass in 0..passes {
-        gcode_comment(file, &format!("Pass {} of {}", pass, passes))?;
-        let cut_depth = actual_stepdown * (pass + 1) as f64;
-        for tooth in 0..teeth {
-            gcode_comment(file, &format!("Tooth {} of {}", tooth, teeth))?;
-            cut_tooth(opt, file, a_step * tooth as f64, stock_top_z, cut_depth)?;
+    // Where a resumed job picks up, as (spiral, pass, tooth), with everything before it already cut
+    let start = (
+        usize::from(opt.start_right_hand),
+        opt.start_pass,
+        opt.start_tooth,
+    );
+    for (spiral, &reverse_spiral) in opt.pattern.spirals().iter().enumerate() {
+        if spiral < start.0 {
+            continue;
+        }
+        if opt.pattern == Pattern::Diamond {
+            gcode_comment(
+                file,
+                if reverse_spiral {
+                    "Left-hand spiral teeth"
+                } else {
+                    "Right-hand spiral teeth"
+                },
+                opt.common.dialect,
+            )?;
+        }
+        for pass in 0..passes {
+            if (spiral, pass) < (start.0, start.1) {
+                continue;
+            }
+            gcode_comment(
+                file,
+                &format!("Pass {} of {}", pass, passes),
+                opt.common.dialect,
+            )?;
+            let cut_depth = actual_stepdown * (pass + 1) as f64;
+            for (tooth, &a_start) in angles.iter().enumerate() {
+                if (spiral, pass, tooth) < start {
+                    continue;
+                }
+                gcode_comment(
+                    file,
+                    &format!("Tooth {} of {}", tooth, teeth),
+                    opt.common.dialect,
+                )?;
+                cut_tooth(opt, file, a_start, cut_depth, reverse_spiral)?;
+            }
         }
     }
 
@@ -181,29 +380,141 @@ fn cut_knurls(opt: &Opt, file: &mut dyn Write) -> Result<()> {
 }
 
 fn main() -> Result<()> {
-    let opt = Opt::from_args();
+    let mut opt: Opt = cli::from_args();
+    if !(0.0..1.0).contains(&opt.spacing_variation) {
+        clap::Error::with_description(
+            "--spacing-variation must be at least 0, and less than 1",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt.start_pass >= passes(&opt) || opt.start_tooth >= teeth(&opt) {
+        clap::Error::with_description(
+            &format!(
+                "--start-pass must be less than {}, and --start-tooth less than {}",
+                passes(&opt),
+                teeth(&opt)
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt.start_right_hand && opt.pattern != Pattern::Diamond {
+        clap::Error::with_description(
+            "--start-right-hand is only for a diamond --pattern, which cuts left-hand teeth first",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
     help_text(&opt);
-    let mut file = BufWriter::new(
-        OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&opt.output)?,
-    );
-
-    preamble(
-        &opt.name,
+    let tool = opt.common.tool(
         opt.tool,
-        &format!(
+        format!(
             "T{} {} degree chamfer mill or engraver",
             opt.tool, opt.tool_inc_angle
         ),
-        opt.rpm,
-        opt.coolant,
+        opt.common.rpm(opt.rpm, None),
+    );
+    opt.feed = opt.common.feed(opt.feed, tool.rpm, None, 1);
+    let mut file = opt.common.open_output(&tool)?;
+    let u = opt.common.units;
+    let mut notes = vec![];
+    if opt.spacing_variation > 0.0 {
+        notes.push(format!(
+            "Unequal spacing, varied by up to {}% with seed {}",
+            opt.spacing_variation * 100.0,
+            opt.seed
+        ));
+    }
+    if resuming(&opt) {
+        notes.push(format!(
+            "Resuming at {}pass {} of {}, tooth {} of {}: leave the work in place, and don't re-zero any axis",
+            if opt.start_right_hand {
+                "the right-hand teeth, "
+            } else {
+                ""
+            },
+            opt.start_pass,
+            passes(&opt),
+            opt.start_tooth,
+            teeth(&opt)
+        ));
+    }
+    job_header(
         &mut file,
+        &JobInfo {
+            stock: match opt.dia_end {
+                Some(dia_end) => format!(
+                    "OD {}{u} at the right face, tapering to {}{u} {}{u} along",
+                    opt.dia,
+                    dia_end,
+                    opt.len,
+                    u = u.abbrev()
+                ),
+                None => format!(
+                    "OD {}{u}, at least {}{u} long",
+                    opt.dia,
+                    opt.len,
+                    u = u.abbrev()
+                ),
+            },
+            datum: "center of right face of stock".to_string(),
+            tools: vec![(&tool, opt.feed)],
+            notes,
+            units: u,
+            dialect: opt.common.dialect,
+        },
     )?;
 
     cut_knurls(&opt, &mut file)?;
-    trailer(&mut file)?;
+    trailer(&mut file, opt.common.home(), opt.common.dialect)?;
 
-    file.flush()
+    opt.common.finish(
+        file,
+        Some(Stock::Rect {
+            min: (
+                -opt.len,
+                -dia_at(&opt, 0.0).max(dia_at(&opt, opt.len)) / 2.0,
+            ),
+            max: (0.0, dia_at(&opt, 0.0).max(dia_at(&opt, opt.len)) / 2.0),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn knurls(args: &[&str]) -> String {
+        let opt = Opt::from_iter(
+            ["knurl_gen", "--dia=20", "--output=knurl.nc"]
+                .iter()
+                .chain(args),
+        );
+        let mut file = vec![];
+        cut_knurls(&opt, &mut file).unwrap();
+        String::from_utf8(file).unwrap()
+    }
+
+    #[test]
+    fn start_options_leave_out_what_was_cut_before() {
+        // The resumed pass gets its heading again, and then its teeth from the --start-tooth on are cut just as they
+        // are in the whole job
+        let full = knurls(&[]);
+        let pass = full.find("(Pass 1 of ").unwrap();
+        let heading = &full[pass..pass + full[pass..].find('\n').unwrap() + 1];
+        let from = pass + full[pass..].find("(Tooth 3 of ").unwrap();
+        let resumed = knurls(&["--start-pass=1", "--start-tooth=3"]);
+        assert_eq!(resumed, format!("{}{}", heading, &full[from..]));
+        assert!(!resumed.contains("(Pass 0 of "));
+
+        // A diamond resumed in its right-hand teeth leaves out every left-hand one
+        let full = knurls(&["--pattern=diamond"]);
+        let from = full.find("(Right-hand spiral teeth)").unwrap();
+        assert!(from > 0);
+        assert_eq!(
+            knurls(&["--pattern=diamond", "--start-right-hand"]),
+            full[from..]
+        );
+    }
 }
@@ -0,0 +1,167 @@
+//! G-Code generator for cutting timing belt pulleys on a 4th axis, using a ball end mill.
+//! Each groove is turned to the top (+Z), roughed out in layers of passes along X, then finished with passes that
+//! follow the belt's tooth profile down each flank.
+use gcode::cli::{self, CommonOpts};
+use gcode::profile::{self, BallCut};
+use gcode::pulleys::{self, Belt};
+use gcode::svg::Stock;
+use gcode::{a, g0, gcode_comment, job_header, trailer, JobInfo};
+use std::io::{Result, Write};
+use structopt::{clap, StructOpt};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "pulley_gen", about = "A timing belt pulley generator")]
+struct Opt {
+    /// Belt profile: gt2 (2mm pitch) or htd5 (5mm pitch)
+    #[structopt(short, long, default_value = "gt2")]
+    belt: Belt,
+
+    /// Number of pulley teeth
+    #[structopt(short, long)]
+    teeth: u32,
+
+    /// Diameter of the ball end mill. It has to fit in the bottom of the groove.
+    #[structopt(long)]
+    tool_dia: f64,
+
+    /// Tool RPM
+    #[structopt(long, default_value = "8000")]
+    rpm: f64,
+
+    /// Feed rate, in units/min
+    #[structopt(long, default_value = "100")]
+    feed: f64,
+
+    /// Tool number for the cut
+    #[structopt(long, default_value = "1")]
+    tool: u32,
+
+    /// Width of the pulley to cut
+    #[structopt(short, long)]
+    width: f64,
+
+    /// Max depth to cut, per roughing layer
+    #[structopt(long, default_value = "0.25")]
+    max_depth: f64,
+
+    /// Distance between finishing passes, along the tooth profile [default: 0.1mm]
+    #[structopt(long)]
+    finish_step: Option<f64>,
+
+    #[structopt(flatten)]
+    common: CommonOpts,
+}
+
+/// Outside diameter of the blank, in the job's units
+fn stock_dia(opt: &Opt) -> f64 {
+    let u = opt.common.units;
+    u.from_mm(pulleys::outside_diameter(opt.belt, opt.teeth))
+}
+
+/// Path of the ball's center along the +Y flank of a groove, in the job's units
+fn ball_path(opt: &Opt) -> std::result::Result<Vec<(f64, f64)>, String> {
+    let u = opt.common.units;
+    let step = opt.finish_step.unwrap_or_else(|| u.from_mm(0.1));
+    let flank = pulleys::gap_flank(opt.belt, opt.teeth, u.to_mm(step))?;
+    Ok(profile::ball_path(&flank, opt.tool_dia, u))
+}
+
+fn cut_pulley(opt: &Opt, file: &mut dyn Write, path: &[(f64, f64)]) -> Result<()> {
+    let gap_angle = 360.0 / opt.teeth as f64;
+    let cut = BallCut {
+        tool_dia: opt.tool_dia,
+        width: opt.width,
+        max_depth: opt.max_depth,
+        feed: opt.feed,
+        stock_dia: stock_dia(opt),
+        units: opt.common.units,
+        dialect: opt.common.dialect,
+    };
+
+    for i in 0..opt.teeth {
+        gcode_comment(
+            file,
+            &format!("Groove {} of {}", i + 1, opt.teeth),
+            opt.common.dialect,
+        )?;
+        g0(file, a(i as f64 * gap_angle).units(opt.common.units))?;
+        profile::cut_gap(file, &cut, path)?;
+    }
+
+    Ok(())
+}
+
+fn help_text(opt: &Opt) {
+    let u = opt.common.units;
+    eprintln!(
+        "Before cut:
+        - Create stock with OD {:.4}{u}
+        - Set home to center of right face of stock
+        - Load a {}{u} ball end mill, with its length measured to the tip",
+        stock_dia(opt),
+        opt.tool_dia,
+        u = u.abbrev()
+    );
+}
+
+fn main() -> Result<()> {
+    let mut opt: Opt = cli::from_args();
+    let u = opt.common.units;
+    if opt.tool_dia > u.from_mm(2.0 * opt.belt.groove_radius()) {
+        clap::Error::with_description(
+            "--tool-dia is too big to fit in the bottom of each groove",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    let path = match ball_path(&opt) {
+        Ok(path) => path,
+        Err(e) => clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit(),
+    };
+    help_text(&opt);
+    let tool = opt.common.tool(
+        opt.tool,
+        format!(
+            "T{} D={}{} - ball end mill",
+            opt.tool,
+            opt.tool_dia,
+            u.abbrev()
+        ),
+        opt.common.rpm(opt.rpm, Some(opt.tool_dia)),
+    );
+    opt.feed = opt.common.feed(opt.feed, tool.rpm, Some(opt.tool_dia), 2);
+    let mut file = opt.common.open_output(&tool)?;
+    job_header(
+        &mut file,
+        &JobInfo {
+            stock: format!(
+                "OD {:.4}{u}, at least {}{u} long",
+                stock_dia(&opt),
+                opt.width,
+                u = u.abbrev()
+            ),
+            datum: "center of right face of stock".to_string(),
+            tools: vec![(&tool, opt.feed)],
+            notes: vec![format!(
+                "{} tooth {} pulley, pitch diameter {:.4}{u}, outside diameter {:.4}{u}",
+                opt.teeth,
+                opt.belt.name(),
+                u.from_mm(pulleys::pitch_diameter(opt.belt, opt.teeth)),
+                stock_dia(&opt),
+                u = u.abbrev()
+            )],
+            units: u,
+            dialect: opt.common.dialect,
+        },
+    )?;
+    cut_pulley(&opt, &mut file, &path)?;
+    trailer(&mut file, opt.common.home(), opt.common.dialect)?;
+
+    opt.common.finish(
+        file,
+        Some(Stock::Rect {
+            min: (-opt.width, -stock_dia(&opt) / 2.0),
+            max: (0.0, stock_dia(&opt) / 2.0),
+        }),
+    )
+}
@@ -0,0 +1,281 @@
+//! G-Code generator for cutting straight racks, using the same involute gear cutter as gear_gen.
+//! The cutter runs along X, just like it does for a gear, but instead of turning the A axis between teeth the rack
+//! steps down the Z axis, so the rack blank is held with its length vertical.
+use gcode::cli::{self, CommonOpts};
+use gcode::gears;
+use gcode::lint::LintKind;
+use gcode::{g0, g1, g2r, gcode_comment, job_header, trailer, x, xf, xy, xyrf, y, yf, z, JobInfo};
+use std::io::{Result, Write};
+use structopt::{clap, StructOpt};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "rack_gen", about = "A simple straight rack generator")]
+struct Opt {
+    /// Rack module, must match cutter module
+    #[structopt(short, long, default_value = "1")]
+    module: f64,
+
+    /// Length of rack to cut. As many whole teeth as fit are cut.
+    #[structopt(short, long)]
+    length: f64,
+
+    /// Diameter of cutter
+    #[structopt(long, default_value = "50")]
+    cutter_dia: f64,
+
+    /// Cutter RPM
+    #[structopt(long, default_value = "650")]
+    rpm: f64,
+
+    /// Feed rate, in units/min
+    #[structopt(long, default_value = "60")]
+    feed: f64,
+
+    /// Tool number for the cut
+    #[structopt(long, default_value = "1")]
+    tool: u32,
+
+    /// Width of the rack's tooth face (the length of each tooth)
+    #[structopt(short, long)]
+    width: f64,
+
+    /// Max depth to cut
+    #[structopt(long, default_value = "0.5")]
+    max_depth: f64,
+
+    /// Radius of the arc the cutter eases out of the work on at the end of each pass [default: 2mm].
+    /// Limited to the clearance, and to how far the cutter reaches ahead of its center. 0 feeds straight out.
+    #[structopt(long)]
+    feed_out_radius: Option<f64>,
+
+    #[structopt(flatten)]
+    common: CommonOpts,
+}
+
+/// Distance between teeth, in the job's units
+fn pitch(opt: &Opt) -> f64 {
+    opt.common.units.from_mm(gears::rack_pitch(opt.module))
+}
+
+/// Number of teeth that fit in the rack
+fn teeth(opt: &Opt) -> u32 {
+    gears::rack_teeth(opt.module, opt.common.units.to_mm(opt.length))
+}
+
+/// Z position of the center of tooth space `i`. The first space is half a pitch down from the top of the blank, so the
+/// rack starts and ends with a half-width tooth.
+fn space_z(opt: &Opt, i: u32) -> f64 {
+    -(i as f64 + 0.5) * pitch(opt)
+}
+
+fn pass_at_depth(opt: &Opt, file: &mut dyn Write, depth: f64, z_pos: f64) -> Result<()> {
+    let u = opt.common.units;
+    // Clearance (4mm) away from the stock where we move at feed rate
+    let clearance = u.from_mm(4.0);
+
+    let x_clearance = gears::cutter_start_x(opt.cutter_dia, depth, clearance);
+
+    let y_pos = opt.cutter_dia / 2.0 // Cutter radius, out from the tooth face
+        - depth; // Minus depth of cut
+    gcode_comment(
+        file,
+        &format!("Pass at depth {}{}", depth, u.abbrev()),
+        opt.common.dialect,
+    )?;
+    // Rapid to our starting point, to the right of the stock, then down to the tooth space
+    g0(file, xy(x_clearance, y_pos).units(u))?;
+    g0(file, z(z_pos).units(u))?;
+
+    // The feed-out arc starts before the end of the stock, but as it's smaller than the reach of the cutter ahead of
+    // its center, the cutting edge still gets all the way to the end at full depth
+    let feed_out_radius = opt
+        .feed_out_radius
+        .unwrap_or_else(|| u.from_mm(2.0))
+        .min(clearance)
+        .min(gears::cutter_half_chord(opt.cutter_dia, depth));
+    let x_end = -opt.width + feed_out_radius;
+
+    // Feed into the stock, cutting as we go
+    g1(file, xf(x_end, opt.feed).units(u))?;
+
+    // Ease out of the stock on a tangent arc, then feed the rest of the way out moving in Y
+    if feed_out_radius > 0.0 {
+        g2r(
+            file,
+            (x_end, y_pos),
+            xyrf(
+                -opt.width,
+                y_pos + feed_out_radius,
+                feed_out_radius,
+                opt.feed,
+            )
+            .units(u),
+        )?;
+    }
+    g1(file, yf(y_pos + clearance, opt.feed).units(u))?;
+    // Then rapid a little bit straight out before we do the cross move
+    g0(file, y(y_pos + clearance + u.from_mm(10.0)).units(u))?;
+
+    // Go back to where we started, in two moves, first X then Y to make sure we have enough clearance
+    g0(file, x(x_clearance).units(u))?;
+    g0(file, y(y_pos).units(u))?;
+
+    Ok(())
+}
+
+fn cut_space(opt: &Opt, file: &mut dyn Write, z_pos: f64) -> Result<()> {
+    let total_depth = opt.common.units.from_mm(gears::total_depth(opt.module));
+
+    let mut depth = 0.0;
+
+    // Take passes until we've consumed the whole depth.
+    while depth < total_depth {
+        let remaining = total_depth - depth;
+        if remaining > 2.0 * opt.max_depth {
+            // Make max_depth passes until we're within 2*max_depth of the final depth
+            depth += opt.max_depth;
+            pass_at_depth(opt, file, depth, z_pos)?;
+        } else {
+            // Then finish off with two equal passes of the remaining depth
+            depth += remaining / 2.0;
+            pass_at_depth(opt, file, depth, z_pos)?;
+            depth = total_depth;
+            pass_at_depth(opt, file, total_depth, z_pos)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn cut_rack(opt: &Opt, file: &mut dyn Write) -> Result<()> {
+    let teeth = teeth(opt);
+
+    for i in 0..teeth {
+        gcode_comment(
+            file,
+            &format!("Tooth space {} of {}", i + 1, teeth),
+            opt.common.dialect,
+        )?;
+        cut_space(opt, file, space_z(opt, i))?;
+    }
+
+    Ok(())
+}
+
+fn help_text(opt: &Opt) {
+    eprintln!(
+        "Before cut:
+        - Create a rack blank at least {:.4}{u} long, with a {}{u} wide tooth face
+        - Clamp it with its length vertical (along Z), and the tooth face towards +Y
+        - Set home to the top right corner of the tooth face",
+        teeth(opt) as f64 * pitch(opt),
+        opt.width,
+        u = opt.common.units.abbrev()
+    );
+}
+
+fn main() -> Result<()> {
+    let mut opt: Opt = cli::from_args();
+    if teeth(&opt) == 0 {
+        clap::Error::with_description(
+            &format!(
+                "A {}{u} rack is too short for a single tooth, which needs {:.4}{u}",
+                opt.length,
+                pitch(&opt),
+                u = opt.common.units.abbrev()
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    help_text(&opt);
+    let tool = opt.common.tool(
+        opt.tool,
+        format!(
+            "T{} D={}{} - gear mill",
+            opt.tool,
+            opt.cutter_dia,
+            opt.common.units.abbrev()
+        ),
+        opt.common.rpm(opt.rpm, Some(opt.cutter_dia)),
+    );
+    opt.feed = opt
+        .common
+        .feed(opt.feed, tool.rpm, Some(opt.cutter_dia), 12);
+    let mut file = opt.common.open_output(&tool)?;
+    // The blank stands on end, so the cutter rapids down Z to each tooth space, clear of the end of the blank in X
+    file.allow(LintKind::RapidBelowZ0);
+    let u = opt.common.units;
+    job_header(
+        &mut file,
+        &JobInfo {
+            stock: format!(
+                "rack blank at least {:.4}{u} long, with a {}{u} wide tooth face, clamped with its length along Z and the tooth face towards +Y",
+                teeth(&opt) as f64 * pitch(&opt),
+                opt.width,
+                u = u.abbrev()
+            ),
+            datum: "top right corner of the tooth face".to_string(),
+            tools: vec![(&tool, opt.feed)],
+            notes: vec![format!(
+                "Rack of {} teeth, {:.4}{u} long at {:.4}{u} pitch",
+                teeth(&opt),
+                teeth(&opt) as f64 * pitch(&opt),
+                pitch(&opt),
+                u = u.abbrev()
+            )],
+            units: u,
+            dialect: opt.common.dialect,
+        },
+    )?;
+    cut_rack(&opt, &mut file)?;
+    trailer(&mut file, opt.common.home(), opt.common.dialect)?;
+
+    opt.common.finish(file, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(args: &[&str]) -> Opt {
+        let common = ["rack_gen", "--width=10", "--output=rack.nc"];
+        Opt::from_iter(common.iter().chain(args))
+    }
+
+    /// The Z of each tooth space the program cuts, in order
+    fn space_zs(opt: &Opt) -> Vec<f64> {
+        let mut file = vec![];
+        cut_rack(opt, &mut file).unwrap();
+        let mut zs: Vec<f64> = String::from_utf8(file)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("G0 Z"))
+            .map(|z| z.parse().unwrap())
+            .collect();
+        zs.dedup();
+        zs
+    }
+
+    #[test]
+    fn tooth_spaces_are_a_pitch_apart() {
+        for (args, pitch) in [
+            (&["--length=50"][..], std::f64::consts::PI),
+            (&["--length=50", "--module=2"], 2.0 * std::f64::consts::PI),
+            (&["--length=2", "--units=imperial"], 0.12368),
+        ] {
+            let opt = opt(args);
+            let zs = space_zs(&opt);
+            assert_eq!(zs.len() as u32, teeth(&opt));
+            // Half a pitch down to the first space, then a pitch between each of them
+            assert!((zs[0] + pitch / 2.0).abs() < 1e-4, "{:?}", zs);
+            for pair in zs.windows(2) {
+                assert!((pair[0] - pair[1] - pitch).abs() < 1e-4, "{:?}", zs);
+            }
+        }
+        // As many whole teeth as fit in the length
+        assert_eq!(teeth(&opt(&["--length=50"])), 15);
+        assert_eq!(teeth(&opt(&["--length=50", "--module=2"])), 7);
+        assert_eq!(teeth(&opt(&["--length=3"])), 0);
+    }
+}
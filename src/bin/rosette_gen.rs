@@ -0,0 +1,223 @@
+//! G-Code generator for engraving a rose engine rosette on a flat dial, with an engraving tool.
+//! The pen's path is an epicycloid or hypocycloid round the center of the dial, engraved in one pass of straight moves,
+//! and only where it's inside the dial.
+use gcode::cli::{self, CommonOpts};
+use gcode::patterns::{self, Region, Rosette, RosetteKind};
+use gcode::svg::Stock;
+use gcode::{job_header, trailer, JobInfo};
+use std::io::{Result, Write};
+use structopt::{clap, StructOpt};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "rosette_gen", about = "A rose engine rosette generator")]
+struct Opt {
+    /// Radius of the path of the center of the pen's circle, round the center of the dial
+    #[structopt(long)]
+    radius: f64,
+
+    /// Radius of the pen's circle, which sets how far in and out the lobes go
+    #[structopt(long)]
+    amplitude: f64,
+
+    /// Number of lobes. With none the rosette is a plain circle.
+    #[structopt(long)]
+    lobes: u32,
+
+    /// Turn the pen's circle against the pattern, for a hypocycloid with pointed lobes, rather than with it for an
+    /// epicycloid with rounded ones
+    #[structopt(long)]
+    hypocycloid: bool,
+
+    /// Angle (degrees) the pen starts round its circle from +X, which turns the lobes round the dial
+    #[structopt(long, default_value = "0")]
+    phase: f64,
+
+    /// Number of straight moves all the way round
+    #[structopt(long, default_value = "720")]
+    resolution: u32,
+
+    /// Diameter of the dial. Only the parts of the rosette inside it are engraved. Leave unset to engrave all of it.
+    #[structopt(long)]
+    dial_dia: Option<f64>,
+
+    /// Depth of the engraved line [default: 0.1mm]
+    #[structopt(long)]
+    depth: Option<f64>,
+
+    /// Tool RPM
+    #[structopt(long, default_value = "10000")]
+    rpm: f64,
+
+    /// Feed rate, in units/min
+    #[structopt(long, default_value = "100")]
+    feed: f64,
+
+    /// Tool number for the cut
+    #[structopt(long, default_value = "1")]
+    tool: u32,
+
+    /// Diameter of the engraving tool, to work out its speeds from with --surface-speed or --material
+    #[structopt(long)]
+    tool_dia: Option<f64>,
+
+    #[structopt(flatten)]
+    common: CommonOpts,
+}
+
+/// The rosette the job engraves, centered on the datum and clipped to the dial
+fn rosette(opt: &Opt) -> Rosette<'static> {
+    let u = opt.common.units;
+    Rosette {
+        center: (0.0, 0.0),
+        radius: opt.radius,
+        amplitude: opt.amplitude,
+        lobes: opt.lobes,
+        kind: if opt.hypocycloid {
+            RosetteKind::Hypocycloid
+        } else {
+            RosetteKind::Epicycloid
+        },
+        phase: opt.phase,
+        resolution: opt.resolution,
+        clip: opt.dial_dia.map(|dia| Region::Circle {
+            center: (0.0, 0.0),
+            radius: dia / 2.0,
+        }),
+        z_cut: -opt.depth.unwrap_or_else(|| u.from_mm(0.1)),
+        z_safe: u.from_mm(4.0),
+        feed: opt.feed,
+        units: u,
+    }
+}
+
+fn cut_rosette(opt: &Opt, file: &mut dyn Write) -> Result<()> {
+    patterns::rosette(file, &rosette(opt))
+}
+
+fn help_text(opt: &Opt) {
+    let u = opt.common.units;
+    eprintln!(
+        "Before cut:
+        - Set X0 Y0 at the center of the dial, and Z0 on its face{}
+        - Load the engraving tool",
+        match opt.dial_dia {
+            Some(dia) => format!(", which is {}{} across", dia, u.abbrev()),
+            None => String::new(),
+        }
+    );
+}
+
+fn main() -> Result<()> {
+    let mut opt: Opt = cli::from_args();
+    if opt.resolution < 3 {
+        clap::Error::with_description(
+            "--resolution must be at least 3",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt.depth.is_some_and(|depth| depth <= 0.0) {
+        clap::Error::with_description("--depth must be more than 0", clap::ErrorKind::InvalidValue)
+            .exit();
+    }
+    let u = opt.common.units;
+    help_text(&opt);
+    let tool = opt.common.tool(
+        opt.tool,
+        format!("T{} engraving tool", opt.tool),
+        opt.common.rpm(opt.rpm, opt.tool_dia),
+    );
+    opt.feed = opt.common.feed(opt.feed, tool.rpm, opt.tool_dia, 1);
+    let mut file = opt.common.open_output(&tool)?;
+    job_header(
+        &mut file,
+        &JobInfo {
+            stock: match opt.dial_dia {
+                Some(dia) => format!("dial {}{} across", dia, u.abbrev()),
+                None => "flat dial".to_string(),
+            },
+            datum: "center of the dial, with Z0 on its face".to_string(),
+            tools: vec![(&tool, opt.feed)],
+            notes: vec![format!(
+                "{} lobe {} rosette, {}{u} round with a {}{u} pen circle",
+                opt.lobes,
+                if opt.hypocycloid {
+                    "hypocycloid"
+                } else {
+                    "epicycloid"
+                },
+                opt.radius,
+                opt.amplitude,
+                u = u.abbrev()
+            )],
+            units: u,
+            dialect: opt.common.dialect,
+        },
+    )?;
+    cut_rosette(&opt, &mut file)?;
+    trailer(&mut file, opt.common.home(), opt.common.dialect)?;
+
+    opt.common.finish(
+        file,
+        opt.dial_dia.map(|dia| Stock::Circle {
+            center: (0.0, 0.0),
+            radius: dia / 2.0,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(args: &[&str]) -> Opt {
+        let common = [
+            "rosette_gen",
+            "--radius=10",
+            "--amplitude=2",
+            "--output=rosette.nc",
+        ];
+        Opt::from_iter(common.iter().chain(args))
+    }
+
+    /// The X and Y of every cutting move
+    fn cuts(opt: &Opt) -> Vec<(f64, f64)> {
+        let mut file = vec![];
+        cut_rosette(opt, &mut file).unwrap();
+        String::from_utf8(file)
+            .unwrap()
+            .lines()
+            .filter(|l| l.starts_with("G1 X"))
+            .map(|l| (word(l, 'X').unwrap(), word(l, 'Y').unwrap()))
+            .collect()
+    }
+
+    /// The value of the `letter` word on a `line` of G code
+    fn word(line: &str, letter: char) -> Option<f64> {
+        line.split_whitespace()
+            .find_map(|w| w.strip_prefix(letter))
+            .and_then(|v| v.parse().ok())
+    }
+
+    #[test]
+    fn no_lobes_is_a_circle() {
+        let moves = cuts(&opt(&["--lobes=0", "--phase=90"]));
+        assert_eq!(moves.len(), 720);
+        for (x, y) in moves {
+            assert!((x.hypot(y) - 104f64.sqrt()).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn clipped_to_the_dial() {
+        let whole = cuts(&opt(&["--lobes=5"]));
+        assert!(whole.iter().any(|&(x, y)| x.hypot(y) > 11.9));
+
+        let clipped = cuts(&opt(&["--lobes=5", "--dial-dia=22"]));
+        assert!(clipped.len() < whole.len());
+        assert!(clipped.iter().all(|&(x, y)| x.hypot(y) < 11.0 + 1e-3));
+        assert!(clipped
+            .iter()
+            .any(|&(x, y)| (x.hypot(y) - 11.0).abs() < 1e-3));
+    }
+}
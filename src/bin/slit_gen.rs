@@ -5,99 +5,188 @@
 //!
 //! The default speeds and feeds here work well on my Tormach 440, HSS saw, and into steel. "By the book" this is too much speed and too little feed, but the Tormach struggles
 //!   with torque at the bottom of its RPM range, and so this approach is needed.
-use gcode::{g0, g1, gcode_comment, preamble, trailer, x, xf, xyz, xyzf};
-use std::f64::consts::PI;
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Result, Write};
-use std::path::PathBuf;
-use structopt::StructOpt;
+//!
+//! With --trochoidal, the slit is cut with an end mill instead, as a slot in light looping bites, for slits too wide or
+//! too shallow to be worth setting up the saw for.
+use gcode::cli::{self, CommonOpts};
+use gcode::patterns::{trochoidal_slot, TrochoidalSlot};
+use gcode::{
+    chip_load_to_feed, g0, g1, gcode_comment, job_header, surface_speed_to_rpm, tool_change,
+    touch_off_z, trailer, x, xf, xy, xyz, xyzf, z, Coolant, JobInfo, SpindleDir, Tool,
+};
+use std::io::{Result, Write};
+use structopt::{clap, StructOpt};
+
+/// Surface speed of the saw, in meters/minute, unless there's a --surface-speed
+const SURFACE_SPEED: f64 = 120.0;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "slit_gen", about = "Generates tool path for a slitting saw")]
 struct Opt {
-    /// Tool surface speed (in meters/minute)
-    #[structopt(long, default_value = "120")]
-    speed: f64,
-
-    /// Feed rate per tooth, in mm/tooth
+    /// Feed rate per tooth, in units/tooth
     #[structopt(long, default_value = "0.001")]
     feed_per_tooth: f64,
 
-    /// Tool teeth
-    #[structopt(long, default_value = "30")]
-    tool_teeth: usize,
-
-    /// Name for the job
-    #[structopt(short, long)]
-    name: Option<String>,
-
     /// Tool number for the cut
     #[structopt(long, default_value = "18")]
     tool: u32,
 
-    /// Tool diameter, in mm
+    /// Tool diameter, of the saw or, with --trochoidal, the end mill
     #[structopt(long, default_value = "76.2")]
     tool_dia: f64,
 
-    /// Tool thickness, in mm
+    /// Tool thickness, of the saw
     #[structopt(long, default_value = "1.55")]
     tool_thick: f64,
 
-    /// Height of the cut, along -Z in mm, for making multiple passes with the saw. Leave unset for a single cut.
+    /// Cut the slit with an end mill, as a trochoidal slot --width wide, rather than with the saw. The slot is cut in
+    /// layers no deeper than the end mill's diameter, down to --height.
+    #[structopt(long, requires_all = &["width", "height"])]
+    trochoidal: bool,
+
+    /// Width of the slit, along Y, for --trochoidal. It must be wider than the end mill.
+    #[structopt(long)]
+    width: Option<f64>,
+
+    /// Distance each loop of a --trochoidal cut advances along the slit [default: a tenth of the tool diameter]
+    #[structopt(long)]
+    step: Option<f64>,
+
+    /// Height of the cut, along -Z, for making multiple passes with the saw. Leave unset for a single cut.
     #[structopt(long)]
     height: Option<f64>,
 
-    /// Depth of the cut, along the X axis, mm
+    /// Depth of the cut, along the X axis
     #[structopt(long)]
     depth: f64,
 
-    /// Output file for the resulting G code
-    #[structopt(short, long, parse(from_os_str))]
-    output: PathBuf,
+    /// Number of slits to cut, in a row along +Y, for a comb or a set of fins
+    #[structopt(long, default_value = "1")]
+    count: u32,
 
+    /// Distance between the slits, center to center along Y
     #[structopt(long)]
-    coolant: bool,
+    spacing: Option<f64>,
+
+    /// Touch off Z with the probe in this tool number before cutting, rather than setting Z by hand.
+    /// The probed surface becomes Z0, so it must be level with the top of the cut.
+    #[structopt(long)]
+    probe_tool: Option<u32>,
+
+    /// X position to touch off at
+    #[structopt(long, default_value = "0")]
+    probe_x: f64,
+
+    /// Y position to touch off at
+    #[structopt(long, default_value = "0")]
+    probe_y: f64,
+
+    /// Furthest distance to probe down in Z before giving up
+    #[structopt(long, default_value = "100")]
+    probe_distance: f64,
+
+    /// Probing feed rate, in units/min
+    #[structopt(long, default_value = "50")]
+    probe_feed: f64,
+
+    #[structopt(flatten)]
+    common: CommonOpts,
 }
 
-fn help_text() {
-    println!(
-        "Before cut:\n
+fn help_text(opt: &Opt) {
+    if opt.trochoidal {
+        eprintln!(
+            "Before cut:\n
+            Set Z0 on the top of the work.
+            Set x, y, and z home along -X from cut"
+        )
+    } else {
+        eprintln!(
+            "Before cut:\n
             Align top of blade with top of cut.
             Set x, y, and z home along -X from cut"
-    )
+        )
+    }
+}
+
+/// Number of teeth on the tool, which are 30 on a saw or 2 on an end mill unless there's a --tool-teeth
+fn tool_teeth(opt: &Opt) -> u32 {
+    opt.common
+        .tool_teeth
+        .unwrap_or(if opt.trochoidal { 2 } else { 30 })
+}
+
+/// How wide each slit is along Y, the thickness of the saw or the --width of a trochoidal slot
+fn slit_width(opt: &Opt) -> f64 {
+    if opt.trochoidal {
+        opt.width.unwrap_or(0.0)
+    } else {
+        opt.tool_thick
+    }
 }
 
-fn make_cut_pass(opt: &Opt, file: &mut dyn Write, z: f64, rpm: f64) -> Result<()> {
-    let feed = opt.feed_per_tooth * rpm * opt.tool_teeth as f64;
-    let z_clear = 4.0;
+fn make_cut_pass(opt: &Opt, file: &mut dyn Write, y: f64, z: f64, rpm: f64) -> Result<()> {
+    let u = opt.common.units;
+    let feed = chip_load_to_feed(opt.feed_per_tooth, rpm, tool_teeth(opt));
+    let z_clear = u.from_mm(4.0);
 
     assert!(z <= 0.0);
 
-    gcode_comment(file, &format!("Making pass at z={}", z))?;
-    // Rapid to our home
-    g0(file, xyz(0.0, 0.0, z + z_clear))?;
+    gcode_comment(
+        file,
+        &format!("Making pass at z={}{}", z, u.abbrev()),
+        opt.common.dialect,
+    )?;
+    // Rapid to our home, or alongside it for the later slits
+    g0(file, xyz(0.0, y, z + z_clear).units(u))?;
     // Feed in slowly along Z, to give us an opportunity to panic
-    g1(file, xyzf(0.0, 0.0, z, feed))?;
+    g1(file, xyzf(0.0, y, z, feed).units(u))?;
     // Feed in along the X axis
-    g1(file, xf(opt.depth, feed))?;
+    g1(file, xf(opt.depth, feed).units(u))?;
     // Feed out along the X axis a little bit at the feed rate
-    g1(file, xf(opt.depth - 1.0, feed))?;
+    g1(file, xf(opt.depth - u.from_mm(1.0), feed).units(u))?;
     // Now rapid back to where we started
-    g0(file, x(0.0))?;
+    g0(file, x(0.0).units(u))?;
 
     Ok(())
 }
 
+/// Cut every slit, each one all the way through its passes before moving along Y to the next
 fn make_cut(opt: &Opt, file: &mut dyn Write, rpm: f64) -> Result<()> {
+    let u = opt.common.units;
+    let spacing = opt.spacing.unwrap_or(0.0);
+    for i in 0..opt.count {
+        if i > 0 {
+            // Come up clear of the top of the work before moving over, in case the saw doesn't clear it at X0
+            g0(file, z(u.from_mm(4.0)).units(u))?;
+        }
+        if opt.count > 1 {
+            gcode_comment(
+                file,
+                &format!("Slit {} of {}", i + 1, opt.count),
+                opt.common.dialect,
+            )?;
+        }
+        make_slit(opt, file, i as f64 * spacing, rpm)?;
+    }
+
+    Ok(())
+}
+
+/// Cut a single slit at `y`, in as many passes as it takes to make it `height` tall
+fn make_slit(opt: &Opt, file: &mut dyn Write, y: f64, rpm: f64) -> Result<()> {
+    if opt.trochoidal {
+        return make_trochoidal_slit(opt, file, y, rpm);
+    }
     let height = opt.height.unwrap_or(0.0);
     // First pass at the top height
-    make_cut_pass(opt, file, 0.0, rpm)?;
+    make_cut_pass(opt, file, y, 0.0, rpm)?;
 
     let bottom = height - opt.tool_thick;
 
     if height > opt.tool_thick {
         // Second pass at the bottom height
-        make_cut_pass(opt, file, -bottom, rpm)?;
+        make_cut_pass(opt, file, y, -bottom, rpm)?;
     }
 
     if height > opt.tool_thick * 2.0 {
@@ -105,44 +194,214 @@ fn make_cut(opt: &Opt, file: &mut dyn Write, rpm: f64) -> Result<()> {
         let start = opt.tool_thick;
         assert!(bottom > start);
         let passes = ((bottom - start) / opt.tool_thick).ceil();
-        println!("end {} start {} passes {}", bottom, start, passes);
 
         for i in 0..(passes as usize) {
             let z = start + i as f64 * (bottom - start) / passes;
             assert!(z < bottom);
-            make_cut_pass(opt, file, -z, rpm)?;
+            make_cut_pass(opt, file, y, -z, rpm)?;
         }
     }
 
     Ok(())
 }
 
+/// Cut a single slit at `y` with an end mill, as a trochoidal slot from X0 to `depth`, in layers evenly spaced down to
+/// `height` and no deeper than the end mill's diameter
+fn make_trochoidal_slit(opt: &Opt, file: &mut dyn Write, y: f64, rpm: f64) -> Result<()> {
+    let u = opt.common.units;
+    let width = slit_width(opt);
+    let height = opt.height.unwrap_or(0.0);
+    let layers = (height / opt.tool_dia).ceil().max(1.0) as usize;
+    let mut slot = TrochoidalSlot {
+        start: (0.0, y),
+        // The round far end of the slot just reaches the depth
+        end: ((opt.depth - width / 2.0).max(0.0), y),
+        width,
+        tool_dia: opt.tool_dia,
+        step: opt.step.unwrap_or(opt.tool_dia / 10.0),
+        z_cut: 0.0,
+        z_safe: u.from_mm(4.0),
+        feed: chip_load_to_feed(opt.feed_per_tooth, rpm, tool_teeth(opt)),
+        units: u,
+    };
+    for layer in 1..=layers {
+        slot.z_cut = -height * layer as f64 / layers as f64;
+        gcode_comment(
+            file,
+            &format!("Making layer at z={}{}", slot.z_cut, u.abbrev()),
+            opt.common.dialect,
+        )?;
+        trochoidal_slot(file, &slot)?;
+    }
+
+    Ok(())
+}
+
+/// Find Z0 on the top of the work with a touch probe, already loaded
+fn touch_off(opt: &Opt, file: &mut dyn Write) -> Result<()> {
+    let u = opt.common.units;
+    // We're still at the home position, so well above the work, and can move straight over the probing point
+    g0(file, xy(opt.probe_x, opt.probe_y).units(u))?;
+    touch_off_z(
+        file,
+        opt.common.work_offset,
+        opt.probe_distance,
+        u.from_mm(4.0),
+        opt.probe_feed,
+        u,
+        opt.common.dialect,
+    )
+}
+
 fn main() -> Result<()> {
-    let opt = Opt::from_args();
-    help_text();
-    let mut file = BufWriter::new(
-        OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&opt.output)?,
+    let mut opt: Opt = cli::from_args();
+    if opt.count == 0 {
+        clap::Error::with_description("--count must be at least 1", clap::ErrorKind::InvalidValue)
+            .exit();
+    }
+    if opt.count > 1
+        && !opt
+            .spacing
+            .is_some_and(|spacing| spacing > slit_width(&opt))
+    {
+        clap::Error::with_description(
+            "--count of more than 1 needs a --spacing wider than the slit",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt.trochoidal && slit_width(&opt) <= opt.tool_dia {
+        clap::Error::with_description(
+            "--trochoidal needs a --width wider than the --tool-dia",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt.step.is_some_and(|step| step <= 0.0) {
+        clap::Error::with_description("--step must be more than 0", clap::ErrorKind::InvalidValue)
+            .exit();
+    }
+    help_text(&opt);
+    // The saw runs at its default surface speed, unless there's a --surface-speed
+    let rpm = opt.common.rpm(
+        surface_speed_to_rpm(SURFACE_SPEED, opt.common.units.to_mm(opt.tool_dia)),
+        Some(opt.tool_dia),
     );
+    opt.feed_per_tooth = opt.common.chip_load(opt.feed_per_tooth, Some(opt.tool_dia));
 
-    // Calculate the RPM from the surface speed
-    let rpm = opt.speed / (PI * (opt.tool_dia / 1000.0));
-
-    preamble(
-        &opt.name,
-        opt.tool,
-        &format!(
-            "T{} {}mm dia {}mm thick {} tooth slitting saw",
-            opt.tool, opt.tool_dia, opt.tool_thick, opt.tool_teeth
-        ),
-        rpm,
-        opt.coolant,
-        &mut file,
-    )?;
+    let comment = if opt.trochoidal {
+        format!(
+            "T{} {}{} dia {} flute end mill",
+            opt.tool,
+            opt.tool_dia,
+            opt.common.units.abbrev(),
+            tool_teeth(&opt)
+        )
+    } else {
+        format!(
+            "T{} {}{u} dia {}{u} thick {} tooth slitting saw",
+            opt.tool,
+            opt.tool_dia,
+            opt.tool_thick,
+            tool_teeth(&opt),
+            u = opt.common.units.abbrev()
+        )
+    };
+    let tool = opt.common.tool(opt.tool, comment, rpm);
+    let u = opt.common.units;
+    let info = |tools| JobInfo {
+        stock: format!("work to slit {}{} deep along +X", opt.depth, u.abbrev()),
+        datum: if opt.trochoidal {
+            "along -X from the cut, with Z0 on the top of the work".to_string()
+        } else {
+            "along -X from the cut, with the top of the saw level with the top of the cut"
+                .to_string()
+        },
+        tools,
+        notes: if opt.count > 1 {
+            vec![format!(
+                "{} slits, {}{} apart along +Y",
+                opt.count,
+                opt.spacing.unwrap_or(0.0),
+                u.abbrev()
+            )]
+        } else {
+            vec![]
+        },
+        units: u,
+        dialect: opt.common.dialect,
+    };
+    let feed = chip_load_to_feed(opt.feed_per_tooth, rpm, tool_teeth(&opt));
+    let mut file = if let Some(probe_tool) = opt.probe_tool {
+        let probe = Tool {
+            number: probe_tool,
+            comment: format!("T{} touch probe", probe_tool),
+            rpm: 0.0,
+            direction: SpindleDir::Cw,
+            coolant: Coolant::Off,
+            spindle_dwell: None,
+        };
+        let mut file = opt.common.open_output(&probe)?;
+        job_header(
+            &mut file,
+            &info(vec![(&probe, opt.probe_feed), (&tool, feed)]),
+        )?;
+        touch_off(&opt, &mut file)?;
+        opt.common
+            .optional_stop(&mut file, "Check the touch-off before cutting")?;
+        gcode_comment(&mut file, &tool.comment, opt.common.dialect)?;
+        tool_change(&mut file, &tool, opt.common.home(), opt.common.dialect)?;
+        file
+    } else {
+        let mut file = opt.common.open_output(&tool)?;
+        job_header(&mut file, &info(vec![(&tool, feed)]))?;
+        file
+    };
     make_cut(&opt, &mut file, rpm)?;
-    trailer(&mut file)?;
+    trailer(&mut file, opt.common.home(), opt.common.dialect)?;
 
-    file.flush()
+    opt.common.finish(file, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gcode::{bounding_box, parse_moves};
+
+    #[test]
+    fn trochoidal_loops_stay_in_the_slit() {
+        let opt = Opt::from_iter([
+            "slit_gen",
+            "--depth=20",
+            "--trochoidal",
+            "--width=8",
+            "--height=5",
+            "--tool-dia=6",
+            "--output=slit.nc",
+        ]);
+        let mut file = vec![];
+        make_cut(&opt, &mut file, 1000.0).unwrap();
+        let program = String::from_utf8(file).unwrap();
+        let moves = parse_moves(&program);
+
+        // The center of the end mill stays a tool radius inside the walls and the far end of the slit
+        let (lo, hi) = bounding_box(&moves);
+        assert_eq!((lo[0], hi[0]), (Some(-1.0), Some(17.0)));
+        assert_eq!((lo[1], hi[1]), (Some(-1.0), Some(1.0)));
+        assert_eq!(lo[2], Some(-5.0));
+
+        // Each loop starts on the right-hand wall, further along than the last, but no more than a step
+        let starts: Vec<f64> = program
+            .lines()
+            .filter_map(|line| line.strip_prefix("G1 X"))
+            .map(|line| {
+                assert!(line.contains(" Y-1. "));
+                line.split(' ').next().unwrap().parse().unwrap()
+            })
+            .collect();
+        assert!(starts.len() > 20);
+        for pair in starts.windows(2) {
+            assert!(pair[1] > pair[0] && pair[1] - pair[0] <= 0.6 + 1e-9);
+        }
+    }
 }
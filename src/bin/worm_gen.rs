@@ -0,0 +1,368 @@
+//! G-Code generator for cutting worms on a rotational axis, to drive gears cut with gear_gen.
+//! The thread is cut like a knurler's spiral teeth: a V tool with a flat tip, pointing down at the top of the stock,
+//! traverses along X while the A axis turns at the lead rate. The tool's sides cut the straight flanks of the thread
+//! form, and each depth takes as many passes side by side as it needs to open the space out to its full width.
+use gcode::cli::{self, CommonOpts};
+use gcode::gears;
+use gcode::svg::Stock;
+use gcode::{
+    g0, g1, gcode_comment, inv_feed_g93, inverse_feed, job_header, standard_feed_g94, trailer, xaf,
+    xyza, z, zf, JobInfo,
+};
+use std::f64::consts::PI;
+use std::io::{Result, Write};
+use structopt::{clap, StructOpt};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "worm_gen", about = "A worm generator")]
+struct Opt {
+    /// Worm module, in mm whatever the --units, the same as the gear it drives
+    #[structopt(short, long, default_value = "1")]
+    module: f64,
+
+    /// Number of threads (starts) on the worm
+    #[structopt(long, default_value = "1")]
+    starts: u32,
+
+    /// Pitch diameter of the worm, as a multiple of the module. A smaller worm has a steeper lead angle.
+    #[structopt(long, default_value = "10")]
+    diameter_quotient: f64,
+
+    /// Length of the threaded part of the worm, from its right-hand (X0) face
+    #[structopt(long)]
+    len: f64,
+
+    /// Pressure angle, in degrees, which is half the included angle of the tool
+    #[structopt(long, default_value = "20")]
+    pressure_angle: f64,
+
+    /// Width of the flat on the tip of the tool [default: the width of the bottom of the thread space]
+    #[structopt(long)]
+    tip_width: Option<f64>,
+
+    /// Cut a left-hand thread, rather than a right-hand one
+    #[structopt(long)]
+    left_hand: bool,
+
+    /// Tool RPM
+    #[structopt(long, default_value = "3000")]
+    rpm: f64,
+
+    /// Feed rate, in units/min
+    #[structopt(long, default_value = "100")]
+    feed: f64,
+
+    /// Tool number for the cut
+    #[structopt(long, default_value = "1")]
+    tool: u32,
+
+    /// Max cutting stepdown, per pass
+    #[structopt(long, default_value = "0.25")]
+    max_stepdown: f64,
+
+    #[structopt(flatten)]
+    common: CommonOpts,
+}
+
+/// Pitch diameter of the worm, in the job's units
+fn pitch_dia(opt: &Opt) -> f64 {
+    opt.common.units.from_mm(opt.module * opt.diameter_quotient)
+}
+
+/// Outside diameter of the worm, which is also the diameter to turn the stock to
+fn stock_dia(opt: &Opt) -> f64 {
+    pitch_dia(opt) + 2.0 * opt.common.units.from_mm(opt.module)
+}
+
+/// Depth of the thread, in the job's units
+fn thread_depth(opt: &Opt) -> f64 {
+    opt.common.units.from_mm(gears::total_depth(opt.module))
+}
+
+/// Lead of the thread, in the job's units
+fn lead(opt: &Opt) -> f64 {
+    opt.common
+        .units
+        .from_mm(gears::worm_lead(opt.module, opt.starts))
+}
+
+/// How far (in degrees) the A axis turns for each unit the tool moves along X
+fn a_per_x(opt: &Opt) -> f64 {
+    360.0 / lead(opt)
+}
+
+/// Width, along the axis, of the space between threads `depth` down from the outside diameter. At the pitch diameter
+/// it's half the rack pitch, and the flanks close in at the pressure angle below it.
+fn space_width(opt: &Opt, depth: f64) -> f64 {
+    let u = opt.common.units;
+    let m = u.from_mm(opt.module);
+    PI * m / 2.0 + 2.0 * (m - depth) * opt.pressure_angle.to_radians().tan()
+}
+
+fn tip_width(opt: &Opt) -> f64 {
+    opt.tip_width
+        .unwrap_or_else(|| space_width(opt, thread_depth(opt)))
+}
+
+/// X offsets of the tool from the middle of the space, for each pass side by side at `depth`. The outermost put the
+/// corners of the tool's tip on the flanks, and the ones between overlap evenly, so nothing is left standing.
+fn pass_offsets(opt: &Opt, depth: f64) -> Vec<f64> {
+    let spread = space_width(opt, depth) - tip_width(opt);
+    if spread <= 1e-9 {
+        return vec![0.0];
+    }
+    let passes = (spread / tip_width(opt)).ceil() as usize + 1;
+    (0..passes)
+        .map(|i| -spread / 2.0 + spread * i as f64 / (passes - 1) as f64)
+        .collect()
+}
+
+fn help_text(opt: &Opt) {
+    let u = opt.common.units;
+    eprintln!(
+        "Before cut:
+        - Create stock with OD {:.4}{u}
+        - Set home to center of right face of stock
+        - Load a {} degree V tool with a {:.4}{u} flat on its tip
+        - Cut the mating gear with --module {} and a {:.4} degree helix angle, of the same hand as the worm",
+        stock_dia(opt),
+        2.0 * opt.pressure_angle,
+        tip_width(opt),
+        opt.module,
+        gears::worm_lead_angle(opt.module, opt.starts, u.to_mm(pitch_dia(opt))),
+        u = u.abbrev()
+    );
+}
+
+/// Cut a single pass along the thread that starts at `a_start`, with the tip of the tool `depth` down and `offset`
+/// along X from the middle of the space
+fn cut_pass(opt: &Opt, file: &mut dyn Write, a_start: f64, depth: f64, offset: f64) -> Result<()> {
+    let u = opt.common.units;
+    let clearance = u.from_mm(3.0);
+    let hand = if opt.left_hand { -1.0 } else { 1.0 };
+    // Where the A axis is for the tool to be on the thread when it's at `x`. A right-hand thread turns A forwards as
+    // the tool moves towards -X.
+    let a_at = |x: f64| a_start - hand * (x - offset) * a_per_x(opt);
+
+    let top_z = stock_dia(opt) / 2.0;
+    let tip_z = top_z - depth;
+    // Start far enough off the end of the stock that the side of the tool clears it
+    let x_start = offset
+        + tip_width(opt) / 2.0
+        + depth * opt.pressure_angle.to_radians().tan()
+        + u.from_mm(1.0);
+
+    g0(
+        file,
+        xyza(x_start, 0.0, top_z + clearance, a_at(x_start)).units(u),
+    )?;
+    g1(file, zf(tip_z, opt.feed).units(u))?;
+
+    // The tool runs round the stock at the tip's diameter, as well as along it
+    let x_len = x_start + opt.len;
+    let path_len = x_len.hypot(PI * 2.0 * tip_z * x_len / lead(opt));
+    inv_feed_g93(file)?;
+    g1(
+        file,
+        xaf(-opt.len, a_at(-opt.len), inverse_feed(path_len, opt.feed)).units(u),
+    )?;
+    standard_feed_g94(file)?;
+
+    g1(file, zf(top_z + u.from_mm(0.5), opt.feed).units(u))?;
+    g0(file, z(top_z + clearance).units(u))?;
+
+    Ok(())
+}
+
+/// Cut the threads. Every thread is cut at each depth before moving on to the next, so a multi-start worm's threads
+/// come out alike.
+fn cut_worm(opt: &Opt, file: &mut dyn Write) -> Result<()> {
+    let depth = thread_depth(opt);
+    let passes = (depth / opt.max_stepdown).ceil() as usize;
+    let start_angle = 360.0 / opt.starts as f64;
+
+    for pass in 0..passes {
+        let cut_depth = depth * (pass + 1) as f64 / passes as f64;
+        let offsets = pass_offsets(opt, cut_depth);
+        gcode_comment(
+            file,
+            &format!(
+                "Depth {} of {}, {} passes side by side",
+                pass + 1,
+                passes,
+                offsets.len()
+            ),
+            opt.common.dialect,
+        )?;
+        for start in 0..opt.starts {
+            if opt.starts > 1 {
+                gcode_comment(
+                    file,
+                    &format!("Start {} of {}", start + 1, opt.starts),
+                    opt.common.dialect,
+                )?;
+            }
+            for &offset in &offsets {
+                cut_pass(opt, file, start as f64 * start_angle, cut_depth, offset)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut opt: Opt = cli::from_args();
+    let u = opt.common.units;
+    if opt.starts == 0 {
+        clap::Error::with_description("--starts must be at least 1", clap::ErrorKind::InvalidValue)
+            .exit();
+    }
+    let bottom_width = space_width(&opt, thread_depth(&opt));
+    if bottom_width <= 0.0 {
+        clap::Error::with_description(
+            "--pressure-angle is too big: the flanks of the thread would meet above the bottom of the space",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    if opt
+        .tip_width
+        .is_some_and(|width| width <= 0.0 || width > bottom_width)
+    {
+        clap::Error::with_description(
+            &format!(
+                "--tip-width must be more than 0, and no wider than the bottom of the thread space, {:.4}{}",
+                bottom_width,
+                u.abbrev()
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+    help_text(&opt);
+    eprintln!(
+        "Lead {:.4}{u}, so the A axis turns {:.4} degrees per {u} along X; lead angle {:.4} degrees",
+        lead(&opt),
+        a_per_x(&opt),
+        gears::worm_lead_angle(opt.module, opt.starts, u.to_mm(pitch_dia(&opt))),
+        u = u.abbrev()
+    );
+
+    let tool = opt.common.tool(
+        opt.tool,
+        format!(
+            "T{} {} degree V tool, {:.4}{} tip",
+            opt.tool,
+            2.0 * opt.pressure_angle,
+            tip_width(&opt),
+            u.abbrev()
+        ),
+        opt.common.rpm(opt.rpm, None),
+    );
+    opt.feed = opt.common.feed(opt.feed, tool.rpm, None, 1);
+    let mut file = opt.common.open_output(&tool)?;
+    job_header(
+        &mut file,
+        &JobInfo {
+            stock: format!(
+                "OD {:.4}{u}, at least {}{u} long",
+                stock_dia(&opt),
+                opt.len,
+                u = u.abbrev()
+            ),
+            datum: "center of right face of stock".to_string(),
+            tools: vec![(&tool, opt.feed)],
+            notes: vec![format!(
+                "{} {}-start worm, module {}mm, pitch diameter {:.4}{u}, lead {:.4}{u}",
+                if opt.left_hand {
+                    "Left-hand"
+                } else {
+                    "Right-hand"
+                },
+                opt.starts,
+                opt.module,
+                pitch_dia(&opt),
+                lead(&opt),
+                u = u.abbrev()
+            )],
+            units: u,
+            dialect: opt.common.dialect,
+        },
+    )?;
+    cut_worm(&opt, &mut file)?;
+    trailer(&mut file, opt.common.home(), opt.common.dialect)?;
+
+    opt.common.finish(
+        file,
+        Some(Stock::Rect {
+            min: (-opt.len, -stock_dia(&opt) / 2.0),
+            max: (0.0, stock_dia(&opt) / 2.0),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(args: &[&str]) -> Opt {
+        let common = ["worm_gen", "--output=worm.nc"];
+        Opt::from_iter(common.iter().chain(args))
+    }
+
+    /// The value of the `letter` word on a `line` of G code
+    fn word(line: &str, letter: char) -> Option<f64> {
+        line.split_whitespace()
+            .find_map(|w| w.strip_prefix(letter))
+            .and_then(|v| v.parse().ok())
+    }
+
+    /// How far A turns for each unit along X on every cutting pass
+    fn a_rates(opt: &Opt) -> Vec<f64> {
+        let mut file = vec![];
+        cut_worm(opt, &mut file).unwrap();
+        let mut from = None;
+        let mut rates = vec![];
+        for line in String::from_utf8(file).unwrap().lines() {
+            if let (Some(x), Some(a)) = (word(line, 'X'), word(line, 'A')) {
+                if line.starts_with("G0 ") {
+                    from = Some((x, a));
+                } else if let Some((x_from, a_from)) = from.take() {
+                    rates.push((a - a_from) / (x - x_from));
+                }
+            }
+        }
+        rates
+    }
+
+    #[test]
+    fn a_follows_the_lead() {
+        for (args, lead_mm, hand) in [
+            (&["--len=30"][..], gears::worm_lead(1.0, 1), -1.0),
+            (&["--len=30", "--starts=2"], gears::worm_lead(1.0, 2), -1.0),
+            (
+                &["--len=30", "--module=1.5", "--left-hand"],
+                gears::worm_lead(1.5, 1),
+                1.0,
+            ),
+            (
+                &["--len=1", "--units=imperial"],
+                gears::worm_lead(1.0, 1),
+                -1.0,
+            ),
+        ] {
+            let opt = opt(args);
+            assert!((lead(&opt) - opt.common.units.from_mm(lead_mm)).abs() < 1e-12);
+            let rates = a_rates(&opt);
+            assert!(!rates.is_empty());
+            // A right-hand thread turns A forwards as the tool moves towards -X
+            let expected = hand * 360.0 / opt.common.units.from_mm(lead_mm);
+            for rate in rates {
+                assert!((rate - expected).abs() < 1e-3 * expected.abs(), "{}", rate);
+            }
+        }
+        // Two starts double the lead, so A turns half as far along X
+        assert!((gears::worm_lead(1.0, 2) - 2.0 * gears::worm_lead(1.0, 1)).abs() < 1e-12);
+    }
+}
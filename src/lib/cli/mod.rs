@@ -0,0 +1,551 @@
+//! Command line options shared by all the generators, flattened into each one's own options, and the setup and
+//! wrap-up that every job does with them.
+//! Speeds, feeds, and tool numbers aren't shared here, because each generator has its own defaults for them, but the
+//! ways of working them out instead (from a surface speed, or a material), and a cap on the spindle speed, are.
+//! The job's notes and reports are printed to stderr, so that with an output of `-` stdout only carries the program.
+//! Any of a generator's options can also come from a `--config` TOML file, keyed by their long names, so a job that
+//! takes a dozen of them can be kept in a readable file.
+use crate::feeds::{self, Material, ToolMaterial};
+use crate::lint::{lint, LintKind};
+use crate::svg::{self, Stock};
+use crate::{
+    chip_load_to_feed, dxf, fill_run_summary, optional_stop, parse_moves, preamble, report_extents,
+    report_run_time, surface_speed_to_rpm, Coolant, Dialect, Envelope, Home, NumberedWriter,
+    SpindleDir, Tool, Units, WorkOffset,
+};
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use structopt::{clap, StructOpt};
+
+#[derive(Debug, StructOpt)]
+pub struct CommonOpts {
+    /// Name for the job
+    #[structopt(short, long)]
+    pub name: Option<String>,
+
+    /// Read options from this TOML file too, one to a line by their long names, like `module = 2` or `climb = true`.
+    /// Options given on the command line take precedence over the file's.
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Units for all lengths and feeds (metric or imperial)
+    #[structopt(long, default_value = "metric")]
+    pub units: Units,
+
+    /// Flavor of G code for the machine's control: linuxcnc, or grbl
+    #[structopt(long, default_value = "linuxcnc")]
+    pub dialect: Dialect,
+
+    /// Output file for the resulting G code, or - for stdout
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: PathBuf,
+
+    /// Overwrite the output, preview, and DXF files if they already exist, rather than failing
+    #[structopt(long)]
+    pub overwrite: bool,
+
+    /// Also write a preview of the toolpath, looking down onto the X-Y plane, to this SVG file
+    #[structopt(long, parse(from_os_str))]
+    pub preview: Option<PathBuf>,
+
+    /// Also write the cutting moves to this DXF file, flattened onto the X-Y plane, to overlay on a drawing in CAD
+    #[structopt(long, parse(from_os_str))]
+    pub dxf: Option<PathBuf>,
+
+    /// Rapid rate of the machine, in units/min, for estimating the run time [default: 5000mm/min]
+    #[structopt(long)]
+    pub rapid_rate: Option<f64>,
+
+    /// Travel limits of the machine, in work coordinates, like X-200:10,Y0:150,Z-100:0. The program is deleted, with an
+    /// error, if any move goes outside them.
+    #[structopt(long)]
+    pub envelope: Option<Envelope>,
+
+    /// Turn on flood coolant
+    #[structopt(long)]
+    pub coolant: bool,
+
+    /// Turn on mist coolant, rather than flood
+    #[structopt(long)]
+    pub mist: bool,
+
+    /// Run the spindle counter-clockwise (M4), for left-hand tools
+    #[structopt(long)]
+    pub ccw: bool,
+
+    /// Seconds to dwell after starting the spindle, before the first cut
+    #[structopt(long)]
+    pub spindle_dwell: Option<f64>,
+
+    /// Work offset to run the job in, G54 to G59
+    #[structopt(long, default_value = "G54")]
+    pub work_offset: WorkOffset,
+
+    /// Command to return to the home reference position: G28, G30, or G53 Z and a height in machine coordinates. Use
+    /// G28 if your machine has no G30 position set. [default: G30, or G53 Z0 for grbl]
+    #[structopt(long)]
+    pub home: Option<Home>,
+
+    /// Prefix each line with an N block number
+    #[structopt(long)]
+    pub line_numbers: bool,
+
+    /// Pause with an optional stop (M1) between the stages of a job, before each new tool, to look over the work. The
+    /// control only stops there with its optional stop switch on. GRBL already stops for each new tool, so it gets no
+    /// more.
+    #[structopt(long)]
+    pub optional_stops: bool,
+
+    /// Read the finished program back and check it for unsafe motion: feed moves without a feed rate, rapids below Z0,
+    /// inverse time feed left on, and tool changes with the spindle running. The program is deleted, with an error, if
+    /// it fails.
+    #[structopt(long)]
+    pub self_check: bool,
+
+    /// Surface speed of the tool, in meters/minute whatever the --units, to set the spindle speed from the tool's
+    /// diameter rather than taking the --rpm
+    #[structopt(long)]
+    pub surface_speed: Option<f64>,
+
+    /// Material being cut, to choose the spindle speed and feed from a table instead of the --rpm and --feed:
+    /// aluminium, brass, 12l14, 1018, 4140, stainless, tool-steel, cast-iron, or plastic. A --surface-speed still
+    /// sets the spindle speed.
+    #[structopt(long)]
+    pub material: Option<Material>,
+
+    /// Material of the tool, for the --material table: hss or carbide
+    #[structopt(long, default_value = "hss")]
+    pub tool_material: ToolMaterial,
+
+    /// Number of teeth (or flutes) on the tool, for working out the feed from the --material's chip load [default:
+    /// each generator's usual tool]
+    #[structopt(long)]
+    pub tool_teeth: Option<u32>,
+
+    /// Highest spindle RPM to command. Faster spindle speeds, asked for or worked out from a surface speed, are cut
+    /// down to it.
+    #[structopt(long)]
+    pub max_rpm: Option<f64>,
+}
+
+/// Parse the command line into the generator's options, along with any from the `--config` file, as `with_config`
+/// merges them
+pub fn from_args<T: StructOpt>() -> T {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    if let Some(path) = config_path(&args[1..]) {
+        let text = fs::read_to_string(&path).unwrap_or_else(|e| config_error(&path, e));
+        args = with_config::<T>(&args, &text).unwrap_or_else(|e| config_error(&path, e));
+    }
+    let matches = app::<T>().get_matches_from(args);
+    T::from_clap(&matches)
+}
+
+/// The parser for the generator's options, where every option overrides any earlier one of itself
+fn app<'a, 'b, T: StructOpt>() -> clap::App<'a, 'b> {
+    T::clap().setting(clap::AppSettings::AllArgsOverrideSelf)
+}
+
+/// The command line `args`, starting with the program's name, with the options in the TOML config `text` put in ahead
+/// of the command line's, so the command line wins. Options from the config that conflict with ones on the command
+/// line, like a `climb = true` against a --conventional, are left out.
+///
+/// Going through the parser, rather than deserializing the config into `T`, means the config gets the same defaults,
+/// value parsing, and checks as the command line, with no second copy of every generator's options to keep in step.
+fn with_config<T: StructOpt>(
+    args: &[OsString],
+    text: &str,
+) -> std::result::Result<Vec<OsString>, String> {
+    let conflicts = |config: &[OsString]| {
+        let all_args = args[..1].iter().chain(config).chain(&args[1..]);
+        matches!(app::<T>().get_matches_from_safe(all_args),
+            Err(e) if e.kind == clap::ErrorKind::ArgumentConflict)
+    };
+    let mut config = config_args(text)?;
+    // If the command line conflicts with itself, that's for the parser to report
+    if !conflicts(&[]) {
+        config.retain(|arg| !conflicts(std::slice::from_ref(arg)));
+    }
+    Ok(args[..1]
+        .iter()
+        .chain(&config)
+        .chain(&args[1..])
+        .cloned()
+        .collect())
+}
+
+/// Path of the `--config` file, if the command line has one
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+fn config_error(path: &Path, e: impl Display) -> ! {
+    clap::Error::with_description(
+        &format!("Can't use --config {}: {}", path.display(), e),
+        clap::ErrorKind::InvalidValue,
+    )
+    .exit()
+}
+
+/// Command line arguments for the options in the TOML config `text`. A `true` turns a flag on, and `false` leaves it
+/// off.
+fn config_args(text: &str) -> std::result::Result<Vec<OsString>, String> {
+    let table = text.parse::<toml::Table>().map_err(|e| e.to_string())?;
+    let mut args = vec![];
+    for (key, value) in table {
+        let name = format!("--{}", key.replace('_', "-"));
+        if name == "--config" {
+            return Err("it can't name another config file".to_string());
+        }
+        // The value goes in the same argument as the name, so a negative number isn't taken for an option
+        let arg = match value {
+            toml::Value::Boolean(true) => name,
+            toml::Value::Boolean(false) => continue,
+            toml::Value::String(s) => format!("{}={}", name, s),
+            toml::Value::Integer(i) => format!("{}={}", name, i),
+            toml::Value::Float(f) => format!("{}={}", name, f),
+            _ => {
+                return Err(format!(
+                    "{} must be a string, a number, or true or false",
+                    key
+                ))
+            }
+        };
+        args.push(OsString::from(arg));
+    }
+    Ok(args)
+}
+
+/// The program being written. It's kept in memory until it's finished, so it can be checked, and its run time and
+/// extents filled in at the top, before it's written to the output file (created empty up front, so a clash with an
+/// existing file shows up before any work is done) or to stdout.
+pub struct Output {
+    program: Vec<u8>,
+    file: Option<File>,
+    allowed: Vec<LintKind>,
+}
+
+impl Output {
+    /// Let `--self-check` pass a program with problems of this `kind`, as the generator leaves them by design
+    pub fn allow(&mut self, kind: LintKind) {
+        self.allowed.push(kind);
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.program.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl CommonOpts {
+    /// Where to go home: as chosen, or by default G30, or for GRBL the top of Z travel
+    pub fn home(&self) -> Home {
+        self.home.unwrap_or(match self.dialect {
+            Dialect::LinuxCnc => Home::G30,
+            Dialect::Grbl => Home::MachineZ(0.0),
+        })
+    }
+
+    fn to_stdout(&self) -> bool {
+        self.output == Path::new("-")
+    }
+
+    /// Create the file at `path`, failing if it already exists unless `--overwrite` is set
+    fn create(&self, path: &Path) -> Result<File> {
+        if self.overwrite {
+            File::create(path)
+        } else {
+            OpenOptions::new().write(true).create_new(true).open(path)
+        }
+    }
+
+    /// Direction the spindle runs, from `--ccw`
+    pub fn spindle_dir(&self) -> SpindleDir {
+        if self.ccw {
+            SpindleDir::Ccw
+        } else {
+            SpindleDir::Cw
+        }
+    }
+
+    /// Diameter of the tool in mm, from `tool_dia` in the job's units, exiting with an error if it has none to work out
+    /// its speeds from
+    fn tool_dia_mm(&self, tool_dia: Option<f64>) -> f64 {
+        match tool_dia {
+            Some(dia) => self.units.to_mm(dia),
+            None => clap::Error::with_description(
+                "--surface-speed and --material need a tool diameter to work from, and this tool has none: set its --rpm and --feed instead",
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit(),
+        }
+    }
+
+    /// With `--optional-stops`, stop the spindle at home and pause (M1) for the operator to do what `message` says,
+    /// before the next stage of the job changes tools
+    pub fn optional_stop(&self, file: &mut dyn Write, message: &str) -> Result<()> {
+        if self.optional_stops && self.dialect == Dialect::LinuxCnc {
+            optional_stop(file, self.home(), message, self.dialect)?;
+        }
+        Ok(())
+    }
+
+    /// Spindle speed for a tool `tool_dia` across (in the job's units, or `None` for a tool with no diameter to speak
+    /// of): set from `--surface-speed` or `--material` if there is one, and otherwise `rpm`, then held to `--max-rpm`
+    pub fn rpm(&self, rpm: f64, tool_dia: Option<f64>) -> f64 {
+        let surface_speed = self.surface_speed.or_else(|| {
+            self.material
+                .map(|material| feeds::cutting(material, self.tool_material).surface_speed)
+        });
+        let rpm = match surface_speed {
+            Some(speed) => surface_speed_to_rpm(speed, self.tool_dia_mm(tool_dia)),
+            None => rpm,
+        };
+        self.clamp_rpm(rpm)
+    }
+
+    /// Chip load per tooth, in the job's units, for a tool `tool_dia` across: from the `--material` table if there is
+    /// one, and otherwise `chip_load`
+    pub fn chip_load(&self, chip_load: f64, tool_dia: Option<f64>) -> f64 {
+        match self.material {
+            Some(material) => self.units.from_mm(feeds::chip_load(
+                feeds::cutting(material, self.tool_material),
+                self.tool_dia_mm(tool_dia),
+            )),
+            None => chip_load,
+        }
+    }
+
+    /// Feed rate for a tool `tool_dia` across running at `rpm`, with `teeth` teeth unless `--tool-teeth` says
+    /// otherwise: from the `--material`'s chip load if there is one, and otherwise `feed`
+    pub fn feed(&self, feed: f64, rpm: f64, tool_dia: Option<f64>, teeth: u32) -> f64 {
+        match self.material {
+            Some(_) => chip_load_to_feed(
+                self.chip_load(0.0, tool_dia),
+                rpm,
+                self.tool_teeth.unwrap_or(teeth),
+            ),
+            None => feed,
+        }
+    }
+
+    /// `rpm`, held to `--max-rpm`
+    pub fn clamp_rpm(&self, rpm: f64) -> f64 {
+        match self.max_rpm {
+            Some(max) if rpm > max => {
+                eprintln!(
+                    "Holding the spindle to {:.0}rpm, rather than {:.0}rpm",
+                    max, rpm
+                );
+                max
+            }
+            _ => rpm,
+        }
+    }
+
+    /// Tool `number`, run at `rpm` in the chosen spindle direction, with the chosen coolant and dwell
+    pub fn tool(&self, number: u32, comment: String, rpm: f64) -> Tool {
+        Tool {
+            number,
+            comment,
+            rpm,
+            direction: self.spindle_dir(),
+            coolant: Coolant::from_flags(self.coolant, self.mist),
+            spindle_dwell: self.spindle_dwell,
+        }
+    }
+
+    /// Create the output, and write the preamble to it, loading `tool`
+    pub fn open_output(&self, tool: &Tool) -> Result<Output> {
+        let file = if self.to_stdout() {
+            None
+        } else {
+            Some(self.create(&self.output)?)
+        };
+        let mut file = Output {
+            program: vec![],
+            file,
+            allowed: vec![],
+        };
+        preamble(
+            &self.name,
+            tool,
+            self.units,
+            self.work_offset,
+            self.home(),
+            self.dialect,
+            &mut file,
+        )?;
+        Ok(file)
+    }
+
+    /// Finish the program, then check it against the envelope and with `--self-check` (deleting the output file and
+    /// exiting with an error if it fails either), fill in its run time and extents, write it out, report its run time
+    /// and extents, and write the preview (with the `stock` outline) and DXF
+    pub fn finish(&self, file: Output, stock: Option<Stock>) -> Result<()> {
+        let program = String::from_utf8_lossy(&file.program).into_owned();
+        let moves = parse_moves(&program);
+        if let Some(envelope) = &self.envelope {
+            if let Err(e) = envelope.check(&moves) {
+                if !self.to_stdout() {
+                    fs::remove_file(&self.output)?;
+                }
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+        }
+        if self.self_check {
+            let lints: Vec<_> = lint(&program)
+                .into_iter()
+                .filter(|lint| !file.allowed.contains(&lint.kind))
+                .collect();
+            if !lints.is_empty() {
+                for lint in &lints {
+                    eprintln!("{}", lint);
+                }
+                if !self.to_stdout() {
+                    fs::remove_file(&self.output)?;
+                }
+                clap::Error::with_description(
+                    &format!(
+                        "The program failed its --self-check, with {} problems",
+                        lints.len()
+                    ),
+                    clap::ErrorKind::InvalidValue,
+                )
+                .exit();
+            }
+        }
+        let rapid_rate = self
+            .rapid_rate
+            .unwrap_or_else(|| self.units.from_mm(5000.0));
+        let program = fill_run_summary(&program, &moves, rapid_rate, self.units, self.dialect)?;
+        let output: Box<dyn Write> = match file.file {
+            Some(file) => Box::new(BufWriter::new(file)),
+            None => Box::new(BufWriter::new(io::stdout())),
+        };
+        let mut output: Box<dyn Write> = if self.line_numbers {
+            Box::new(NumberedWriter::new(output))
+        } else {
+            output
+        };
+        output.write_all(program.as_bytes())?;
+        output.flush()?;
+        drop(output);
+        report_run_time(&moves, rapid_rate);
+        report_extents(&moves, self.units);
+
+        if let Some(preview) = &self.preview {
+            let mut file = BufWriter::new(self.create(preview)?);
+            svg::render(&moves, stock, &mut file)?;
+            file.flush()?;
+        }
+        if let Some(dxf) = &self.dxf {
+            let mut file = BufWriter::new(self.create(dxf)?);
+            dxf::render(&moves, self.units, &mut file)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, StructOpt)]
+    struct Opt {
+        #[structopt(long)]
+        depth: f64,
+        #[structopt(long)]
+        finish: bool,
+        #[structopt(long)]
+        climb: bool,
+        #[structopt(long, conflicts_with = "climb")]
+        conventional: bool,
+    }
+
+    fn parse(args: &[&str], config: &str) -> Opt {
+        let args: Vec<OsString> = ["test"].iter().chain(args).map(OsString::from).collect();
+        let args = with_config::<Opt>(&args, config).unwrap();
+        Opt::from_clap(&app::<Opt>().get_matches_from_safe(args).unwrap())
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        assert_eq!(parse(&[], "depth = 2.5").depth, 2.5);
+        assert_eq!(parse(&[], "depth = -3").depth, -3.0);
+        assert_eq!(parse(&["--depth=4"], "depth = 2.5").depth, 4.0);
+        assert_eq!(parse(&["--depth", "4"], "depth = 2.5").depth, 4.0);
+    }
+
+    #[test]
+    fn config_false_leaves_a_flag_for_the_command_line() {
+        assert!(!parse(&[], "depth = 1\nfinish = false").finish);
+        assert!(parse(&[], "depth = 1\nfinish = true").finish);
+        assert!(parse(&["--finish"], "depth = 1\nfinish = false").finish);
+    }
+
+    #[test]
+    fn command_line_overrides_a_conflicting_config_flag() {
+        let opt = parse(&["--conventional"], "depth = 1\nclimb = true");
+        assert!(opt.conventional && !opt.climb);
+        assert_eq!(opt.depth, 1.0);
+        assert!(parse(&[], "depth = 1\nclimb = true").climb);
+    }
+
+    fn common(args: &[&str]) -> CommonOpts {
+        CommonOpts::from_iter(["test", "--output=job.nc"].iter().chain(args))
+    }
+
+    #[test]
+    fn material_overrides_rpm_and_feed() {
+        // Without a material, the generator's own speeds and feeds go through
+        let given = common(&[]);
+        assert_eq!(given.rpm(800.0, Some(10.0)), 800.0);
+        assert_eq!(given.feed(60.0, 800.0, Some(10.0), 3), 60.0);
+
+        let carbide = common(&["--material=6061", "--tool-material=carbide"]);
+        let table = feeds::speeds_and_feeds(Material::Aluminium, ToolMaterial::Carbide, 10.0, 3);
+        let rpm = carbide.rpm(800.0, Some(10.0));
+        assert!((rpm - table.rpm).abs() < 1e-9);
+        assert!((carbide.feed(60.0, rpm, Some(10.0), 3) - table.feed).abs() < 1e-9);
+        // In inches, the tool is measured in inches and the feed comes out in inches/min
+        let inches = common(&[
+            "--material=6061",
+            "--tool-material=carbide",
+            "--units=imperial",
+        ]);
+        let rpm = inches.rpm(800.0, Some(10.0 / 25.4));
+        assert!((rpm - table.rpm).abs() < 1e-6);
+        assert!((inches.feed(60.0, rpm, Some(10.0 / 25.4), 3) - table.feed / 25.4).abs() < 1e-6);
+        // A --surface-speed still sets the spindle speed, and --max-rpm holds it down
+        let surface = common(&["--material=6061", "--surface-speed=100"]).rpm(800.0, Some(10.0));
+        assert!((surface - 100_000.0 / (std::f64::consts::PI * 10.0)).abs() < 1e-9);
+        let held = common(&["--material=6061", "--max-rpm=2000"]).rpm(800.0, Some(10.0));
+        assert_eq!(held, 2000.0);
+    }
+
+    #[test]
+    fn config_errors() {
+        let args = [OsString::from("test")];
+        assert!(with_config::<Opt>(&args, "config = \"other.toml\"").is_err());
+        assert!(with_config::<Opt>(&args, "depth = [1, 2]").is_err());
+        assert!(with_config::<Opt>(&args, "depth = ").is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! DXF exports of toolpaths, for overlaying the generated path on a part drawing in CAD.
+//! Only the cutting moves are exported, flattened onto the X-Y plane: feeds as LINE entities, and arcs as ARC
+//! entities (not polylines), all on layer 0.
+use crate::{arc_center, xy_segments, Move, Units, XySegment};
+use std::io::{Result, Write};
+
+/// Write a DXF group: its code, then its value, on lines of their own
+fn group(file: &mut dyn Write, code: u32, value: &str) -> Result<()> {
+    writeln!(file, "{}", code)?;
+    writeln!(file, "{}", value)
+}
+
+/// Write a DXF of the cutting moves in `moves`, which are in `units`
+pub fn render(moves: &[Move], units: Units, file: &mut dyn Write) -> Result<()> {
+    let (_, feeds) = xy_segments(moves);
+    let num = |v: f64| format!("{:.6}", v);
+
+    group(file, 0, "SECTION")?;
+    group(file, 2, "HEADER")?;
+    group(file, 9, "$INSUNITS")?;
+    group(
+        file,
+        70,
+        match units {
+            Units::Metric => "4",
+            Units::Imperial => "1",
+        },
+    )?;
+    group(file, 0, "ENDSEC")?;
+
+    group(file, 0, "SECTION")?;
+    group(file, 2, "ENTITIES")?;
+    for segment in feeds {
+        match segment {
+            XySegment::Line(from, to) => {
+                group(file, 0, "LINE")?;
+                group(file, 8, "0")?;
+                group(file, 10, &num(from.0))?;
+                group(file, 20, &num(from.1))?;
+                group(file, 30, "0.0")?;
+                group(file, 11, &num(to.0))?;
+                group(file, 21, &num(to.1))?;
+                group(file, 31, "0.0")?;
+            }
+            XySegment::Arc(from, to, r, ccw) => {
+                let center = arc_center(from, to, r, ccw);
+                let angle = |p: (f64, f64)| (p.1 - center.1).atan2(p.0 - center.0).to_degrees();
+                // DXF arcs always run counter-clockwise, so a clockwise one runs backwards from its end
+                let (start, end) = if ccw {
+                    (angle(from), angle(to))
+                } else {
+                    (angle(to), angle(from))
+                };
+                group(file, 0, "ARC")?;
+                group(file, 8, "0")?;
+                group(file, 10, &num(center.0))?;
+                group(file, 20, &num(center.1))?;
+                group(file, 30, "0.0")?;
+                group(file, 40, &num(r.abs()))?;
+                group(file, 50, &num(start))?;
+                group(file, 51, &num(end))?;
+            }
+        }
+    }
+    group(file, 0, "ENDSEC")?;
+    group(file, 0, "EOF")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_moves;
+
+    fn dxf(gcode: &str) -> Vec<String> {
+        let mut file = vec![];
+        render(&parse_moves(gcode), Units::Metric, &mut file).unwrap();
+        String::from_utf8(file)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The entities in a DXF, as their type and groups
+    fn entities(lines: &[String]) -> Vec<(String, Vec<(u32, String)>)> {
+        let groups: Vec<(u32, String)> = lines
+            .chunks(2)
+            .map(|pair| (pair[0].parse().unwrap(), pair[1].clone()))
+            .collect();
+        let from = groups.iter().position(|g| g.1 == "ENTITIES").unwrap() + 1;
+        let mut entities: Vec<(String, Vec<(u32, String)>)> = vec![];
+        for (code, value) in &groups[from..] {
+            if *code == 0 {
+                if value == "ENDSEC" {
+                    break;
+                }
+                entities.push((value.clone(), vec![]));
+            } else {
+                entities.last_mut().unwrap().1.push((*code, value.clone()));
+            }
+        }
+        entities
+    }
+
+    /// The value of group `code` in an entity, as a number
+    fn value(groups: &[(u32, String)], code: u32) -> f64 {
+        groups
+            .iter()
+            .find(|g| g.0 == code)
+            .unwrap()
+            .1
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn square_is_four_lines() {
+        let lines = dxf("G0 X0 Y0\nG1 X10 F100\nG1 Y10\nG1 X0\nG1 Y0\nG0 Z5\n");
+        assert_eq!(lines.last().unwrap(), "EOF");
+        let entities = entities(&lines);
+        assert_eq!(entities.len(), 4);
+        let corners = [
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+            (0.0, 0.0),
+        ];
+        for ((kind, groups), ends) in entities.iter().zip(corners.windows(2)) {
+            assert_eq!(kind, "LINE");
+            assert_eq!(groups[0], (8, "0".to_string()));
+            assert_eq!((value(groups, 10), value(groups, 20)), ends[0]);
+            assert_eq!((value(groups, 11), value(groups, 21)), ends[1]);
+        }
+    }
+
+    #[test]
+    fn arcs_run_counter_clockwise() {
+        // A clockwise half circle over the top, from (0, 0) to (10, 0), is the ARC from 0 to 180 degrees
+        let entities = entities(&dxf("G0 X0 Y0\nG2 X10 Y0 R5 F100\n"));
+        assert_eq!(entities.len(), 1);
+        let (kind, groups) = &entities[0];
+        assert_eq!(kind, "ARC");
+        assert_eq!((value(groups, 10), value(groups, 20)), (5.0, 0.0));
+        assert_eq!(value(groups, 40), 5.0);
+        assert_eq!((value(groups, 50), value(groups, 51)), (0.0, 180.0));
+    }
+
+    #[test]
+    fn inch_units() {
+        let mut file = vec![];
+        render(&[], Units::Imperial, &mut file).unwrap();
+        let text = String::from_utf8(file).unwrap();
+        assert!(text.contains("$INSUNITS\n70\n1\n"));
+    }
+}
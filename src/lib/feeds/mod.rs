@@ -0,0 +1,194 @@
+//! Starting speeds and feeds for milling, by work material and tool material.
+//! These are the middle of the book ranges for a rigid hobby machine: a light mill or a small one like the Tormach,
+//! cutting dry or with flood coolant. A big rigid machine can go faster, and a deep full-width slot should go slower.
+//! Lengths are in mm, and surface speeds in meters/minute.
+use crate::{chip_load_to_feed, surface_speed_to_rpm};
+use std::str::FromStr;
+
+/// Material being cut
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Material {
+    Aluminium,
+    Brass,
+    /// 12L14, and other free-machining leaded steels
+    FreeMachiningSteel,
+    /// 1018, and other low carbon steels
+    MildSteel,
+    /// 4140, and other alloy steels, annealed or pre-hardened
+    AlloySteel,
+    /// 303 and 304
+    Stainless,
+    /// W1, O1, and other tool steels, annealed
+    ToolSteel,
+    CastIron,
+    /// Acetal (Delrin), nylon, and the like
+    Plastic,
+}
+
+impl FromStr for Material {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "aluminium" | "aluminum" | "6061" => Ok(Material::Aluminium),
+            "brass" | "360" => Ok(Material::Brass),
+            "12l14" | "free-machining-steel" => Ok(Material::FreeMachiningSteel),
+            "1018" | "mild-steel" => Ok(Material::MildSteel),
+            "4140" | "alloy-steel" => Ok(Material::AlloySteel),
+            "303" | "304" | "stainless" => Ok(Material::Stainless),
+            "w1" | "o1" | "tool-steel" => Ok(Material::ToolSteel),
+            "cast-iron" => Ok(Material::CastIron),
+            "delrin" | "plastic" => Ok(Material::Plastic),
+            _ => Err(format!(
+                "Unknown material {}, expected aluminium, brass, 12l14, 1018, 4140, stainless, tool-steel, cast-iron, or plastic",
+                s
+            )),
+        }
+    }
+}
+
+/// Material the cutting edges of the tool are made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolMaterial {
+    Hss,
+    Carbide,
+}
+
+impl FromStr for ToolMaterial {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hss" => Ok(ToolMaterial::Hss),
+            "carbide" => Ok(ToolMaterial::Carbide),
+            _ => Err(format!(
+                "Unknown tool material {}, expected hss or carbide",
+                s
+            )),
+        }
+    }
+}
+
+/// Surface speed and chip load to cut a material with a tool
+#[derive(Debug, Clone, Copy)]
+pub struct Cutting {
+    /// Surface speed at the edge of the tool, in meters/minute
+    pub surface_speed: f64,
+    /// Chip load per tooth, for each mm of the tool's diameter
+    pub chip_load_per_mm: f64,
+}
+
+/// Past this diameter the chip load stops growing with the tool, as the teeth of big cutters (gear cutters and
+/// slitting saws) are no stronger than those of a mid-sized end mill.
+const CHIP_LOAD_DIA_LIMIT: f64 = 12.0;
+
+/// Surface speed and chip load for cutting `material` with a `tool_material` tool
+pub fn cutting(material: Material, tool_material: ToolMaterial) -> Cutting {
+    let (hss, carbide) = match material {
+        Material::Aluminium => ((90.0, 0.008), (300.0, 0.010)),
+        Material::Brass => ((60.0, 0.006), (200.0, 0.008)),
+        Material::FreeMachiningSteel => ((45.0, 0.004), (150.0, 0.006)),
+        Material::MildSteel => ((30.0, 0.003), (120.0, 0.005)),
+        Material::AlloySteel => ((20.0, 0.0025), (90.0, 0.004)),
+        Material::Stainless => ((15.0, 0.002), (70.0, 0.0035)),
+        Material::ToolSteel => ((15.0, 0.002), (60.0, 0.003)),
+        Material::CastIron => ((25.0, 0.003), (90.0, 0.005)),
+        Material::Plastic => ((100.0, 0.010), (300.0, 0.012)),
+    };
+    let (surface_speed, chip_load_per_mm) = match tool_material {
+        ToolMaterial::Hss => hss,
+        ToolMaterial::Carbide => carbide,
+    };
+    Cutting {
+        surface_speed,
+        chip_load_per_mm,
+    }
+}
+
+/// Chip load per tooth, in mm, for a tool `tool_dia` mm across
+pub fn chip_load(cutting: Cutting, tool_dia: f64) -> f64 {
+    cutting.chip_load_per_mm * tool_dia.min(CHIP_LOAD_DIA_LIMIT)
+}
+
+/// Spindle speed, and feed in mm/min
+#[derive(Debug, Clone, Copy)]
+pub struct SpeedsAndFeeds {
+    pub rpm: f64,
+    pub feed: f64,
+}
+
+/// Spindle speed and feed for cutting `material` with a `tool_material` tool `tool_dia` mm across, with `teeth` teeth
+pub fn speeds_and_feeds(
+    material: Material,
+    tool_material: ToolMaterial,
+    tool_dia: f64,
+    teeth: u32,
+) -> SpeedsAndFeeds {
+    let cutting = cutting(material, tool_material);
+    let rpm = surface_speed_to_rpm(cutting.surface_speed, tool_dia);
+    SpeedsAndFeeds {
+        rpm,
+        feed: chip_load_to_feed(chip_load(cutting, tool_dia), rpm, teeth),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn assert_close(got: f64, expected: f64) {
+        assert!(
+            (got - expected).abs() < 1e-9,
+            "got {}, expected {}",
+            got,
+            expected
+        );
+    }
+
+    #[test]
+    fn table_lookup() {
+        let mild = cutting(Material::MildSteel, ToolMaterial::Carbide);
+        assert_eq!((mild.surface_speed, mild.chip_load_per_mm), (120.0, 0.005));
+        let brass = cutting(Material::Brass, ToolMaterial::Hss);
+        assert_eq!((brass.surface_speed, brass.chip_load_per_mm), (60.0, 0.006));
+        // Every material cuts faster, with a heavier chip, with carbide
+        for name in [
+            "6061",
+            "360",
+            "12l14",
+            "1018",
+            "4140",
+            "304",
+            "o1",
+            "cast-iron",
+            "delrin",
+        ] {
+            let material: Material = name.parse().unwrap();
+            let (hss, carbide) = (
+                cutting(material, ToolMaterial::Hss),
+                cutting(material, ToolMaterial::Carbide),
+            );
+            assert!(carbide.surface_speed > hss.surface_speed);
+            assert!(carbide.chip_load_per_mm > hss.chip_load_per_mm);
+        }
+        assert!("unobtainium".parse::<Material>().is_err());
+    }
+
+    #[test]
+    fn chip_load_stops_growing_with_big_tools() {
+        let mild = cutting(Material::MildSteel, ToolMaterial::Hss);
+        assert_close(chip_load(mild, 6.0), 0.018);
+        assert_close(chip_load(mild, 12.0), 0.036);
+        assert_close(chip_load(mild, 76.2), 0.036);
+    }
+
+    #[test]
+    fn speeds_and_feeds_of_an_end_mill() {
+        // A 10mm 3 flute carbide end mill in aluminium, at 300m/min and 0.1mm a tooth
+        let cut = speeds_and_feeds(Material::Aluminium, ToolMaterial::Carbide, 10.0, 3);
+        let rpm = 300.0 * 1000.0 / (PI * 10.0);
+        assert_close(cut.rpm, rpm);
+        assert_close(cut.feed, 0.1 * 3.0 * rpm);
+    }
+}
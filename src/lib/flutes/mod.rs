@@ -0,0 +1,365 @@
+//! Cutting spiral flutes into a cylinder on a 4th axis, with relief behind each tooth, as for turning a round hobber
+//! blank into a cutter.
+//! The cylinder's axis lies along X, with its right face at X0. Each flute is cut with the tool along the top (+Z) of
+//! the stock, feeding down in Z while the A axis turns, in G93 inverse time mode so the feed is right at the tool.
+use crate::{
+    g0, g1, gcode_comment, go_home, index_angles, inv_feed_g93, inverse_feed,
+    rotary_surface_distance, standard_feed_g94, xyza, zaf, zf, Dialect, Home, Units,
+};
+use std::f64::consts::PI;
+use std::io::{Result, Write};
+use std::str::FromStr;
+
+/// Shape of the end of the tool cutting the flutes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolType {
+    /// Ball end mill, measured to the tip of the ball
+    Ball,
+    /// Flat end mill
+    Flat,
+}
+
+impl ToolType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ToolType::Ball => "ball mill",
+            ToolType::Flat => "flat end mill",
+        }
+    }
+}
+
+impl FromStr for ToolType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ball" => Ok(ToolType::Ball),
+            "flat" => Ok(ToolType::Flat),
+            _ => Err(format!("Unknown tool type {}, expected ball or flat", s)),
+        }
+    }
+}
+
+/// How to cut the flutes, all in the job's units
+#[derive(Debug, Clone, Copy)]
+pub struct FluteCut {
+    /// Number of flutes, shared evenly between the starts
+    pub flutes: u32,
+    /// Number of starts, for a multi-start hob
+    pub starts: u32,
+    /// Max depth of each flute, at the right face
+    pub depth: f64,
+    /// Max depth of each flute at the far (-X) end, which is the same as `depth` for flutes of constant depth
+    pub depth_end: f64,
+    /// Length of the cutter, from X0 to -len
+    pub len: f64,
+    /// Diameter of the cutter blank
+    pub dia: f64,
+    pub feed: f64,
+    /// Diameter of the tool cutting the flutes
+    pub tool_dia: f64,
+    pub tool_type: ToolType,
+    /// Max cutting stepdown, per pass
+    pub max_stepdown: f64,
+    /// Tool stepover, as a ratio of tool width
+    pub max_stepover: f64,
+    /// Spiral angle (degrees) of a single-start helix. 0 for straight flutes.
+    pub spiral_angle: f64,
+    /// Relief angle (degrees) behind each tooth, for a constant-relief back. `None` cuts a straight ramp.
+    pub relief_angle: Option<f64>,
+    /// Vary the spacing between flutes by up to this fraction, pseudo-randomly from `seed` (see `index_angles`)
+    pub spacing_variation: f64,
+    pub seed: u64,
+    /// Step along X from the far end to the right face, rather than out from the right face, which puts the uncut
+    /// stock on the other side of the tool and so swaps between conventional and climb milling
+    pub from_far_end: bool,
+    /// Index of the first flute to cut, counting from 0, to resume a job part way through
+    pub first_flute: u32,
+    pub home: Home,
+    pub units: Units,
+    pub dialect: Dialect,
+}
+
+/// Inverse time feed rate (for G93 mode) to cut at the job's feed, while moving `delta_z` down and turning from
+/// `a_start` to `a_end`
+pub fn machine_feedrate(cut: &FluteCut, delta_z: f64, a_start: f64, a_end: f64) -> f64 {
+    let delta_a_along_surface = rotary_surface_distance(a_end - a_start, cut.dia);
+    inverse_feed(delta_z.hypot(delta_a_along_surface), cut.feed)
+}
+
+/// Z (the radius of the back of the tooth) at each of `steps` + 1 evenly spaced angles, turning `a_move` degrees
+/// from the cutting edge at `z_start`. The back falls away from the edge at a constant `relief_angle` (in degrees), so
+/// its radius shrinks by the same proportion for each degree turned, until it meets `z_floor`, where it stays flat.
+pub fn relief_curve(
+    z_start: f64,
+    z_floor: f64,
+    relief_angle: f64,
+    a_move: f64,
+    steps: u32,
+) -> Vec<f64> {
+    let k = relief_angle.to_radians().tan();
+    (0..=steps)
+        .map(|i| {
+            let turned = (a_move * i as f64 / steps as f64).to_radians();
+            (z_start * (-k * turned).exp()).max(z_floor)
+        })
+        .collect()
+}
+
+/// A axis angle (degrees) of the flute that starts at `angle` on the right face, `x` along the axis.
+/// A single-start helix at the spiral angle advances (its lead) PI * dia / tan(spiral_angle) along X for each turn of
+/// A. With N starts the lead is N times that, so A turns 1/N as far for each unit along X.
+pub fn angle_on_spiral(cut: &FluteCut, angle: f64, x: f64) -> f64 {
+    angle + 360.0 * x * cut.spiral_angle.to_radians().tan() / (PI * cut.dia * cut.starts as f64)
+}
+
+/// Starting angles of all the flutes. Each start has the same flutes, turned 1/N of the way round from the last, so
+/// any unequal spacing is repeated on each start and the hob stays balanced.
+pub fn flute_angles(cut: &FluteCut) -> Vec<f64> {
+    let per_start = cut.flutes / cut.starts;
+    let start_angles = index_angles(per_start, cut.spacing_variation, cut.seed);
+    (0..cut.starts)
+        .flat_map(|start| {
+            let offset = 360.0 * start as f64 / cut.starts as f64;
+            start_angles
+                .iter()
+                .map(move |angle| angle / cut.starts as f64 + offset)
+        })
+        .collect()
+}
+
+/// How far (in degrees of A) the side of the tool reaches past its center, with its tip `depth` into the stock.
+/// The end of each pass is backed off by this much, so the tool stops short of the face of the next tooth.
+///
+/// A flat end mill is taken to reach its radius around the outside of the stock. A ball's widest point inside the
+/// stock is either where a line from the axis touches the ball, or where the ball comes out of the stock, so it
+/// reaches nowhere when just touching and further as it goes deeper.
+pub fn tool_reach(cut: &FluteCut, depth: f64) -> f64 {
+    let tool_radius = cut.tool_dia / 2.0;
+    let stock_radius = cut.dia / 2.0;
+    match cut.tool_type {
+        ToolType::Flat => 360.0 * tool_radius / (PI * cut.dia),
+        ToolType::Ball => {
+            // Distance from the stock's axis to the center of the ball
+            let center = stock_radius - depth + tool_radius;
+            let tangent_radius = (center * center - tool_radius * tool_radius).sqrt();
+            let reach = if tangent_radius <= stock_radius {
+                (tool_radius / center).asin()
+            } else {
+                ((stock_radius * stock_radius + center * center - tool_radius * tool_radius)
+                    / (2.0 * stock_radius * center))
+                    .clamp(-1.0, 1.0)
+                    .acos()
+            };
+            reach.to_degrees()
+        }
+    }
+}
+
+/// Target depth of the flutes `x` along the axis, changing linearly from `depth` at the right face to `depth_end` at
+/// the far end
+pub fn depth_at(cut: &FluteCut, x: f64) -> f64 {
+    let along = (-x / cut.len).clamp(0.0, 1.0);
+    cut.depth + (cut.depth_end - cut.depth) * along
+}
+
+fn pass_at_depth(
+    cut: &FluteCut,
+    file: &mut dyn Write,
+    x_pos: f64,
+    max_depth: f64,
+    a_start: f64,
+    a_end: f64,
+) -> Result<()> {
+    let u = cut.units;
+    // Clearance (4mm) away from the stock where we move at feed rate
+    let clearance = u.from_mm(4.0);
+
+    // All ops happen along the "top" of the stock, minus some Z depth, moving in A and -Z simultaneously
+    let y_pos = 0.0;
+
+    let z_start = cut.dia / 2.0;
+    let z_end = z_start - max_depth;
+    // Cutting feed rate, in inverse minutes
+    let cutting_feed = machine_feedrate(cut, z_start - z_end, a_start, a_end);
+    let in_out_feed = cut.feed;
+
+    gcode_comment(
+        file,
+        &format!("Pass at depth {}{}", max_depth, u.abbrev()),
+        cut.dialect,
+    )?;
+    // Rapid to some distance above the start of the work
+    g0(
+        file,
+        xyza(x_pos, y_pos, z_start + clearance, a_start).units(u),
+    )?;
+    // Feed in to the starting Z at feed rate (this shouldn't plunge the tool, but we're just being cautious by not making this rapid)
+    g1(file, zf(z_start, in_out_feed).units(u))?;
+    // Now simultaneously feed in the Z and A axes
+    inv_feed_g93(file)?;
+    match cut.relief_angle {
+        Some(relief_angle) => {
+            // Follow the relief curve in short (at most 1 degree) moves
+            let steps = (a_end - a_start).abs().ceil().max(1.0) as u32;
+            let curve = relief_curve(z_start, z_end, relief_angle, a_end - a_start, steps);
+            for (i, pair) in curve.windows(2).enumerate() {
+                let a_from = a_start + (a_end - a_start) * i as f64 / steps as f64;
+                let a_to = a_start + (a_end - a_start) * (i + 1) as f64 / steps as f64;
+                let feed = machine_feedrate(cut, pair[0] - pair[1], a_from, a_to);
+                g1(file, zaf(pair[1], a_to, feed).units(u))?;
+            }
+        }
+        None => g1(file, zaf(z_end, a_end, cutting_feed).units(u))?,
+    }
+    standard_feed_g94(file)?;
+    // Then feed out back to the Z clearance point
+    g1(file, zf(z_start, in_out_feed).units(u))?;
+
+    Ok(())
+}
+
+fn cut_flute(cut: &FluteCut, file: &mut dyn Write, angle: f64) -> Result<()> {
+    // Start x so that the tool is barely touching the work, and take passes until we've consumed the whole X distance
+    let mut xs = vec![];
+    let mut x = cut.tool_dia / 2.0;
+    while x > -cut.len {
+        xs.push(x);
+        // Move up the x axis by our stepover value
+        x -= cut.tool_dia * cut.max_stepover;
+    }
+    if cut.from_far_end {
+        xs.reverse();
+    }
+
+    for x in xs {
+        let angle_on_spiral = angle_on_spiral(cut, angle, x);
+
+        let target_depth = depth_at(cut, x);
+        let mut depth = 0.0;
+        // Take passes until we've consumed the whole target depth
+        while depth < target_depth {
+            depth = (depth + cut.max_stepdown).clamp(0.0, target_depth);
+            let angle_end = angle_on_spiral + 360.0 / cut.flutes as f64 - tool_reach(cut, depth);
+            pass_at_depth(cut, file, x, depth, angle_on_spiral, angle_end)?;
+        }
+    }
+
+    // Go home between teeth
+    go_home(file, cut.home)?;
+    writeln!(file)?;
+
+    Ok(())
+}
+
+/// Cut all the flutes from `first_flute` on, one at a time, going home between them
+pub fn cut_flutes(file: &mut dyn Write, cut: &FluteCut) -> Result<()> {
+    for (i, angle) in flute_angles(cut)
+        .into_iter()
+        .enumerate()
+        .skip(cut.first_flute as usize)
+    {
+        gcode_comment(
+            file,
+            &format!("Flute {} of {}", i + 1, cut.flutes),
+            cut.dialect,
+        )?;
+        cut_flute(cut, file, angle)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cut(starts: u32) -> FluteCut {
+        FluteCut {
+            flutes: 8,
+            starts,
+            depth: 2.0,
+            depth_end: 2.0,
+            len: 30.0,
+            dia: 20.0,
+            feed: 60.0,
+            tool_dia: 3.0,
+            tool_type: ToolType::Ball,
+            max_stepdown: 0.5,
+            max_stepover: 0.25,
+            spiral_angle: 30.0,
+            relief_angle: None,
+            spacing_variation: 0.0,
+            seed: 1,
+            from_far_end: false,
+            first_flute: 0,
+            home: crate::DEFAULT_HOME,
+            units: Units::Metric,
+            dialect: Dialect::LinuxCnc,
+        }
+    }
+
+    fn assert_close(got: f64, expected: f64) {
+        assert!(
+            (got - expected).abs() < 1e-9,
+            "got {}, expected {}",
+            got,
+            expected
+        );
+    }
+
+    #[test]
+    fn two_starts_double_the_lead() {
+        // Distance along X for one turn of A
+        let lead = |cut: &FluteCut| 360.0 / (angle_on_spiral(cut, 10.0, 1.0) - 10.0);
+        let single = lead(&cut(1));
+        assert_close(single, PI * 20.0 / 30.0f64.to_radians().tan());
+        assert_close(lead(&cut(2)), 2.0 * single);
+        // Either way, each flute starts at its angle on the right face
+        assert_close(angle_on_spiral(&cut(2), 10.0, 0.0), 10.0);
+    }
+
+    #[test]
+    fn straight_flutes_stay_at_their_angle() {
+        let straight = FluteCut {
+            spiral_angle: 0.0,
+            ..cut(1)
+        };
+        assert_close(angle_on_spiral(&straight, 45.0, -20.0), 45.0);
+    }
+
+    #[test]
+    fn machine_feedrate_along_the_surface() {
+        // 36 degrees round a 20 dia blank is 2 * PI along its surface, while feeding 2 down
+        let len = 2.0f64.hypot(2.0 * PI);
+        assert_close(machine_feedrate(&cut(1), 2.0, 10.0, 46.0), 60.0 / len);
+        assert_close(machine_feedrate(&cut(1), 2.0, 46.0, 10.0), 60.0 / len);
+    }
+
+    #[test]
+    fn relief_curve_falls_away_to_the_floor() {
+        let curve = relief_curve(10.0, 8.0, 10.0, 90.0, 3);
+        assert_eq!(curve.len(), 4);
+        assert_close(curve[0], 10.0);
+        // The radius shrinks by the same proportion for each 30 degrees turned
+        let per_step = (-10.0f64.to_radians().tan() * 30.0f64.to_radians()).exp();
+        assert_close(curve[1], 10.0 * per_step);
+        assert_close(curve[2], 10.0 * per_step * per_step);
+        // Until it would go below the floor
+        assert_close(curve[3], 8.0);
+    }
+
+    #[test]
+    fn depth_at_tapers_along_the_length() {
+        let tapered = FluteCut {
+            depth_end: 1.0,
+            ..cut(1)
+        };
+        assert_close(depth_at(&tapered, 0.0), 2.0);
+        assert_close(depth_at(&tapered, -15.0), 1.5);
+        assert_close(depth_at(&tapered, -30.0), 1.0);
+        // Off either end of the blank, it stays at the depth of that end
+        assert_close(depth_at(&tapered, 1.5), 2.0);
+        assert_close(depth_at(&tapered, -35.0), 1.0);
+    }
+}
@@ -0,0 +1,755 @@
+//! Geometry of module-system spur gears, cut with an involute form cutter, or with a ball end mill following a
+//! cycloidal flank.
+//! Lengths are in mm, like the module, unless noted otherwise.
+use crate::profile::ProfilePoint;
+use std::f64::consts::PI;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Module (in mm) equivalent to a diametral pitch of `dp` teeth per inch of pitch diameter
+pub fn module_from_dp(dp: f64) -> f64 {
+    25.4 / dp
+}
+
+/// Diameter of the pitch circle
+pub fn pitch_diameter(module: f64, teeth: u32) -> f64 {
+    module * teeth as f64
+}
+
+/// Outside diameter of the gear, which is also the diameter to turn the stock to
+pub fn outside_diameter(module: f64, teeth: u32) -> f64 {
+    module * (teeth as f64 + 2.0)
+}
+
+/// Total depth of each tooth space.
+/// Total depth varies from source to source.
+/// Here, I'm using the formula from the Machinery's Handbook, 31st Edition, "Module System Gear Design"
+pub fn total_depth(module: f64) -> f64 {
+    2.157 * module
+}
+
+/// Pitch diameter of a helical gear, cut with a cutter of (normal) `module`.
+/// `helix_angle` is in degrees; at zero this is the same as `pitch_diameter`.
+pub fn helical_pitch_diameter(module: f64, teeth: u32, helix_angle: f64) -> f64 {
+    pitch_diameter(module, teeth) / helix_angle.to_radians().cos()
+}
+
+/// Outside diameter of a helical gear, cut with a cutter of (normal) `module`
+pub fn helical_outside_diameter(module: f64, teeth: u32, helix_angle: f64) -> f64 {
+    helical_pitch_diameter(module, teeth, helix_angle) + 2.0 * module
+}
+
+/// Smallest and largest profile shift coefficients we'll cut. Outside this range teeth get pointed (for big positive
+/// shifts) or badly undercut (for big negative ones).
+pub const PROFILE_SHIFT_RANGE: (f64, f64) = (-0.5, 1.0);
+
+/// How far (radially) a profile shift of `shift` moves the cutter out from where it would finish on an unshifted gear.
+/// The addendum grows, and dedendum shrinks, by this amount, so the blank needs turning to an outside diameter twice
+/// this much bigger, while the total depth of cut stays the same.
+///
+/// Meshed with an unshifted gear the center distance grows by roughly the same amount. The exact figure comes from
+/// the operating pressure angle, but this is close for small shifts.
+pub fn profile_shift_offset(module: f64, shift: f64) -> f64 {
+    shift * module
+}
+
+/// Fewest teeth a gear at `pressure_angle` (in degrees), with an addendum of `addendum` times the module, can have
+/// without its flanks being undercut: 2 addendum / sin^2(pressure angle). For full-depth teeth that's about 17 at 20
+/// degrees and 32 at 14.5 degrees, and for 20 degree stub teeth about 14.
+pub fn min_teeth_without_undercut(pressure_angle: f64, addendum: f64) -> f64 {
+    2.0 * addendum / pressure_angle.to_radians().sin().powi(2)
+}
+
+/// Smallest profile shift that avoids undercut on a gear of `teeth`, with an addendum of `addendum` times the module:
+/// addendum - teeth sin^2(pressure angle) / 2. It's zero (or negative) if there are enough teeth not to need one.
+pub fn min_profile_shift(teeth: u32, pressure_angle: f64, addendum: f64) -> f64 {
+    addendum - teeth as f64 * pressure_angle.to_radians().sin().powi(2) / 2.0
+}
+
+/// Extra depth to cut so the gear meshes with `backlash` of play, in the same units as `backlash`.
+///
+/// Backlash is measured along the pitch circle. A form cutter can't thin the teeth directly, but sinking it deeper
+/// moves both flanks of the tooth space in along the pressure angle, which thins the teeth on either side. Doing that
+/// by `d` opens the space at the pitch circle by `2 * d * tan(pressure angle)`, so that's the depth we need for the
+/// whole backlash to be taken up by this gear (leaving the mating gear cut to full thickness).
+pub fn backlash_extra_depth(backlash: f64, pressure_angle: f64) -> f64 {
+    backlash / (2.0 * pressure_angle.to_radians().tan())
+}
+
+/// Lead of the helix: how far along the gear's axis a tooth travels in one full turn, in the same units as
+/// `pitch_dia`. Infinite for straight teeth.
+pub fn helix_lead(pitch_dia: f64, helix_angle: f64) -> f64 {
+    PI * pitch_dia / helix_angle.to_radians().tan()
+}
+
+/// Lead of a worm with `starts` threads: how far along its axis a thread travels in one full turn. Each thread is one
+/// rack pitch from the next, so the worm meshes with a gear of the same module.
+pub fn worm_lead(module: f64, starts: u32) -> f64 {
+    rack_pitch(module) * starts as f64
+}
+
+/// Lead angle of a worm's thread, in degrees, measured from a plane square to its axis at the pitch diameter. The
+/// gear it drives is cut with this as its helix angle.
+pub fn worm_lead_angle(module: f64, starts: u32, pitch_dia: f64) -> f64 {
+    (worm_lead(module, starts) / (PI * pitch_dia))
+        .atan()
+        .to_degrees()
+}
+
+/// Involute function, inv(a) = tan(a) - a, of an angle in radians
+pub fn involute(angle: f64) -> f64 {
+    angle.tan() - angle
+}
+
+/// Angle (in radians) whose involute function is `inv`, found by Newton's method
+pub fn inverse_involute(inv: f64) -> f64 {
+    // inv(a) is close to a^3 / 3 for small angles, which makes a good first guess
+    let mut angle = (3.0 * inv).cbrt();
+    for _ in 0..20 {
+        let tan = angle.tan();
+        angle -= (tan - angle - inv) / (tan * tan);
+    }
+    angle
+}
+
+/// Tooth thickness, measured along the pitch circle, of a gear cut with a profile shift of `shift`.
+/// The shift moves the cutter out, so the tooth space at the pitch circle narrows by `2 * shift * module * tan(pressure
+/// angle)`, and the tooth thickens by the same amount.
+pub fn tooth_thickness(module: f64, pressure_angle: f64, shift: f64) -> f64 {
+    module * (PI / 2.0 + 2.0 * shift * pressure_angle.to_radians().tan())
+}
+
+/// Angle (in degrees) across a tooth at `dia`, for teeth `thickness` thick at the pitch circle (see `tooth_thickness`).
+/// The involute flanks close in on each other going out from the base circle, so this shrinks with the diameter, down
+/// to zero where the tooth comes to a point.
+pub fn tooth_angle_at(
+    module: f64,
+    teeth: u32,
+    pressure_angle: f64,
+    thickness: f64,
+    dia: f64,
+) -> f64 {
+    let pa = pressure_angle.to_radians();
+    let pitch_dia = pitch_diameter(module, teeth);
+    let flank_angle = (pitch_dia * pa.cos() / dia).min(1.0).acos();
+    (2.0 * (thickness / pitch_dia + involute(pa) - involute(flank_angle)))
+        .max(0.0)
+        .to_degrees()
+}
+
+/// Number of teeth to measure the base tangent span over, so the caliper jaws touch the flanks near the pitch circle
+pub fn span_teeth(teeth: u32, pressure_angle: f64) -> u32 {
+    ((teeth as f64 * pressure_angle / 180.0 + 0.5).round() as u32).clamp(1, teeth)
+}
+
+/// Base tangent length: the distance across `span` teeth, measured with the caliper jaws flat on the outside flanks,
+/// for teeth `thickness` thick at the pitch circle (see `tooth_thickness`).
+///
+/// The jaws are tangent to the base circle, so this is (`span` - 1) base pitches plus the thickness of one tooth at
+/// the base circle.
+pub fn base_tangent_span(
+    module: f64,
+    teeth: u32,
+    pressure_angle: f64,
+    thickness: f64,
+    span: u32,
+) -> f64 {
+    let pa = pressure_angle.to_radians();
+    let base_pitch = PI * module * pa.cos();
+    let base_thickness = pa.cos() * (thickness + pitch_diameter(module, teeth) * involute(pa));
+    (span - 1) as f64 * base_pitch + base_thickness
+}
+
+/// Measurement over two pins of `pin_dia` laid in opposite tooth spaces (or as near to opposite as an odd number of
+/// teeth allows), for teeth `thickness` thick at the pitch circle.
+///
+/// From the Machinery's Handbook, "Checking Spur Gear Size by Measurement Over Pins": the pin centers sit where the
+/// involute angle is inv(phi) = t / D + inv(pressure angle) + pin_dia / Db - pi / teeth.
+pub fn measurement_over_pins(
+    module: f64,
+    teeth: u32,
+    pressure_angle: f64,
+    thickness: f64,
+    pin_dia: f64,
+) -> f64 {
+    let pa = pressure_angle.to_radians();
+    let n = teeth as f64;
+    let pitch_dia = pitch_diameter(module, teeth);
+    let base_dia = pitch_dia * pa.cos();
+    let phi = inverse_involute(thickness / pitch_dia + involute(pa) + pin_dia / base_dia - PI / n);
+    // Distance between the pin centers
+    let centers = base_dia / phi.cos();
+    if teeth.is_multiple_of(2) {
+        centers + pin_dia
+    } else {
+        centers * (PI / (2.0 * n)).cos() + pin_dia
+    }
+}
+
+/// Tooth proportions, which set how deep each tooth space is cut
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthStandard {
+    /// Full depth teeth, with a total depth of 2.157 module at 14.5 or 20 degrees
+    FullDepth,
+    /// 20 degree stub teeth: addendum 0.8 module, dedendum 1.0 module
+    Stub,
+    /// A total depth of this many times the module
+    Custom(f64),
+}
+
+impl DepthStandard {
+    /// Total depth of the tooth space, as a multiple of the module.
+    ///
+    /// Full depth comes from the Machinery's Handbook, 31st Edition, "Module System Gear Design", and matches the
+    /// 2.157/P whole depth of the 14.5 degree full depth system. The stub proportions are the American standard
+    /// 20 degree stub tooth (0.8/P addendum, 1/P dedendum), which is only defined at 20 degrees.
+    pub fn depth_coefficient(self, pressure_angle: f64) -> Result<f64, String> {
+        match self {
+            DepthStandard::FullDepth => Ok(2.157),
+            DepthStandard::Stub if (pressure_angle - 20.0).abs() < 1e-9 => Ok(1.8),
+            DepthStandard::Stub => Err(format!(
+                "Stub teeth are only standard at 20 degrees, not {}",
+                pressure_angle
+            )),
+            DepthStandard::Custom(c) if c > 0.0 => Ok(c),
+            DepthStandard::Custom(c) => Err(format!("Depth coefficient {} must be positive", c)),
+        }
+    }
+    /// Addendum of the teeth, as a multiple of the module: 0.8 for stub teeth, and otherwise the standard 1.0, with a
+    /// custom depth taken as changing only the dedendum
+    pub fn addendum_coefficient(self) -> f64 {
+        match self {
+            DepthStandard::Stub => 0.8,
+            DepthStandard::FullDepth | DepthStandard::Custom(_) => 1.0,
+        }
+    }
+}
+
+impl FromStr for DepthStandard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "full" | "full-depth" => Ok(DepthStandard::FullDepth),
+            "stub" => Ok(DepthStandard::Stub),
+            other => other.parse().map(DepthStandard::Custom).map_err(|_| {
+                format!(
+                    "Unknown depth standard {}, expected full-depth, stub, or a depth coefficient",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+/// Total depth of each tooth space, cut to `standard` at `pressure_angle` (in degrees)
+pub fn standard_total_depth(
+    module: f64,
+    standard: DepthStandard,
+    pressure_angle: f64,
+) -> Result<f64, String> {
+    Ok(standard.depth_coefficient(pressure_angle)? * module)
+}
+
+/// Angle between adjacent teeth, in degrees
+pub fn tooth_angle(teeth: u32) -> f64 {
+    360.0 / teeth as f64
+}
+
+/// Distance between tooth centers along a rack, which is the same as the circular pitch of a gear it meshes with
+pub fn rack_pitch(module: f64) -> f64 {
+    PI * module
+}
+
+/// Number of whole teeth that fit in `length` (in mm) of rack
+pub fn rack_teeth(module: f64, length: f64) -> u32 {
+    (length / rack_pitch(module)).floor() as u32
+}
+
+/// Brown & Sharpe involute cutter numbers, and the (inclusive) range of tooth counts each one cuts.
+/// The #1 cutter covers everything from 135 teeth up to a rack.
+const CUTTER_RANGES: [(u32, u32, u32); 8] = [
+    (8, 12, 13),
+    (7, 14, 16),
+    (6, 17, 20),
+    (5, 21, 25),
+    (4, 26, 34),
+    (3, 35, 54),
+    (2, 55, 134),
+    (1, 135, u32::MAX),
+];
+
+/// The Brown & Sharpe cutter number for a gear of `teeth`, and the range of tooth counts that cutter covers.
+/// `None` for fewer than 12 teeth, which the standard set doesn't cover.
+pub fn cutter_number(teeth: u32) -> Option<(u32, RangeInclusive<u32>)> {
+    CUTTER_RANGES
+        .iter()
+        .find(|(_, min, max)| (*min..=*max).contains(&teeth))
+        .map(|&(number, min, max)| (number, min..=max))
+}
+
+/// Number of teeth on the spur gear whose tooth spaces match those of a helical gear, cut normal to the helix, which is
+/// what chooses the cutter for a helical gear: `teeth / cos^3(helix angle)`
+pub fn equivalent_teeth(teeth: u32, helix_angle: f64) -> u32 {
+    (teeth as f64 / helix_angle.to_radians().cos().powi(3)).round() as u32
+}
+
+/// Half the length of the chord the cutter makes across the top of the stock, when it's set `depth` into it.
+/// This is how far the cutting edge reaches ahead of (and behind) the cutter's center along the cut.
+///
+/// The cutter is a disc of radius R lying in the X-Y plane, with the top of the stock a line of constant Y. Cutting
+/// `depth` deep puts the cutter's center R - `depth` above that line, so the cutter crosses it along a chord with
+/// half-length sqrt(R^2 - (R - depth)^2).
+///
+/// `cutter_dia` and `depth` can be in any units, as long as they match.
+pub fn cutter_half_chord(cutter_dia: f64, depth: f64) -> f64 {
+    let r = cutter_dia / 2.0;
+    // Cutting deeper than the radius doesn't make the cutter any wider
+    let height = (r - depth).max(0.0);
+    (r * r - height * height).sqrt()
+}
+
+/// X position (measured from the end of the stock) to start a pass from, so the cutter starts `clearance` away
+/// from the stock when it's set `depth` into it: the end of the chord (see `cutter_half_chord`) nearest the stock has
+/// to sit `clearance` past the end of the stock.
+pub fn cutter_start_x(cutter_dia: f64, depth: f64, clearance: f64) -> f64 {
+    cutter_half_chord(cutter_dia, depth) + clearance
+}
+
+/// The +Y flank of a cycloidal tooth space, from the middle of its bottom out to where it meets the outside diameter
+/// (or the middle of the tooth, if that comes first), with points about `step` apart. The space is centered on +Z, with
+/// the gear's axis at the origin, like a sprocket's gap in `profile`. The -Y flank is its mirror image.
+///
+/// Above the pitch circle the flank is the epicycloid a point on a circle of `generating_dia` traces rolling round the
+/// outside of the pitch circle, and below it the hypocycloid the same circle traces rolling round the inside. A
+/// generating circle half the pitch diameter makes the flanks below the pitch circle straight and radial, which is
+/// the usual clockmakers' choice for pinions. The tooth and the space are the same width at the pitch circle, the
+/// addendum is one module, and the space is `dedendum` deep below the pitch circle, with a `fillet_radius` between the
+/// flank and the bottom.
+///
+/// Fails if the generating circle is too big (it would lean the flanks into the tooth below the pitch circle), too
+/// small to reach down to the bottom of the space, or if the fillet doesn't fit in the bottom of the space.
+pub fn cycloidal_gap_flank(
+    module: f64,
+    teeth: u32,
+    generating_dia: f64,
+    dedendum: f64,
+    fillet_radius: f64,
+    step: f64,
+) -> Result<Vec<ProfilePoint>, String> {
+    let pitch_radius = pitch_diameter(module, teeth) / 2.0;
+    let rho = generating_dia / 2.0;
+    if rho <= 0.0 || rho > pitch_radius / 2.0 {
+        return Err(format!(
+            "The generating circle must be more than 0, and no more than half the {}mm pitch diameter",
+            pitch_diameter(module, teeth)
+        ));
+    }
+    if dedendum >= generating_dia {
+        return Err(format!(
+            "A {}mm generating circle is too small to reach {}mm down to the bottom of the tooth space",
+            generating_dia, dedendum
+        ));
+    }
+    let root = pitch_radius - dedendum;
+    let outside_radius = outside_diameter(module, teeth) / 2.0;
+    let half_tooth = PI / teeth as f64;
+
+    // Place a point that's (re, im) in a frame with the flank crossing the pitch circle on +Z. The tooth and the space
+    // are the same width at the pitch circle, so the flank really crosses it a quarter of a tooth angle round, at +Y.
+    let (sin, cos) = (half_tooth / 2.0).sin_cos();
+    let place = |re: f64, im: f64| (re * sin + im * cos, re * cos - im * sin);
+    // Each curve starts from the pitch circle at t = 0, and gets furthest from it after t = `roll`, once the generating
+    // circle has turned half way round
+    let roll = PI * rho / pitch_radius;
+    let hypocycloid = |t: f64| {
+        let (big, k) = (pitch_radius - rho, (pitch_radius - rho) / rho);
+        place(
+            big * t.cos() + rho * (k * t).cos(),
+            big * t.sin() - rho * (k * t).sin(),
+        )
+    };
+    let epicycloid = |t: f64| {
+        let (big, k) = (pitch_radius + rho, (pitch_radius + rho) / rho);
+        place(
+            big * t.cos() - rho * (k * t).cos(),
+            big * t.sin() - rho * (k * t).sin(),
+        )
+    };
+    let outside = |p: (f64, f64)| p.0.hypot(p.1) > outside_radius || p.0.atan2(p.1) > half_tooth;
+
+    // Trace the flank finely from the bottom of the hypocycloid up, no more than a tenth of a step between points.
+    // Neither curve moves faster than twice the radius of the generating circle's center.
+    let dt = step / 10.0 / (2.0 * (pitch_radius + rho));
+    let steps = (roll / dt).ceil() as u32;
+    let mut fine: Vec<(f64, f64)> = (0..=steps)
+        .map(|i| hypocycloid(-roll + roll * i as f64 / steps as f64))
+        .collect();
+    for i in 1..=steps {
+        let (t_last, t) = (
+            roll * (i - 1) as f64 / steps as f64,
+            roll * i as f64 / steps as f64,
+        );
+        if outside(epicycloid(t)) {
+            // Find where we cross the edge, and stop there
+            let (mut lo, mut hi) = (t_last, t);
+            for _ in 0..50 {
+                let mid = (lo + hi) / 2.0;
+                if outside(epicycloid(mid)) {
+                    hi = mid;
+                } else {
+                    lo = mid;
+                }
+            }
+            fine.push(epicycloid(lo));
+            break;
+        }
+        fine.push(epicycloid(t));
+    }
+
+    // Unit normal out of the tooth into the space at each point, square to the flank on its left going up
+    let normal = |i: usize| {
+        let (a, b) = (fine[i.saturating_sub(1)], fine[(i + 1).min(fine.len() - 1)]);
+        let len = (b.0 - a.0).hypot(b.1 - a.1);
+        (-(b.1 - a.1) / len, (b.0 - a.0) / len)
+    };
+    // The fillet meets the flank where a circle of its radius, against the flank, just touches the bottom
+    let start = (0..fine.len())
+        .find(|&i| {
+            let n = normal(i);
+            (fine[i].0 + fillet_radius * n.0).hypot(fine[i].1 + fillet_radius * n.1)
+                >= root + fillet_radius
+        })
+        .ok_or_else(|| "The tooth space doesn't reach the outside diameter".to_string())?;
+    let n = normal(start);
+    let tangent = fine[start];
+    let center = (
+        tangent.0 + fillet_radius * n.0,
+        tangent.1 + fillet_radius * n.1,
+    );
+    let bottom_angle = center.0.atan2(center.1);
+    if bottom_angle <= 0.0 {
+        return Err(format!(
+            "A {}mm radius fillet is too big to fit in the bottom of the tooth space",
+            fillet_radius
+        ));
+    }
+
+    // Round the bottom of the space from its middle to the fillet, with the normals pointing out from the axis
+    let mut points = vec![];
+    let bottom_steps = (root * bottom_angle / step).ceil().max(1.0) as u32;
+    for i in 0..=bottom_steps {
+        let (sin, cos) = (bottom_angle * i as f64 / bottom_steps as f64).sin_cos();
+        points.push(ProfilePoint {
+            y: root * sin,
+            z: root * cos,
+            normal: (sin, cos),
+        });
+    }
+    // Then round the fillet, with the normals pointing in to its center
+    if fillet_radius > 0.0 {
+        let from = (-bottom_angle.cos()).atan2(-bottom_angle.sin());
+        let mut sweep = (tangent.1 - center.1).atan2(tangent.0 - center.0) - from;
+        if sweep > PI {
+            sweep -= 2.0 * PI;
+        } else if sweep < -PI {
+            sweep += 2.0 * PI;
+        }
+        let fillet_steps = (fillet_radius * sweep.abs() / step).ceil().max(1.0) as u32;
+        for i in 1..=fillet_steps {
+            let (sin, cos) = (from + sweep * i as f64 / fillet_steps as f64).sin_cos();
+            points.push(ProfilePoint {
+                y: center.0 + fillet_radius * cos,
+                z: center.1 + fillet_radius * sin,
+                normal: (-cos, -sin),
+            });
+        }
+    }
+    // Then up the flank, a step at a time, finishing right at its end
+    let mut along = 0.0;
+    for i in start + 1..fine.len() {
+        along += (fine[i].0 - fine[i - 1].0).hypot(fine[i].1 - fine[i - 1].1);
+        if along >= step || i == fine.len() - 1 {
+            points.push(ProfilePoint {
+                y: fine[i].0,
+                z: fine[i].1,
+                normal: normal(i),
+            });
+            along = 0.0;
+        }
+    }
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(got: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (got - expected).abs() <= tolerance,
+            "got {}, expected {}",
+            got,
+            expected
+        );
+    }
+
+    #[test]
+    fn base_tangent_span_of_module_1_gears() {
+        // The span table for standard 20 degree gears of module 1: W over 3 teeth of a 20 tooth gear, and over 4 of a
+        // 30 tooth gear
+        let thickness = tooth_thickness(1.0, 20.0, 0.0);
+        assert_eq!(span_teeth(20, 20.0), 3);
+        assert_close(base_tangent_span(1.0, 20, 20.0, thickness, 3), 7.6604, 1e-4);
+        assert_eq!(span_teeth(30, 20.0), 4);
+        assert_close(
+            base_tangent_span(1.0, 30, 20.0, thickness, 4),
+            10.7526,
+            1e-4,
+        );
+        // It scales with the module
+        let thickness = tooth_thickness(2.5, 20.0, 0.0);
+        assert_close(
+            base_tangent_span(2.5, 20, 20.0, thickness, 3),
+            2.5 * 7.6604,
+            1e-3,
+        );
+    }
+
+    #[test]
+    fn measurement_over_pins_of_1_dp_gears() {
+        // The handbook's tables are for 1 diametral pitch and 1.728in pins, so work in inches with a module of 1in.
+        // Standard 20 degree gears of 20 teeth (pins opposite) and 21 teeth (pins as near opposite as they go).
+        let thickness = tooth_thickness(1.0, 20.0, 0.0);
+        assert_close(
+            measurement_over_pins(1.0, 20, 20.0, thickness, 1.728),
+            22.3900,
+            1e-4,
+        );
+        assert_close(
+            measurement_over_pins(1.0, 21, 20.0, thickness, 1.728),
+            23.3321,
+            1e-4,
+        );
+        // And for other pitches they're divided by the pitch: an 8 DP gear of 32 teeth, with 0.216in pins
+        let thickness = tooth_thickness(1.0 / 8.0, 20.0, 0.0);
+        assert_close(
+            measurement_over_pins(1.0 / 8.0, 32, 20.0, thickness, 0.216),
+            4.3016,
+            1e-4,
+        );
+    }
+
+    #[test]
+    fn dimensions_of_a_module_1_gear() {
+        // 20 teeth of module 1: 20mm pitch diameter, and one module of addendum either side
+        assert_close(pitch_diameter(1.0, 20), 20.0, 1e-12);
+        assert_close(outside_diameter(1.0, 20), 22.0, 1e-12);
+        assert_close(total_depth(1.0), 2.157, 1e-12);
+        assert_close(tooth_angle(20), 18.0, 1e-12);
+        // Everything scales with the module
+        assert_close(outside_diameter(2.5, 20), 55.0, 1e-12);
+        assert_close(total_depth(2.5), 5.3925, 1e-12);
+        // And a helical gear with no helix is a spur gear
+        assert_close(helical_outside_diameter(1.0, 20, 0.0), 22.0, 1e-12);
+    }
+
+    #[test]
+    fn cutter_starts_clear_of_the_stock() {
+        // Round the edge of a 50mm cutter set into stock whose top is Y0 and whose end is X0, the nearest it comes to
+        // the stock (X <= 0 and Y <= 0) is the clearance. Checking every tenth of a degree comes within 0.05 of it.
+        for depth in [0.5, 2.157, 10.0, 30.0] {
+            let x_start = cutter_start_x(50.0, depth, 4.0);
+            let center = (x_start, 25.0 - depth);
+            let nearest = (0..3600)
+                .map(|i| (i as f64 / 10.0).to_radians().sin_cos())
+                .map(|(sin, cos)| (center.0 + 25.0 * cos, center.1 + 25.0 * sin))
+                .filter(|p| p.1 <= 0.0)
+                .map(|p| p.0)
+                .fold(f64::MAX, f64::min);
+            assert!(
+                (4.0 - 1e-9..4.05).contains(&nearest),
+                "{} deep, {} clear",
+                depth,
+                nearest
+            );
+        }
+        // The full-depth pass of a module 1 gear with a 50mm cutter starts with its center 14.1586 off the end
+        assert_close(cutter_start_x(50.0, 2.157, 4.0), 14.1586, 1e-4);
+        // Deeper than the cutter's radius, it's clear by its radius
+        assert_close(cutter_start_x(50.0, 30.0, 4.0), 29.0, 1e-12);
+    }
+
+    #[test]
+    fn profile_shifted_pinion() {
+        // The usual worked example: a 12 tooth, module 2, 20 degree pinion shifted +0.3 to avoid undercut. The cutter
+        // finishes 0.6mm further out, and the tooth is 2 (PI / 2 + 2 0.3 tan 20) = 3.5784mm thick at the pitch circle.
+        assert_close(profile_shift_offset(2.0, 0.3), 0.6, 1e-12);
+        let thickness = tooth_thickness(2.0, 20.0, 0.3);
+        assert_close(thickness, 3.5784, 1e-4);
+        // That's its angle across the 24mm pitch circle, where an unshifted tooth is half the 30 degree tooth angle
+        assert_close(
+            tooth_angle_at(2.0, 12, 20.0, thickness, 24.0),
+            (2.0 * thickness / 24.0).to_degrees(),
+            1e-9,
+        );
+        let unshifted = tooth_thickness(2.0, 20.0, 0.0);
+        assert_close(tooth_angle_at(2.0, 12, 20.0, unshifted, 24.0), 15.0, 1e-9);
+        // Going out, the flanks close in, until the tooth comes to a point
+        let at_tip = tooth_angle_at(2.0, 12, 20.0, thickness, 24.0 + 4.0 + 1.2);
+        assert!(at_tip > 0.0 && at_tip < 15.0);
+        assert_eq!(tooth_angle_at(2.0, 12, 20.0, thickness, 40.0), 0.0);
+    }
+
+    #[test]
+    fn backlash_sinks_the_cutter() {
+        // No backlash leaves the depth alone
+        assert_eq!(backlash_extra_depth(0.0, 20.0), 0.0);
+        assert_eq!(backlash_extra_depth(0.0, 14.5), 0.0);
+        // Sinking the cutter d deeper opens the space by 2 d tan(pressure angle) at the pitch circle
+        let extra = backlash_extra_depth(0.1, 20.0);
+        assert_close(2.0 * extra * 20f64.to_radians().tan(), 0.1, 1e-12);
+        assert_close(extra, 0.1374, 1e-4);
+    }
+
+    #[test]
+    fn depth_standards() {
+        assert_eq!(DepthStandard::FullDepth.depth_coefficient(20.0), Ok(2.157));
+        assert_eq!(DepthStandard::FullDepth.depth_coefficient(14.5), Ok(2.157));
+        // Stub teeth are 0.8 module addendum and 1 module dedendum, and only at 20 degrees
+        assert_eq!(DepthStandard::Stub.depth_coefficient(20.0), Ok(1.8));
+        assert!(DepthStandard::Stub.depth_coefficient(14.5).is_err());
+        assert_eq!(DepthStandard::Stub.addendum_coefficient(), 0.8);
+        assert_eq!(DepthStandard::FullDepth.addendum_coefficient(), 1.0);
+        assert_eq!(DepthStandard::Custom(2.25).addendum_coefficient(), 1.0);
+        assert_eq!(
+            DepthStandard::Custom(2.25).depth_coefficient(14.5),
+            Ok(2.25)
+        );
+        assert!(DepthStandard::Custom(0.0).depth_coefficient(20.0).is_err());
+        assert!(DepthStandard::Custom(-1.0).depth_coefficient(20.0).is_err());
+        assert_close(
+            standard_total_depth(2.0, DepthStandard::Stub, 20.0).unwrap(),
+            3.6,
+            1e-12,
+        );
+
+        assert_eq!("full-depth".parse(), Ok(DepthStandard::FullDepth));
+        assert_eq!("Full".parse(), Ok(DepthStandard::FullDepth));
+        assert_eq!("STUB".parse(), Ok(DepthStandard::Stub));
+        assert_eq!("2.25".parse(), Ok(DepthStandard::Custom(2.25)));
+        assert!("deep".parse::<DepthStandard>().is_err());
+    }
+
+    #[test]
+    fn undercut_limits() {
+        // About 17 teeth at 20 degrees, and 32 at 14.5
+        assert_close(min_teeth_without_undercut(20.0, 1.0), 17.097, 1e-3);
+        assert_close(min_teeth_without_undercut(14.5, 1.0), 31.9, 1e-1);
+        // A 12 tooth pinion needs 1 - 12 / 17.097 = 0.298 of shift, and a 20 tooth gear needs none
+        assert_close(min_profile_shift(12, 20.0, 1.0), 0.2981, 1e-4);
+        assert!(min_profile_shift(20, 20.0, 1.0) < 0.0);
+        // The shorter addendum of stub teeth lets a 14 tooth pinion go unshifted, where full-depth teeth need 0.18
+        let stub = DepthStandard::Stub.addendum_coefficient();
+        assert_close(min_teeth_without_undercut(20.0, stub), 13.678, 1e-3);
+        assert!(min_profile_shift(14, 20.0, stub) < 0.0);
+        assert_close(min_profile_shift(14, 20.0, 1.0), 0.1811, 1e-4);
+        assert_close(min_profile_shift(12, 20.0, stub), 0.0981, 1e-4);
+        // With just enough teeth, no shift is needed
+        for addendum in [0.8, 1.0] {
+            let teeth = min_teeth_without_undercut(20.0, addendum);
+            let sin2 = 20f64.to_radians().sin().powi(2);
+            assert_close(addendum - teeth * sin2 / 2.0, 0.0, 1e-12);
+        }
+        // Shifting by the minimum moves the cutter out that many modules
+        assert_close(
+            profile_shift_offset(3.0, min_profile_shift(12, 20.0, 1.0)),
+            0.8944,
+            1e-4,
+        );
+        assert_eq!(profile_shift_offset(3.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn diametral_pitch() {
+        // 24 DP is a little over module 1, and 25.4 DP is exactly it
+        assert_close(module_from_dp(24.0), 1.0583, 1e-4);
+        assert_close(module_from_dp(25.4), 1.0, 1e-12);
+        // A 1 DP gear of 20 teeth has a 20in pitch diameter
+        assert_close(pitch_diameter(module_from_dp(1.0), 20), 20.0 * 25.4, 1e-9);
+    }
+
+    #[test]
+    fn cutter_numbers_at_each_boundary() {
+        assert_eq!(cutter_number(11), None);
+        let boundaries = [
+            (12, 8),
+            (13, 8),
+            (14, 7),
+            (16, 7),
+            (17, 6),
+            (20, 6),
+            (21, 5),
+            (25, 5),
+            (26, 4),
+            (34, 4),
+            (35, 3),
+            (54, 3),
+            (55, 2),
+            (134, 2),
+            (135, 1),
+            (10_000, 1),
+        ];
+        for (teeth, number) in boundaries {
+            let (got, range) = cutter_number(teeth).unwrap();
+            assert_eq!(got, number, "{} teeth", teeth);
+            assert!(range.contains(&teeth));
+        }
+        assert_eq!(cutter_number(20), Some((6, 17..=20)));
+        // A 30 degree helical gear of 20 teeth cuts like a spur gear of 31
+        assert_eq!(equivalent_teeth(20, 30.0), 31);
+        assert_eq!(cutter_number(equivalent_teeth(20, 30.0)).unwrap().0, 4);
+    }
+
+    #[test]
+    fn cycloidal_flank_of_a_pinion() {
+        // 20 teeth of module 1, with the clockmakers' generating circle half the 20mm pitch diameter
+        let flank = cycloidal_gap_flank(1.0, 20, 20.0 / 2.0, 1.25, 0.3, 0.05).unwrap();
+        // It starts in the middle of the bottom of the space, facing out
+        let first = flank[0];
+        assert_close(first.y, 0.0, 1e-12);
+        assert_close(first.z, 10.0 - 1.25, 1e-12);
+        assert_close(first.normal.0, 0.0, 1e-12);
+        assert_close(first.normal.1, 1.0, 1e-12);
+        for p in &flank {
+            assert_close(p.normal.0.hypot(p.normal.1), 1.0, 1e-9);
+        }
+        // Below the pitch circle and clear of the fillet, the flank is radial, a quarter of a tooth from the middle of
+        // the space so the space and the tooth are the same width
+        let radial: Vec<_> = flank
+            .iter()
+            .filter(|p| (9.1..10.0).contains(&p.y.hypot(p.z)))
+            .collect();
+        assert!(radial.len() > 10);
+        for p in radial {
+            assert_close(p.y.atan2(p.z), PI / 40.0, 1e-6);
+        }
+        // The last point is on the outside diameter, or in the middle of the tooth
+        let last = flank.last().unwrap();
+        let (radius, angle) = (last.y.hypot(last.z), last.y.atan2(last.z));
+        assert!((radius - 11.0).abs() < 1e-6 || (angle - PI / 20.0).abs() < 1e-6);
+        assert!(radius <= 11.0 + 1e-6 && angle <= PI / 20.0 + 1e-6);
+    }
+
+    #[test]
+    fn cycloidal_flank_errors() {
+        // Generating circles bigger than half the pitch diameter lean the flanks into the tooth
+        assert!(cycloidal_gap_flank(1.0, 20, 10.5, 1.25, 0.3, 0.05).is_err());
+        assert!(cycloidal_gap_flank(1.0, 20, 0.0, 1.25, 0.3, 0.05).is_err());
+        // Smaller than the dedendum, and they don't reach the bottom of the space
+        assert!(cycloidal_gap_flank(1.0, 20, 1.2, 1.25, 0.3, 0.05).is_err());
+        // And the fillet has to fit in the bottom of the space
+        assert!(cycloidal_gap_flank(1.0, 20, 10.0, 1.25, 2.0, 0.05).is_err());
+        assert!(cycloidal_gap_flank(1.0, 20, 10.0, 1.25, 0.0, 0.05).is_ok());
+    }
+}
@@ -1,71 +1,538 @@
+pub mod cli;
+pub mod dxf;
+pub mod feeds;
+pub mod flutes;
+pub mod gears;
+pub mod lint;
+pub mod patterns;
+pub mod profile;
+pub mod pulleys;
+pub mod sprockets;
+pub mod svg;
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
 use std::io::{Result, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Units of the generated program.
+/// Callers supply values already in these units; this only chooses the G20/G21 mode word and the
+/// precision values are printed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Units {
+    #[default]
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    /// Short unit label for human-readable output, e.g. `mm`
+    pub fn abbrev(self) -> &'static str {
+        match self {
+            Units::Metric => "mm",
+            Units::Imperial => "in",
+        }
+    }
+
+    /// Convert a length given in mm into these units.
+    /// Useful for the fixed clearances the generators use internally.
+    pub fn from_mm(self, mm: f64) -> f64 {
+        match self {
+            Units::Metric => mm,
+            Units::Imperial => mm / 25.4,
+        }
+    }
+
+    /// Convert a length in these units into mm
+    pub fn to_mm(self, v: f64) -> f64 {
+        match self {
+            Units::Metric => v,
+            Units::Imperial => v * 25.4,
+        }
+    }
+
+    fn mode_word(self) -> (&'static str, &'static str) {
+        match self {
+            Units::Metric => ("G21", "Metric"),
+            Units::Imperial => ("G20", "Imperial"),
+        }
+    }
+
+    /// Decimal places to print. A ten-thousandth of an inch is coarser than a micron, so inches get one more.
+    fn decimals(self) -> usize {
+        match self {
+            Units::Metric => 4,
+            Units::Imperial => 5,
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "metric" | "mm" => Ok(Units::Metric),
+            "imperial" | "inch" | "in" => Ok(Units::Imperial),
+            _ => Err(format!("Unknown units {}, expected metric or imperial", s)),
+        }
+    }
+}
+
+/// Work coordinate system (fixture offset) the job runs in, one of G54 to G59
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkOffset(u32);
+
+pub const DEFAULT_WORK_OFFSET: WorkOffset = WorkOffset(54);
+
+impl WorkOffset {
+    /// The `P` number G10 uses for this coordinate system: 1 for G54, up to 6 for G59
+    fn p_number(self) -> u32 {
+        self.0 - 53
+    }
+}
+
+impl FromStr for WorkOffset {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.strip_prefix(['G', 'g']).map(str::parse) {
+            Some(Ok(n)) if (54..=59).contains(&n) => Ok(WorkOffset(n)),
+            _ => Err(format!("Unknown work offset {}, expected G54 to G59", s)),
+        }
+    }
+}
+
+/// Reference position the machine returns to before tool changes and at the end of the job
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Home {
+    G28,
+    G30,
+    /// Rapid Z to this height in machine coordinates (G53), for controls without G28 and G30 positions
+    MachineZ(f64),
+}
+
+pub const DEFAULT_HOME: Home = Home::G30;
+
+impl Home {
+    /// Which of X, Y, Z, and A going home moves, to somewhere that isn't known in work coordinates
+    fn moved_axes(self) -> [bool; 4] {
+        match self {
+            Home::G28 | Home::G30 => [true; 4],
+            Home::MachineZ(_) => [false, false, true, false],
+        }
+    }
+}
+
+impl FromStr for Home {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "G28" => Ok(Home::G28),
+            "G30" => Ok(Home::G30),
+            s => s
+                .replace(' ', "")
+                .strip_prefix("G53")
+                .map(|z| z.strip_prefix("G0").unwrap_or(z))
+                .and_then(|z| z.strip_prefix('Z'))
+                .and_then(|z| z.parse().ok())
+                .map(Home::MachineZ)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown home command {}, expected G28, G30, or G53 Z and a height",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+/// Return to the `home` reference position
+pub fn go_home(file: &mut dyn Write, home: Home) -> Result<()> {
+    match home {
+        Home::G28 => writeln!(file, "G28"),
+        Home::G30 => writeln!(file, "G30"),
+        Home::MachineZ(z) => writeln!(file, "G53 G0 Z{}", z),
+    }
+}
+
+/// Flavor of G code to write, for the control that will run it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    LinuxCnc,
+    /// GRBL, for hobby machines: no tool changer or tool length offsets, no subprograms, and `;` comments
+    Grbl,
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linuxcnc" => Ok(Dialect::LinuxCnc),
+            "grbl" => Ok(Dialect::Grbl),
+            _ => Err(format!("Unknown dialect {}, expected linuxcnc or grbl", s)),
+        }
+    }
+}
 
-pub fn gcode_comment(file: &mut dyn Write, s: &str) -> Result<()> {
-    writeln!(file, "({})", s)
+pub fn gcode_comment(file: &mut dyn Write, s: &str, dialect: Dialect) -> Result<()> {
+    match dialect {
+        Dialect::LinuxCnc => writeln!(file, "({})", s),
+        Dialect::Grbl => writeln!(file, "; {}", s),
+    }
 }
 
-pub fn trailer(file: &mut dyn Write) -> Result<()> {
-    writeln!(file, "M9 (Coolant off)")?;
-    writeln!(file, "M5 (Spindle off)")?;
+/// Write a line of `words`, with a `comment` in the style of the `dialect` after them
+fn commented(file: &mut dyn Write, words: &str, comment: &str, dialect: Dialect) -> Result<()> {
+    match dialect {
+        Dialect::LinuxCnc => writeln!(file, "{} ({})", words, comment),
+        Dialect::Grbl => writeln!(file, "{} ; {}", words, comment),
+    }
+}
+
+pub fn trailer(file: &mut dyn Write, home: Home, dialect: Dialect) -> Result<()> {
+    go_home(file, home)?;
+    writeln!(file)?;
+    commented(file, "M9", "Coolant off", dialect)?;
+    commented(file, "M5", "Spindle off", dialect)?;
     writeln!(file, "M30")?;
 
     Ok(())
 }
 
+/// Direction to run the spindle, looking down from above
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpindleDir {
+    /// M3, for normal right-hand tools
+    Cw,
+    /// M4, for left-hand tools
+    Ccw,
+}
+
+impl SpindleDir {
+    fn word(self) -> &'static str {
+        match self {
+            SpindleDir::Cw => "M3",
+            SpindleDir::Ccw => "M4",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coolant {
+    Off,
+    /// M7
+    Mist,
+    /// M8
+    Flood,
+}
+
+impl Coolant {
+    /// Map the `--coolant` and `--mist` command line flags onto a coolant mode. Mist wins if both are set.
+    pub fn from_flags(flood: bool, mist: bool) -> Coolant {
+        if mist {
+            Coolant::Mist
+        } else if flood {
+            Coolant::Flood
+        } else {
+            Coolant::Off
+        }
+    }
+}
+
+/// How the cutter's teeth meet the work. In conventional milling they cut against the cutter's travel through the work,
+/// starting each chip thin; in climb milling they cut along with it, starting each chip thick. Climbing leaves a better
+/// finish, but needs a rigid machine without backlash, as it pulls the cutter into the work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Milling {
+    Conventional,
+    Climb,
+}
+
+impl Milling {
+    pub fn name(self) -> &'static str {
+        match self {
+            Milling::Conventional => "Conventional",
+            Milling::Climb => "Climb",
+        }
+    }
+
+    /// Map the `--climb` and `--conventional` command line flags onto a milling direction, or `None` for neither
+    pub fn from_flags(climb: bool, conventional: bool) -> Option<Milling> {
+        if climb {
+            Some(Milling::Climb)
+        } else if conventional {
+            Some(Milling::Conventional)
+        } else {
+            None
+        }
+    }
+
+    /// Whether to cut the opposite way to a generator's usual direction, which gives `usual` milling with a clockwise
+    /// spindle, to get this milling with the `spindle` turning as it is
+    pub fn reverses(self, usual: Milling, spindle: SpindleDir) -> bool {
+        (self != usual) != (spindle == SpindleDir::Ccw)
+    }
+}
+
+/// A cutting tool, and how to run it.
+/// A tool with an `rpm` of zero (like a touch probe) is loaded with the spindle, and coolant, left off.
+pub struct Tool {
+    /// Tool number, also used as the length offset register
+    pub number: u32,
+    /// Description of the tool, emitted as a comment
+    pub comment: String,
+    pub rpm: f64,
+    pub direction: SpindleDir,
+    pub coolant: Coolant,
+    /// Seconds to wait after starting the spindle, to let it get up to speed before the first cut
+    pub spindle_dwell: Option<f64>,
+}
+
 pub fn preamble(
     name: &Option<String>,
-    tool: u32,
-    tool_comment: &str,
-    rpm: f64,
-    coolant: bool,
+    tool: &Tool,
+    units: Units,
+    work_offset: WorkOffset,
+    home: Home,
+    dialect: Dialect,
     file: &mut dyn Write,
 ) -> Result<()> {
     // Print out the name as a comment on the first line, if set
     if let Some(name) = &name {
-        gcode_comment(file, name)?;
+        gcode_comment(file, name, dialect)?;
     }
     // Comment with tool information
-    gcode_comment(file, tool_comment)?;
+    gcode_comment(file, &tool.comment, dialect)?;
 
     // Preamble to set the machine into a reasonable mode
-    let preamble_str = "
-G90 (Absolute)
-G54 (G54 Datum)
-G17 (X-Y Plane)
-G40 (No cutter compensation)
-G80 (No cycles)
-G94 (Feed per minute)
-G91.1 (Arc absolute mode)
-G49 (No tool length compensation)
-M9 (Coolant off)
-
-G21 (Metric)
-
-G30 (Go Home Before Starting)
-    ";
-    write!(file, "{}\n\n", preamble_str)?;
-    // Print the tool mode preamble, choosing the tool,
-    // enabling length compensation,
-    // and executing the tool change cycle
-    writeln!(file, "T{} G43 H{} M6", tool, tool)?;
+    let wcs = format!("G{}", work_offset.0);
+    let datum = format!("{} Datum", wcs);
+    let mut modes = vec![
+        ("G90", "Absolute"),
+        (&wcs, &datum),
+        ("G17", "X-Y Plane"),
+        ("G40", "No cutter compensation"),
+        ("G80", "No cycles"),
+        ("G94", "Feed per minute"),
+    ];
+    // GRBL has no tool length offsets, and older versions don't know G91.1
+    if dialect == Dialect::LinuxCnc {
+        modes.push(("G91.1", "Arc absolute mode"));
+        modes.push(("G49", "No tool length compensation"));
+    }
+    modes.push(("M9", "Coolant off"));
+    writeln!(file)?;
+    for (words, comment) in modes {
+        commented(file, words, comment, dialect)?;
+    }
+    writeln!(file)?;
+    let (words, comment) = units.mode_word();
+    commented(file, words, comment, dialect)?;
+    writeln!(file)?;
+
+    go_home(file, home)?;
+    writeln!(file)?;
+    // Nothing is running yet, so the first tool goes straight in. GRBL has only the one tool, which is already loaded.
+    if dialect == Dialect::LinuxCnc {
+        load_tool(file, tool.number, dialect)?;
+    }
+    start_tool(file, tool, dialect)
+}
+
+/// Choose tool `number`, enable its length compensation, and execute the tool change cycle.
+/// GRBL can't change tools itself, so it stops (M0) for the operator to load the tool and set its length.
+fn load_tool(file: &mut dyn Write, number: u32, dialect: Dialect) -> Result<()> {
+    match dialect {
+        Dialect::LinuxCnc => writeln!(file, "T{} G43 H{} M6", number, number),
+        Dialect::Grbl => commented(
+            file,
+            "M0",
+            &format!("Load T{}, and set its Z0", number),
+            dialect,
+        ),
+    }
+}
+
+/// Go home, stop the last tool, load `tool`, and get the spindle (and coolant, if chosen) running
+pub fn tool_change(file: &mut dyn Write, tool: &Tool, home: Home, dialect: Dialect) -> Result<()> {
+    go_home(file, home)?;
+    writeln!(file)?;
+    commented(file, "M5", "Spindle off", dialect)?;
+    commented(file, "M9", "Coolant off", dialect)?;
+
+    load_tool(file, tool.number, dialect)?;
+    start_tool(file, tool, dialect)
+}
+
+/// Get the spindle (and coolant, if chosen) running for `tool`, once it's loaded
+pub fn start_tool(file: &mut dyn Write, tool: &Tool, dialect: Dialect) -> Result<()> {
+    if tool.rpm <= 0.0 {
+        // Not a cutting tool, so make sure nothing is spinning or spraying
+        commented(file, "M5", "Spindle off", dialect)?;
+        return commented(file, "M9", "Coolant off", dialect);
+    }
 
     // Print the Speed preamble, and turn on the spindle
-    writeln!(file, "S{} M3", rpm)?;
+    writeln!(file, "S{} {}", tool.rpm, tool.direction.word())?;
+
+    // If chosen, give the spindle time to come up to speed
+    if let Some(seconds) = tool.spindle_dwell {
+        dwell(file, seconds)?;
+    }
 
     // If chosen, start coolant flowing
-    if coolant {
-        writeln!(file, "M8")?;
+    match tool.coolant {
+        Coolant::Off => {}
+        Coolant::Mist => writeln!(file, "M7")?,
+        Coolant::Flood => writeln!(file, "M8")?,
     }
 
     Ok(())
 }
 
+/// Go home, stop the spindle and coolant, and pause (M0) for the operator to do what `message` says. After cycle start,
+/// `start_tool` or `tool_change` gets the spindle running again.
+pub fn operator_stop(
+    file: &mut dyn Write,
+    home: Home,
+    message: &str,
+    dialect: Dialect,
+) -> Result<()> {
+    go_home(file, home)?;
+    writeln!(file)?;
+    commented(file, "M5", "Spindle off", dialect)?;
+    commented(file, "M9", "Coolant off", dialect)?;
+    commented(file, "M0", message, dialect)
+}
+
+/// Go home, stop the spindle and coolant, and pause with an optional stop (M1) for the operator to do what `message`
+/// says. The control only stops if its optional stop switch is on, and carries straight on if it isn't. Either way
+/// `start_tool` or `tool_change` gets the spindle running again after it.
+pub fn optional_stop(
+    file: &mut dyn Write,
+    home: Home,
+    message: &str,
+    dialect: Dialect,
+) -> Result<()> {
+    go_home(file, home)?;
+    writeln!(file)?;
+    commented(file, "M5", "Spindle off", dialect)?;
+    commented(file, "M9", "Coolant off", dialect)?;
+    commented(file, "M1", message, dialect)
+}
+
+/// What the operator needs to know to set up a job, for `job_header` to write at the top of its program, where it can
+/// be read at the machine
+pub struct JobInfo<'a> {
+    /// The stock to load, like "OD 32.0000mm, 10mm long"
+    pub stock: String,
+    /// Where home is on the stock, like "center of right face of stock"
+    pub datum: String,
+    /// Every tool the job loads, in order, with the feed rate (in units/min) it cuts at
+    pub tools: Vec<(&'a Tool, f64)>,
+    /// Anything else about the job worth knowing at the machine, a line each
+    pub notes: Vec<String>,
+    pub units: Units,
+    pub dialect: Dialect,
+}
+
+/// Stands in for the run time and extents in `job_header`, until `fill_run_summary` can work them out from the
+/// finished program
+const RUN_SUMMARY: &str = "Run time and extents to follow";
+
+/// Write a block of comments summarizing the job's setup from `info`, after the preamble. The estimated run time and
+/// extents go at the end of the block, once the program is finished (see `fill_run_summary`).
+pub fn job_header(file: &mut dyn Write, info: &JobInfo) -> Result<()> {
+    let (u, dialect) = (info.units, info.dialect);
+    writeln!(file)?;
+    gcode_comment(file, "Job setup", dialect)?;
+    gcode_comment(file, &format!("Stock: {}", info.stock), dialect)?;
+    gcode_comment(file, &format!("Home: {}", info.datum), dialect)?;
+    for (tool, feed) in &info.tools {
+        let speed = if tool.rpm > 0.0 {
+            format!("{:.0}rpm", tool.rpm)
+        } else {
+            "spindle off".to_string()
+        };
+        gcode_comment(
+            file,
+            &format!(
+                "{}: {}, feed {:.2}{}/min",
+                tool.comment,
+                speed,
+                feed,
+                u.abbrev()
+            ),
+            dialect,
+        )?;
+    }
+    for note in &info.notes {
+        gcode_comment(file, note, dialect)?;
+    }
+    gcode_comment(file, RUN_SUMMARY, dialect)?;
+    writeln!(file)
+}
+
+/// Replace the stand-in left by `job_header` in `program` (if there is one) with its estimated run time and extents,
+/// from its `moves`
+pub(crate) fn fill_run_summary(
+    program: &str,
+    moves: &[Move],
+    rapid_rate: f64,
+    units: Units,
+    dialect: Dialect,
+) -> Result<String> {
+    let comment = |s: &str| -> Result<String> {
+        let mut line = vec![];
+        gcode_comment(&mut line, s, dialect)?;
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    };
+    let summary =
+        comment(&run_time_text(moves, rapid_rate))? + &comment(&extents_text(moves, units))?;
+    Ok(program.replacen(&comment(RUN_SUMMARY)?, &summary, 1))
+}
+
+/// Pause for `seconds` (G4)
+pub fn dwell(file: &mut dyn Write, seconds: f64) -> Result<()> {
+    if seconds <= 0.0 {
+        panic!("Refusing to make illegal G4 of {} seconds", seconds);
+    }
+    write!(file, "G4")?;
+    g_val(file, "P", Some(seconds), Units::Metric)?;
+    writeln!(file)
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct PosAndFeed {
     x: Option<f64>,
     y: Option<f64>,
     z: Option<f64>,
     a: Option<f64>,
     feed: Option<f64>,
+    units: Units,
+}
+
+impl PosAndFeed {
+    /// Print this move with the precision appropriate for `units`
+    pub fn units(self, units: Units) -> PosAndFeed {
+        PosAndFeed { units, ..self }
+    }
+}
+
+pub fn a(a: f64) -> PosAndFeed {
+    PosAndFeed {
+        x: None,
+        y: None,
+        z: None,
+        a: Some(a),
+        feed: None,
+        units: Units::Metric,
+    }
 }
 
 pub fn x(x: f64) -> PosAndFeed {
@@ -75,6 +542,18 @@ pub fn x(x: f64) -> PosAndFeed {
         z: None,
         a: None,
         feed: None,
+        units: Units::Metric,
+    }
+}
+
+pub fn xa(x: f64, a: f64) -> PosAndFeed {
+    PosAndFeed {
+        x: Some(x),
+        y: None,
+        z: None,
+        a: Some(a),
+        feed: None,
+        units: Units::Metric,
     }
 }
 
@@ -85,6 +564,7 @@ pub fn xaf(x: f64, a: f64, feed: f64) -> PosAndFeed {
         z: None,
         a: Some(a),
         feed: Some(feed),
+        units: Units::Metric,
     }
 }
 
@@ -95,6 +575,7 @@ pub fn xf(x: f64, feed: f64) -> PosAndFeed {
         z: None,
         a: None,
         feed: Some(feed),
+        units: Units::Metric,
     }
 }
 
@@ -105,6 +586,29 @@ pub fn xy(x: f64, y: f64) -> PosAndFeed {
         z: None,
         a: None,
         feed: None,
+        units: Units::Metric,
+    }
+}
+
+pub fn xya(x: f64, y: f64, a: f64) -> PosAndFeed {
+    PosAndFeed {
+        x: Some(x),
+        y: Some(y),
+        z: None,
+        a: Some(a),
+        feed: None,
+        units: Units::Metric,
+    }
+}
+
+pub fn xyf(x: f64, y: f64, feed: f64) -> PosAndFeed {
+    PosAndFeed {
+        x: Some(x),
+        y: Some(y),
+        z: None,
+        a: None,
+        feed: Some(feed),
+        units: Units::Metric,
     }
 }
 
@@ -115,6 +619,7 @@ pub fn xyza(x: f64, y: f64, z: f64, a: f64) -> PosAndFeed {
         z: Some(z),
         a: Some(a),
         feed: None,
+        units: Units::Metric,
     }
 }
 
@@ -125,6 +630,7 @@ pub fn xyz(x: f64, y: f64, z: f64) -> PosAndFeed {
         z: Some(z),
         a: None,
         feed: None,
+        units: Units::Metric,
     }
 }
 
@@ -135,6 +641,7 @@ pub fn xyzf(x: f64, y: f64, z: f64, feed: f64) -> PosAndFeed {
         z: Some(z),
         a: None,
         feed: Some(feed),
+        units: Units::Metric,
     }
 }
 
@@ -145,6 +652,40 @@ pub fn xzf(x: f64, z: f64, feed: f64) -> PosAndFeed {
         z: Some(z),
         a: None,
         feed: Some(feed),
+        units: Units::Metric,
+    }
+}
+
+pub fn xzaf(x: f64, z: f64, a: f64, feed: f64) -> PosAndFeed {
+    PosAndFeed {
+        x: Some(x),
+        y: None,
+        z: Some(z),
+        a: Some(a),
+        feed: Some(feed),
+        units: Units::Metric,
+    }
+}
+
+pub fn y(y: f64) -> PosAndFeed {
+    PosAndFeed {
+        x: None,
+        y: Some(y),
+        z: None,
+        a: None,
+        feed: None,
+        units: Units::Metric,
+    }
+}
+
+pub fn yf(y: f64, feed: f64) -> PosAndFeed {
+    PosAndFeed {
+        x: None,
+        y: Some(y),
+        z: None,
+        a: None,
+        feed: Some(feed),
+        units: Units::Metric,
     }
 }
 
@@ -155,6 +696,7 @@ pub fn z(z: f64) -> PosAndFeed {
         z: Some(z),
         a: None,
         feed: None,
+        units: Units::Metric,
     }
 }
 
@@ -165,6 +707,7 @@ pub fn zf(z: f64, feed: f64) -> PosAndFeed {
         z: Some(z),
         a: None,
         feed: Some(feed),
+        units: Units::Metric,
     }
 }
 
@@ -175,17 +718,63 @@ pub fn zaf(z: f64, a: f64, feed: f64) -> PosAndFeed {
         z: Some(z),
         a: Some(a),
         feed: Some(feed),
+        units: Units::Metric,
+    }
+}
+
+/// Endpoint, radius, and feed rate for an arc in the X-Y plane
+#[derive(Debug, Clone, Copy)]
+pub struct PosRadiusAndFeed {
+    x: f64,
+    y: f64,
+    z: Option<f64>,
+    r: f64,
+    feed: f64,
+    units: Units,
+}
+
+impl PosRadiusAndFeed {
+    /// Print this move with the precision appropriate for `units`
+    pub fn units(self, units: Units) -> PosRadiusAndFeed {
+        PosRadiusAndFeed { units, ..self }
     }
 }
 
+pub fn xyrf(x: f64, y: f64, r: f64, feed: f64) -> PosRadiusAndFeed {
+    PosRadiusAndFeed {
+        x,
+        y,
+        z: None,
+        r,
+        feed,
+        units: Units::Metric,
+    }
+}
+
+pub fn xyzrf(x: f64, y: f64, z: f64, r: f64, feed: f64) -> PosRadiusAndFeed {
+    PosRadiusAndFeed {
+        x,
+        y,
+        z: Some(z),
+        r,
+        feed,
+        units: Units::Metric,
+    }
+}
+
+/// Values within this distance of an integer are printed in their minimal form. This needs to be much looser than
+/// `f64::EPSILON`, so the error accumulated through the trig in the generators doesn't defeat it.
+const MINIMAL_FORM_TOLERANCE: f64 = 1e-9;
+
 /// Emit a gcode parameter value, if `ov` is `Some`.
 /// To make the gcode human-friendly, numbers that round nicely are printed in their minimal form.
-fn g_val(file: &mut dyn Write, name: &str, ov: Option<f64>) -> Result<()> {
+fn g_val(file: &mut dyn Write, name: &str, ov: Option<f64>, units: Units) -> Result<()> {
     if let Some(v) = ov {
-        if (v - v.round()).abs() < f64::EPSILON {
-            write!(file, " {}{}.", name, v)
+        if (v - v.round()).abs() < MINIMAL_FORM_TOLERANCE {
+            // Adding zero turns -0.0 into 0.0, so tiny negative values don't print as `-0.`
+            write!(file, " {}{}.", name, v.round() + 0.0)
         } else {
-            write!(file, " {}{:.4}", name, v)
+            write!(file, " {}{:.*}", name, units.decimals(), v)
         }
     } else {
         Ok(())
@@ -193,15 +782,15 @@ fn g_val(file: &mut dyn Write, name: &str, ov: Option<f64>) -> Result<()> {
 }
 
 fn g_move_linear(file: &mut dyn Write, g: &str, p: PosAndFeed) -> Result<()> {
-    if p.x.is_none() && p.y.is_none() && p.z.is_none() {
+    if p.x.is_none() && p.y.is_none() && p.z.is_none() && p.a.is_none() {
         panic!("Refusing to make illegal {}", g);
     }
     write!(file, "{}", g)?;
-    g_val(file, "X", p.x)?;
-    g_val(file, "Y", p.y)?;
-    g_val(file, "Z", p.z)?;
-    g_val(file, "A", p.a)?;
-    g_val(file, "F", p.feed)?;
+    g_val(file, "X", p.x, p.units)?;
+    g_val(file, "Y", p.y, p.units)?;
+    g_val(file, "Z", p.z, p.units)?;
+    g_val(file, "A", p.a, p.units)?;
+    g_val(file, "F", p.feed, p.units)?;
     writeln!(file)?;
     Ok(())
 }
@@ -216,6 +805,230 @@ pub fn g1(file: &mut dyn Write, p: PosAndFeed) -> Result<()> {
     g_move_linear(file, "G1", p)
 }
 
+/// Probe towards the position in `p` at `feed` (G38.2), stopping when the probe trips.
+/// The machine faults if the probe reaches `p` without tripping.
+pub fn probe(file: &mut dyn Write, p: PosAndFeed, feed: f64) -> Result<()> {
+    g_move_linear(
+        file,
+        "G38.2",
+        PosAndFeed {
+            feed: Some(feed),
+            ..p
+        },
+    )
+}
+
+/// Set the origin of `work_offset` so that the current position reads as the coordinates in `p` (G10 L20)
+pub fn set_work_offset(file: &mut dyn Write, work_offset: WorkOffset, p: PosAndFeed) -> Result<()> {
+    assert!(p.feed.is_none(), "G10 must not include a feed rate");
+    g_move_linear(file, &format!("G10 L20 P{}", work_offset.p_number()), p)
+}
+
+/// Probe straight down from the current position, by at most `distance`, and make the touched surface Z0 in
+/// `work_offset`. Backs off by `backoff` afterwards, so the probe isn't left in contact with the work.
+pub fn touch_off_z(
+    file: &mut dyn Write,
+    work_offset: WorkOffset,
+    distance: f64,
+    backoff: f64,
+    feed: f64,
+    units: Units,
+    dialect: Dialect,
+) -> Result<()> {
+    gcode_comment(file, "Touch off Z", dialect)?;
+    // The starting height isn't known in work coordinates yet, so probe and back off incrementally
+    commented(file, "G91", "Incremental", dialect)?;
+    probe(file, z(-distance).units(units), feed)?;
+    commented(file, "G90", "Absolute", dialect)?;
+    set_work_offset(file, work_offset, z(0.0).units(units))?;
+    g0(file, z(backoff).units(units))
+}
+
+/// Radius-form arcs are ambiguous (and machines disagree on what to do) when the endpoint is further away than
+/// the diameter of the circle, so refuse to make them. `from` is the X-Y position the arc starts at.
+fn g_move_arc_radius(
+    file: &mut dyn Write,
+    g: &str,
+    from: (f64, f64),
+    p: PosRadiusAndFeed,
+) -> Result<()> {
+    let chord = (p.x - from.0).hypot(p.y - from.1);
+    if chord > 2.0 * p.r.abs() + 1e-9 {
+        panic!(
+            "Refusing to make illegal {}: radius {} is too small to span {}",
+            g, p.r, chord
+        );
+    }
+    write!(file, "{}", g)?;
+    g_val(file, "X", Some(p.x), p.units)?;
+    g_val(file, "Y", Some(p.y), p.units)?;
+    g_val(file, "Z", p.z, p.units)?;
+    g_val(file, "R", Some(p.r), p.units)?;
+    g_val(file, "F", Some(p.feed), p.units)?;
+    writeln!(file)?;
+    Ok(())
+}
+
+/// Clockwise arc from `from` to the endpoint in `p`, specified by radius. Unlike the other moves these take the X-Y
+/// position the arc starts at, since the output is a plain `Write` that doesn't know where the last move left the tool,
+/// and it's needed to refuse an arc whose endpoint is out of reach of its radius.
+pub fn g2r(file: &mut dyn Write, from: (f64, f64), p: PosRadiusAndFeed) -> Result<()> {
+    g_move_arc_radius(file, "G2", from, p)
+}
+
+/// Counter-clockwise arc from `from` to the endpoint in `p`, specified by radius, and taking `from` as `g2r` does
+pub fn g3r(file: &mut dyn Write, from: (f64, f64), p: PosRadiusAndFeed) -> Result<()> {
+    g_move_arc_radius(file, "G3", from, p)
+}
+
+/// Emit a canned drilling cycle at each of `holes`, followed by G80 to cancel it.
+/// Each hole needs an X and Y position, and the first needs the feed rate. `q` is the peck increment, for G83.
+/// The cycle is G99: the tool retracts to `retract` between holes, so that plane must clear any clamps.
+fn canned_cycle(
+    file: &mut dyn Write,
+    g: &str,
+    holes: &[PosAndFeed],
+    retract: f64,
+    z: f64,
+    q: Option<f64>,
+) -> Result<()> {
+    for (i, p) in holes.iter().enumerate() {
+        if p.x.is_none() || p.y.is_none() || p.z.is_some() {
+            panic!(
+                "Refusing to make illegal {} without an X-Y hole position",
+                g
+            );
+        }
+        let mut words: Vec<u8> = Vec::new();
+        if i == 0 {
+            assert!(p.feed.is_some(), "{} cycles must include a feed rate", g);
+            write!(words, "G99 {}", g)?;
+        }
+        g_val(&mut words, "X", p.x, p.units)?;
+        g_val(&mut words, "Y", p.y, p.units)?;
+        if i == 0 {
+            // The rest of the cycle is modal, so only appears on the first hole
+            g_val(&mut words, "Z", Some(z), p.units)?;
+            g_val(&mut words, "R", Some(retract), p.units)?;
+            g_val(&mut words, "Q", q, p.units)?;
+            g_val(&mut words, "F", p.feed, p.units)?;
+        }
+        writeln!(file, "{}", String::from_utf8_lossy(&words).trim_start())?;
+    }
+    writeln!(file, "G80")
+}
+
+/// Drill a hole at the X-Y position in `p` down to `z` (G81), starting from and returning to the `retract` plane
+pub fn drill_g81(file: &mut dyn Write, p: PosAndFeed, retract: f64, z: f64) -> Result<()> {
+    canned_cycle(file, "G81", &[p], retract, z, None)
+}
+
+/// Peck drill a hole at the X-Y position in `p` down to `z` (G83), clearing chips every `peck` of depth
+pub fn peck_drill_g83(
+    file: &mut dyn Write,
+    p: PosAndFeed,
+    retract: f64,
+    z: f64,
+    peck: f64,
+) -> Result<()> {
+    drill_holes(file, &[p], retract, z, Some(peck))
+}
+
+/// Drill each of `holes` (G81, or G83 peck drilling every `peck` of depth), in a single canned cycle
+pub fn drill_holes(
+    file: &mut dyn Write,
+    holes: &[PosAndFeed],
+    retract: f64,
+    z: f64,
+    peck: Option<f64>,
+) -> Result<()> {
+    match peck {
+        Some(peck) => {
+            assert!(peck > 0.0, "G83 peck increment must be positive");
+            canned_cycle(file, "G83", holes, retract, z, Some(peck))
+        }
+        None => canned_cycle(file, "G81", holes, retract, z, None),
+    }
+}
+
+/// Positions of `count` holes evenly spaced on a circle of `radius` around `center`, with the first hole at
+/// `start_angle` degrees from +X. Pass the result to `drill_holes`.
+pub fn bolt_circle(
+    center: (f64, f64),
+    radius: f64,
+    count: u32,
+    start_angle: f64,
+    feed: f64,
+) -> Vec<PosAndFeed> {
+    (0..count)
+        .map(|i| {
+            let angle = (start_angle + 360.0 * i as f64 / count as f64).to_radians();
+            PosAndFeed {
+                feed: Some(feed),
+                ..xy(
+                    center.0 + radius * angle.cos(),
+                    center.1 + radius * angle.sin(),
+                )
+            }
+        })
+        .collect()
+}
+
+/// Next number from a splitmix64 generator. It's plenty random enough to spread cuts around a part, and doesn't need
+/// any dependencies.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A axis angles (in degrees) to index `count` teeth or flutes around a part, starting at 0.
+/// With a `variation` of 0 the teeth are evenly spaced. Otherwise each space is widened or narrowed by up to
+/// `variation` (as a fraction of the even spacing), pseudo-randomly from `seed`, to break up the chatter that even
+/// spacing resonates with. The spaces are then scaled so they still add up to 360 degrees, and the same seed always
+/// gives the same angles.
+pub fn index_angles(count: u32, variation: f64, seed: u64) -> Vec<f64> {
+    let even = 360.0 / count as f64;
+    if variation == 0.0 {
+        return (0..count).map(|i| even * i as f64).collect();
+    }
+    let mut state = seed;
+    let spaces: Vec<f64> = (0..count)
+        .map(|_| {
+            // Uniform in [-1, 1), from the top 53 bits
+            let r = (splitmix64(&mut state) >> 11) as f64 / (1u64 << 53) as f64;
+            1.0 + variation * (2.0 * r - 1.0)
+        })
+        .collect();
+    let total: f64 = spaces.iter().sum();
+    spaces
+        .iter()
+        .scan(0.0, |angle, space| {
+            let start = *angle;
+            *angle += 360.0 * space / total;
+            Some(start)
+        })
+        .collect()
+}
+
+/// Start the definition of subprogram `number` (an O-word).
+/// Subprograms are defined after the end (`M30`) of the main program, and finished with `subprogram_end`.
+pub fn subprogram_start(file: &mut dyn Write, number: u32) -> Result<()> {
+    writeln!(file, "O{}", number)
+}
+
+/// Finish the definition of a subprogram, returning to the caller (M99)
+pub fn subprogram_end(file: &mut dyn Write) -> Result<()> {
+    writeln!(file, "M99")
+}
+
+/// Call subprogram `number` (M98)
+pub fn call_subprogram(file: &mut dyn Write, number: u32) -> Result<()> {
+    writeln!(file, "M98 P{}", number)
+}
+
 /// Enable inverse feed rate mode (G93)
 /// With inverse feed rate mode enabled, each non-rapid move needs to contain an `F` parameter.
 /// `F` is interpreted as the inverse of the feed time, in minutes. E.g. `F3.0` is interpreted
@@ -224,7 +1037,941 @@ pub fn inv_feed_g93(file: &mut dyn Write) -> Result<()> {
     writeln!(file, "G93")
 }
 
+/// Distance the surface of a part `dia` across moves past the tool when the rotary axis turns `delta_a` degrees
+pub fn rotary_surface_distance(delta_a: f64, dia: f64) -> f64 {
+    delta_a.abs() / 360.0 * (PI * dia)
+}
+
+/// Inverse time feed rate (the F word in G93 mode, in 1/minutes) for a move with `path_len` of tool travel over the
+/// work, to cut at `target_feed` units per minute. For a move that turns a rotary axis, `path_len` should combine the
+/// linear travel with the surface distance from `rotary_surface_distance`.
+/// LinuxCNC says this about the way feed rate is interpreted during simultaneous multi-axis:
+///   "If any of XYZ are moving, F is in units per minute in the XYZ cartesian system, and all
+///    other axes (ABCUVW) move so as to start and stop in coordinated fashion."
+/// So an ordinary feed rate doesn't give the right actual feed at the tip of the tool. Correcting it in a way that
+///  machines agree on seems hard, so instead we use G93 mode and let the machine figure out the XYZ and ABC feed rates.
+pub fn inverse_feed(path_len: f64, target_feed: f64) -> f64 {
+    target_feed / path_len
+}
+
 /// Enable units-per-minute feed rate mode (G94)
 pub fn standard_feed_g94(file: &mut dyn Write) -> Result<()> {
     writeln!(file, "G94")
 }
+
+/// Enable feed-per-revolution mode (G95)
+/// With feed-per-revolution mode enabled, `F` is interpreted as units per spindle revolution, so `F0.1` in metric
+/// advances the tool 0.1mm for each turn of the spindle, whatever the RPM.
+pub fn feed_per_rev_g95(file: &mut dyn Write) -> Result<()> {
+    writeln!(file, "G95")
+}
+
+/// Largest feed per revolution (in mm) we'll believe is intended. Anything bigger is almost certainly a per-minute
+/// feed that's ended up in a G95 block.
+const MAX_FEED_PER_REV_MM: f64 = 5.0;
+
+/// Check that `feed` looks like a feed per revolution, rather than a per-minute feed used by mistake in G95 mode.
+pub fn assert_feed_per_rev(feed: f64, units: Units) {
+    assert!(
+        feed > 0.0 && feed <= units.from_mm(MAX_FEED_PER_REV_MM),
+        "Refusing feed of {}{} per revolution, is this a per-minute feed?",
+        feed,
+        units.abbrev()
+    );
+}
+
+/// Spindle RPM for a tool `dia_mm` across to cut at `surface_m_per_min` meters/minute at its edge
+pub fn surface_speed_to_rpm(surface_m_per_min: f64, dia_mm: f64) -> f64 {
+    surface_m_per_min / (PI * (dia_mm / 1000.0))
+}
+
+/// Feed rate, per minute, for a tool with `teeth` teeth at `rpm` to take `chip_load` per tooth. It comes out in
+/// whatever length unit the chip load is in.
+pub fn chip_load_to_feed(chip_load: f64, rpm: f64, teeth: u32) -> f64 {
+    chip_load * rpm * teeth as f64
+}
+
+/// One step of a toolpath.
+/// Collecting moves into a `Vec<Move>` before writing them lets callers inspect or post-process a path, then `emit` it.
+#[derive(Debug, Clone)]
+pub enum Move {
+    Comment(String),
+    /// G0
+    Rapid(PosAndFeed),
+    /// G1
+    Feed(PosAndFeed),
+    /// G2, from the given X-Y start point
+    ArcCw((f64, f64), PosRadiusAndFeed),
+    /// G3, from the given X-Y start point
+    ArcCcw((f64, f64), PosRadiusAndFeed),
+    /// G4, in seconds
+    Dwell(f64),
+    /// G93
+    InverseFeed,
+    /// G94
+    StandardFeed,
+    /// M6, loading the given tool. This is only the tool change itself, `tool_change` does the rest of the sequence.
+    ToolChange(u32),
+    /// G38.2, probing towards the position, which is as far as the probe goes if it doesn't trip first
+    Probe(PosAndFeed),
+    /// Return to the home position, which isn't known in work coordinates, so neither is where the tool is after it
+    Home(Home),
+}
+
+impl Move {
+    /// Write the move as G code for the `dialect`
+    pub fn emit(&self, file: &mut dyn Write, dialect: Dialect) -> Result<()> {
+        match self {
+            Move::Comment(s) => gcode_comment(file, s, dialect),
+            Move::Rapid(p) => g0(file, *p),
+            Move::Feed(p) => g1(file, *p),
+            Move::ArcCw(from, p) => g2r(file, *from, *p),
+            Move::ArcCcw(from, p) => g3r(file, *from, *p),
+            Move::Dwell(seconds) => dwell(file, *seconds),
+            Move::InverseFeed => inv_feed_g93(file),
+            Move::StandardFeed => standard_feed_g94(file),
+            Move::ToolChange(number) => load_tool(file, *number, dialect),
+            Move::Probe(p) => probe(file, *p, p.feed.unwrap_or(0.0)),
+            Move::Home(home) => go_home(file, *home),
+        }
+    }
+}
+
+/// Write out a whole toolpath, for the `dialect`
+pub fn emit(path: &[Move], file: &mut dyn Write, dialect: Dialect) -> Result<()> {
+    for m in path {
+        m.emit(file, dialect)?;
+    }
+    Ok(())
+}
+
+/// Read back the moves in a program written by this crate, the reverse of `emit`, so a finished program can be
+/// previewed or checked.
+/// Only the words this crate writes are understood, and everything it writes that moves the tool is read as moves:
+/// - Canned drilling cycles (G81 and G83, retracting to R as G99 does) are read as the rapids and feeds they make.
+///   G83's rapids back down to the last peck are taken to go all the way, with no clearance above it.
+/// - Probing (G38.2) is read as a `Move::Probe` all the way to its target, which is as far as the probe can go.
+/// - Going home (G28, G30, or G53 Z) is read as a `Move::Home`, after which the position is unknown until each axis is
+///   next given.
+/// - Incremental (G91) moves are read as the absolute positions they reach, where the start is known. Where it isn't,
+///   the axis stays unknown.
+/// - Setting the work offset (G10 L20) doesn't move the tool, but makes the current position known.
+/// - Each subprogram call (M98 P) is read as the moves of the subprogram it calls, from where it's called. The
+///   subprograms' definitions, after the end of the program, aren't read as moves themselves.
+pub fn parse_moves(gcode: &str) -> Vec<Move> {
+    // Each subprogram's lines, from its O-word to its M99
+    let mut main = vec![];
+    let mut subprograms: HashMap<u32, Vec<&str>> = HashMap::new();
+    let mut defining = None;
+    for line in gcode.lines() {
+        let code = without_block_number(line.trim());
+        if let Some(number) = defining {
+            if code.split_whitespace().any(|word| word == "M99") {
+                defining = None;
+            } else {
+                subprograms.entry(number).or_default().push(line);
+            }
+        } else if let Some(number) = code.strip_prefix('O').and_then(|n| n.trim().parse().ok()) {
+            subprograms.entry(number).or_default();
+            defining = Some(number);
+        } else {
+            main.push(line);
+        }
+    }
+
+    let mut reader = MoveReader {
+        moves: vec![],
+        units: Units::Metric,
+        motion: None,
+        cycle: None,
+        absolute: true,
+        feed: None,
+        at: [None; 4],
+    };
+    for line in main {
+        reader.line(line, &subprograms, 0);
+    }
+    reader.moves
+}
+
+/// Deepest nesting of subprogram calls `parse_moves` follows, so one that calls itself doesn't go on forever
+const MAX_CALL_DEPTH: usize = 10;
+
+/// `line` without any N block number at the start
+fn without_block_number(line: &str) -> &str {
+    if line.starts_with('N') {
+        line.split_once(' ').map_or("", |(_, rest)| rest)
+    } else {
+        line
+    }
+}
+
+/// A canned drilling cycle, once it's started, down to `z` from the retract plane at `r`, pecking every `peck` (G83)
+#[derive(Debug, Clone, Copy)]
+struct Cycle {
+    z: f64,
+    r: f64,
+    peck: Option<f64>,
+}
+
+/// The modes and position of a program, as `parse_moves` reads through it
+struct MoveReader {
+    moves: Vec<Move>,
+    units: Units,
+    /// The motion mode carries over to lines without a G word, unless we're in a canned cycle
+    motion: Option<&'static str>,
+    cycle: Option<Cycle>,
+    absolute: bool,
+    feed: Option<f64>,
+    /// Where we are in X, Y, Z, and A, as far as we know
+    at: [Option<f64>; 4],
+}
+
+impl MoveReader {
+    fn pos(&self, to: [Option<f64>; 4], feed: Option<f64>) -> PosAndFeed {
+        PosAndFeed {
+            x: to[0],
+            y: to[1],
+            z: to[2],
+            a: to[3],
+            feed,
+            units: self.units,
+        }
+    }
+
+    /// A rapid or feed (in the canned cycle's feed) to `z`, from where we are
+    fn z_move(&mut self, z: f64, rapid: bool) {
+        let p = self.pos([None, None, Some(z), None], self.feed);
+        self.moves.push(if rapid {
+            Move::Rapid(PosAndFeed { feed: None, ..p })
+        } else {
+            Move::Feed(p)
+        });
+        self.at[2] = Some(z);
+    }
+
+    /// Drill a hole at (`x`, `y`) in the current canned `cycle`, ending at its retract plane
+    fn drill(&mut self, cycle: Cycle, x: Option<f64>, y: Option<f64>) {
+        // Starting below the retract plane, the cycle rises to it before moving across
+        if self.at[2].is_some_and(|z| z < cycle.r) {
+            self.z_move(cycle.r, true);
+        }
+        self.moves
+            .push(Move::Rapid(self.pos([x, y, None, None], None)));
+        self.at[0] = x.or(self.at[0]);
+        self.at[1] = y.or(self.at[1]);
+        self.z_move(cycle.r, true);
+        match cycle.peck {
+            None => self.z_move(cycle.z, false),
+            Some(peck) => {
+                let mut depth = cycle.r;
+                loop {
+                    depth = (depth - peck).max(cycle.z);
+                    self.z_move(depth, false);
+                    if depth <= cycle.z {
+                        break;
+                    }
+                    self.z_move(cycle.r, true);
+                    self.z_move(depth, true);
+                }
+            }
+        }
+        self.z_move(cycle.r, true);
+    }
+
+    /// Read a `line` of the program, `depth` subprogram calls down, following any calls it makes to `subprograms`
+    fn line(&mut self, line: &str, subprograms: &HashMap<u32, Vec<&str>>, depth: usize) {
+        let line = without_block_number(line.trim());
+        if let Some(comment) = line.strip_prefix('(') {
+            self.moves
+                .push(Move::Comment(comment.trim_end_matches(')').to_string()));
+            return;
+        }
+        if let Some(comment) = line.strip_prefix(';') {
+            self.moves.push(Move::Comment(comment.trim().to_string()));
+            return;
+        }
+        if line.starts_with('O') {
+            return;
+        }
+        let code = line.split(['(', ';']).next().unwrap_or("");
+        let words: Vec<&str> = code.split_whitespace().collect();
+        let value = |letter: char| -> Option<f64> {
+            words
+                .iter()
+                .find(|w| w.starts_with(letter))
+                .and_then(|w| w[1..].parse().ok())
+        };
+
+        let mut line_motion = None;
+        let mut cycle_start = None;
+        let mut home = None;
+        let mut probe = false;
+        let mut set_offset = false;
+        let mut call = None;
+        for &word in &words {
+            match word {
+                "G0" => line_motion = Some("G0"),
+                "G1" => line_motion = Some("G1"),
+                "G2" => line_motion = Some("G2"),
+                "G3" => line_motion = Some("G3"),
+                "G4" => {
+                    if let Some(seconds) = value('P') {
+                        self.moves.push(Move::Dwell(seconds));
+                    }
+                }
+                "G20" => self.units = Units::Imperial,
+                "G21" => self.units = Units::Metric,
+                "G90" => self.absolute = true,
+                "G91" => self.absolute = false,
+                "G93" => self.moves.push(Move::InverseFeed),
+                "G94" => self.moves.push(Move::StandardFeed),
+                "G81" | "G83" => cycle_start = Some(word),
+                "G80" => self.cycle = None,
+                "G28" => home = Some(Home::G28),
+                "G30" => home = Some(Home::G30),
+                "G53" => home = value('Z').map(Home::MachineZ),
+                "G38.2" => probe = true,
+                "G10" => set_offset = true,
+                "M98" => call = value('P').map(|number| number as u32),
+                "M6" => {
+                    if let Some(number) = value('T') {
+                        self.moves.push(Move::ToolChange(number as u32));
+                    }
+                }
+                _ => (),
+            }
+        }
+        let given = [value('X'), value('Y'), value('Z'), value('A')];
+
+        if let Some(number) = call {
+            if depth < MAX_CALL_DEPTH {
+                for line in subprograms.get(&number).into_iter().flatten() {
+                    self.line(line, subprograms, depth + 1);
+                }
+            }
+            return;
+        }
+        if let Some(home) = home {
+            self.moves.push(Move::Home(home));
+            for (at, moved) in self.at.iter_mut().zip(home.moved_axes()) {
+                if moved {
+                    *at = None;
+                }
+            }
+            return;
+        }
+        if set_offset {
+            // The current position becomes the coordinates given, in the offset's new frame
+            for (at, v) in self.at.iter_mut().zip(given) {
+                *at = v.or(*at);
+            }
+            return;
+        }
+        if let Some(f) = value('F') {
+            self.feed = Some(f);
+        }
+        if let Some(g) = cycle_start {
+            self.cycle = match (value('Z'), value('R')) {
+                (Some(z), Some(r)) => Some(Cycle {
+                    z,
+                    r,
+                    peck: if g == "G83" { value('Q') } else { None },
+                }),
+                _ => None,
+            };
+        } else if line_motion.is_some() {
+            // A new motion mode ends any cycle
+            self.cycle = None;
+        }
+        if let Some(cycle) = self.cycle {
+            if given[0].is_some() || given[1].is_some() {
+                self.drill(cycle, given[0], given[1]);
+            }
+            return;
+        }
+        if line_motion.is_some() {
+            self.motion = line_motion;
+        }
+        if given.iter().all(Option::is_none) {
+            return;
+        }
+
+        // Where each axis that's given ends up, if we can tell
+        let mut to = [None; 4];
+        for i in 0..4 {
+            to[i] = match (given[i], self.absolute) {
+                (Some(v), true) => Some(v),
+                (Some(v), false) => self.at[i].map(|at| at + v),
+                (None, _) => None,
+            };
+        }
+        let p = self.pos(to, value('F'));
+        let from = (self.at[0].unwrap_or(0.0), self.at[1].unwrap_or(0.0));
+        for i in 0..4 {
+            if given[i].is_some() {
+                self.at[i] = to[i];
+            }
+        }
+        if to.iter().all(Option::is_none) {
+            return;
+        }
+        if probe {
+            self.moves.push(Move::Probe(p));
+            return;
+        }
+        let arc = || PosRadiusAndFeed {
+            x: p.x.unwrap_or(from.0),
+            y: p.y.unwrap_or(from.1),
+            z: p.z,
+            r: value('R').unwrap_or(0.0),
+            feed: p.feed.unwrap_or(0.0),
+            units: self.units,
+        };
+        let m = match self.motion {
+            Some("G0") => Move::Rapid(p),
+            Some("G1") => Move::Feed(p),
+            Some("G2") => Move::ArcCw(from, arc()),
+            Some("G3") => Move::ArcCcw(from, arc()),
+            _ => return,
+        };
+        self.moves.push(m);
+    }
+}
+
+/// A straight or circular piece of a toolpath, in the X-Y plane
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum XySegment {
+    Line((f64, f64), (f64, f64)),
+    /// Arc from the first point to the second, with the radius (negative for more than half a circle) and whether
+    /// it runs counter-clockwise
+    Arc((f64, f64), (f64, f64), f64, bool),
+}
+
+/// Follow `moves` around the X-Y plane, splitting them into rapids and feeds (including arcs and probing). Moves that
+/// only change Z or A, or that start before both X and Y are known, are left out.
+pub(crate) fn xy_segments(moves: &[Move]) -> (Vec<XySegment>, Vec<XySegment>) {
+    let (mut rapids, mut feeds) = (vec![], vec![]);
+    let mut at: (Option<f64>, Option<f64>) = (None, None);
+    for m in moves {
+        match m {
+            Move::Rapid(p) | Move::Feed(p) | Move::Probe(p) => {
+                let to = (p.x.or(at.0), p.y.or(at.1));
+                if let ((Some(x0), Some(y0)), (Some(x1), Some(y1))) = (at, to) {
+                    if (x0, y0) != (x1, y1) {
+                        let segment = XySegment::Line((x0, y0), (x1, y1));
+                        if let Move::Rapid(_) = m {
+                            rapids.push(segment);
+                        } else {
+                            feeds.push(segment);
+                        }
+                    }
+                }
+                at = to;
+            }
+            Move::ArcCw(from, p) | Move::ArcCcw(from, p) => {
+                let ccw = matches!(m, Move::ArcCcw(..));
+                feeds.push(XySegment::Arc(*from, (p.x, p.y), p.r, ccw));
+                at = (Some(p.x), Some(p.y));
+            }
+            Move::Home(home) if home.moved_axes()[0] => at = (None, None),
+            _ => (),
+        }
+    }
+    (rapids, feeds)
+}
+
+/// Center of the arc from `from` to `to` with radius `r` (negative for more than half a circle), turning
+/// counter-clockwise if `ccw`
+pub fn arc_center(from: (f64, f64), to: (f64, f64), r: f64, ccw: bool) -> (f64, f64) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let chord = dx.hypot(dy);
+    let mid = ((from.0 + to.0) / 2.0, (from.1 + to.1) / 2.0);
+    // Distance from the middle of the chord out to the center. Rounding can leave the radius a hair short of spanning
+    // the chord, so don't let that go negative.
+    let h = (r * r - chord * chord / 4.0).max(0.0).sqrt();
+    // The center of a short arc is on its inside: left of the chord going counter-clockwise, and right going
+    // clockwise. A long arc has it on the other side.
+    let side = if ccw == (r > 0.0) { 1.0 } else { -1.0 };
+    (mid.0 - side * h * dy / chord, mid.1 + side * h * dx / chord)
+}
+
+/// Smallest and largest X, Y, Z, and A positions reached by `path`, counting rapids and feeds, and the furthest
+/// points out around each arc. Axes that never move are `None`. Moves that aren't in the path (like going home) aren't
+/// counted.
+pub fn bounding_box(path: &[Move]) -> ([Option<f64>; 4], [Option<f64>; 4]) {
+    let mut min: [Option<f64>; 4] = [None; 4];
+    let mut max: [Option<f64>; 4] = [None; 4];
+    let mut include = |axis: usize, v: Option<f64>| {
+        if let Some(v) = v {
+            min[axis] = Some(min[axis].map_or(v, |m: f64| m.min(v)));
+            max[axis] = Some(max[axis].map_or(v, |m: f64| m.max(v)));
+        }
+    };
+    for m in path {
+        match m {
+            Move::Rapid(p) | Move::Feed(p) | Move::Probe(p) => {
+                include(0, p.x);
+                include(1, p.y);
+                include(2, p.z);
+                include(3, p.a);
+            }
+            Move::ArcCw(from, p) | Move::ArcCcw(from, p) => {
+                let ccw = matches!(m, Move::ArcCcw(..));
+                include(0, Some(from.0));
+                include(1, Some(from.1));
+                include(0, Some(p.x));
+                include(1, Some(p.y));
+                include(2, p.z);
+                // The arc reaches out to its radius in any of the four directions it passes through
+                let center = arc_center(*from, (p.x, p.y), p.r, ccw);
+                let angle = |q: (f64, f64)| (q.1 - center.1).atan2(q.0 - center.0);
+                let (start, end) = if ccw {
+                    (angle(*from), angle((p.x, p.y)))
+                } else {
+                    (angle((p.x, p.y)), angle(*from))
+                };
+                let sweep = (end - start).rem_euclid(2.0 * PI);
+                let r = p.r.abs();
+                for (i, &(dx, dy)) in [(1.0, 0.0), (0.0, 1.0), (-1.0, 0.0), (0.0, -1.0)]
+                    .iter()
+                    .enumerate()
+                {
+                    let direction = i as f64 * PI / 2.0;
+                    if (direction - start).rem_euclid(2.0 * PI) <= sweep {
+                        include(0, Some(center.0 + r * dx));
+                        include(1, Some(center.1 + r * dy));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    (min, max)
+}
+
+/// Print the range of positions on each axis of `path`, which is in `units` (see `bounding_box`), to check it fits
+/// the machine and misses the clamps
+pub fn report_extents(path: &[Move], units: Units) {
+    eprintln!("{}", extents_text(path, units));
+}
+
+/// The range of positions on each axis of `path`, like "Extents X[-10.0000, 4.0000] Y[-] Z[0.0000, 12.0000] A[-]"
+fn extents_text(path: &[Move], units: Units) -> String {
+    let (min, max) = bounding_box(path);
+    let range = |axis: usize| match (min[axis], max[axis]) {
+        (Some(lo), Some(hi)) => {
+            format!("[{:.*}, {:.*}]", units.decimals(), lo, units.decimals(), hi)
+        }
+        _ => "[-]".to_string(),
+    };
+    format!(
+        "Extents X{} Y{} Z{} A{}",
+        range(0),
+        range(1),
+        range(2),
+        range(3)
+    )
+}
+
+/// Axis letters, in the order `bounding_box` and `Envelope` use
+const AXES: [char; 4] = ['X', 'Y', 'Z', 'A'];
+
+/// The travel of the machine (its soft limits), in work coordinates, as the smallest and largest position allowed on
+/// each of X, Y, Z, and A. Axes without limits are `None`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Envelope {
+    pub min: [Option<f64>; 4],
+    pub max: [Option<f64>; 4],
+}
+
+impl Envelope {
+    /// Check that every move in `path` stays inside the envelope, or describe the first one that doesn't, and which
+    /// axis it goes too far on
+    pub fn check(&self, path: &[Move]) -> std::result::Result<(), String> {
+        for (i, m) in path.iter().enumerate() {
+            let (lo, hi) = bounding_box(std::slice::from_ref(m));
+            for axis in 0..4 {
+                let outside = match (lo[axis], self.min[axis], hi[axis], self.max[axis]) {
+                    (Some(v), Some(min), _, _) if v < min => Some(v),
+                    (_, _, Some(v), Some(max)) if v > max => Some(v),
+                    _ => None,
+                };
+                if let Some(v) = outside {
+                    let mut text = vec![];
+                    m.emit(&mut text, Dialect::default())
+                        .map_err(|e| e.to_string())?;
+                    return Err(format!(
+                        "Move {} ({}) goes to {}{:.4}, outside the machine's {} travel of {} to {}",
+                        i + 1,
+                        String::from_utf8_lossy(&text).trim(),
+                        AXES[axis],
+                        v,
+                        AXES[axis],
+                        self.min[axis].map_or("-".to_string(), |v| v.to_string()),
+                        self.max[axis].map_or("-".to_string(), |v| v.to_string()),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Envelope {
+    type Err = String;
+
+    /// Parse limits like `X-200:10,Y0:150,Z-100:0`, with the smallest and largest position for each axis given
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut envelope = Envelope::default();
+        for limit in s.split(',') {
+            let limit = limit.trim();
+            let axis = limit
+                .chars()
+                .next()
+                .and_then(|c| AXES.iter().position(|&a| a == c.to_ascii_uppercase()))
+                .ok_or_else(|| format!("Unknown axis in {}, expected X, Y, Z, or A", limit))?;
+            let (min, max) = limit[1..]
+                .split_once(':')
+                .and_then(|(min, max)| Some((min.parse::<f64>().ok()?, max.parse::<f64>().ok()?)))
+                .filter(|(min, max)| min <= max)
+                .ok_or_else(|| format!("Bad limits {}, expected like X-200:10", limit))?;
+            envelope.min[axis] = Some(min);
+            envelope.max[axis] = Some(max);
+        }
+        Ok(envelope)
+    }
+}
+
+/// Time allowed for each tool change, by `estimate_time`
+pub const TOOL_CHANGE_TIME: Duration = Duration::from_secs(30);
+
+/// Length of the arc from `from` to `to` with radius `r` (negative for more than half a circle)
+fn arc_length(from: (f64, f64), to: (f64, f64), r: f64) -> f64 {
+    let r_abs = r.abs();
+    let chord = (to.0 - from.0).hypot(to.1 - from.1);
+    let short = 2.0 * (chord / (2.0 * r_abs)).min(1.0).asin();
+    let angle = if r < 0.0 { 2.0 * PI - short } else { short };
+    r_abs * angle
+}
+
+/// Rough running time of `path`, with rapids at `rapid_rate` (in the path's units per minute), feeds at their feed rate
+/// (or inverse time, in G93 mode), dwells, and `TOOL_CHANGE_TIME` for each tool change. A rapid of only the rotary axis
+/// goes at the rapid rate in mm/min taken as degrees per minute, whatever the units. Probing is taken to go all the way
+/// to its target, so it's overestimated.
+/// Acceleration isn't accounted for, and neither are moves whose start isn't known, like the first move after going
+/// home, so real jobs take a bit longer.
+pub fn estimate_time(path: &[Move], rapid_rate: f64) -> Duration {
+    let mut minutes = 0.0;
+    let mut tool_changes = 0;
+    let mut inverse = false;
+    let mut feed = None;
+    // Where we are in X, Y, Z, and A, as far as we know
+    let mut at: [Option<f64>; 4] = [None; 4];
+    let feed_minutes = |inverse: bool, len: f64, f: f64| {
+        if inverse {
+            1.0 / f
+        } else {
+            len / f
+        }
+    };
+
+    for m in path {
+        match m {
+            Move::Rapid(p) | Move::Feed(p) | Move::Probe(p) => {
+                let to = [p.x.or(at[0]), p.y.or(at[1]), p.z.or(at[2]), p.a.or(at[3])];
+                let delta = |i: usize| match (at[i], to[i]) {
+                    (Some(from), Some(to)) => to - from,
+                    _ => 0.0,
+                };
+                let linear = (delta(0).powi(2) + delta(1).powi(2) + delta(2).powi(2)).sqrt();
+                // A move of only the rotary axis goes at its rate in degrees per minute
+                let len = if linear > 0.0 { linear } else { delta(3).abs() };
+                if let Move::Rapid(_) = m {
+                    minutes += if linear > 0.0 {
+                        len / rapid_rate
+                    } else {
+                        len / p.units.to_mm(rapid_rate)
+                    };
+                } else {
+                    feed = p.feed.or(feed);
+                    if let Some(f) = feed {
+                        minutes += feed_minutes(inverse, len, f);
+                    }
+                }
+                at = to;
+            }
+            Move::ArcCw(from, p) | Move::ArcCcw(from, p) => {
+                let dz = match (at[2], p.z) {
+                    (Some(from), Some(to)) => to - from,
+                    _ => 0.0,
+                };
+                let len = arc_length(*from, (p.x, p.y), p.r).hypot(dz);
+                minutes += feed_minutes(inverse, len, p.feed);
+                feed = Some(p.feed);
+                at = [Some(p.x), Some(p.y), p.z.or(at[2]), at[3]];
+            }
+            Move::Dwell(seconds) => minutes += seconds / 60.0,
+            Move::InverseFeed => inverse = true,
+            Move::StandardFeed => inverse = false,
+            Move::ToolChange(_) => tool_changes += 1,
+            Move::Home(home) => {
+                for (at, moved) in at.iter_mut().zip(home.moved_axes()) {
+                    if moved {
+                        *at = None;
+                    }
+                }
+            }
+            Move::Comment(_) => (),
+        }
+    }
+    Duration::from_secs_f64(minutes * 60.0) + TOOL_CHANGE_TIME * tool_changes
+}
+
+/// Print how long `path` should take to run (see `estimate_time`)
+pub fn report_run_time(path: &[Move], rapid_rate: f64) {
+    eprintln!("{}", run_time_text(path, rapid_rate));
+}
+
+/// How long `path` should take to run, like "Estimated run time 1h 05m 30s"
+fn run_time_text(path: &[Move], rapid_rate: f64) -> String {
+    let time = estimate_time(path, rapid_rate).as_secs();
+    format!(
+        "Estimated run time {}h {:02}m {:02}s",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Wraps a writer, prefixing each line written through it with an `N` block number.
+/// Blank (or whitespace-only) lines, and the O-word lines that start subprograms, are passed through unnumbered.
+pub struct NumberedWriter<W: Write> {
+    inner: W,
+    next: u32,
+    step: u32,
+    at_line_start: bool,
+}
+
+impl<W: Write> NumberedWriter<W> {
+    /// Number lines N10, N20, N30, ...
+    pub fn new(inner: W) -> NumberedWriter<W> {
+        NumberedWriter::with_step(inner, 10)
+    }
+
+    pub fn with_step(inner: W, step: u32) -> NumberedWriter<W> {
+        NumberedWriter {
+            inner,
+            next: step,
+            step,
+            at_line_start: true,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for NumberedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // A single write may contain many lines (like the preamble), or only part of one
+        for line in buf.split_inclusive(|b| *b == b'\n') {
+            let blank = line.iter().all(|b| b.is_ascii_whitespace());
+            if self.at_line_start && !blank && !line.starts_with(b"O") {
+                write!(self.inner, "N{} ", self.next)?;
+                self.next += self.step;
+            }
+            self.inner.write_all(line)?;
+            if line.ends_with(b"\n") {
+                self.at_line_start = true;
+            } else if !blank {
+                self.at_line_start = false;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(got: f64, expected: f64) {
+        assert!(
+            (got - expected).abs() < 1e-9,
+            "got {}, expected {}",
+            got,
+            expected
+        );
+    }
+
+    #[test]
+    fn inverse_feed_of_a_pure_z_move() {
+        // 5mm down at 100mm/min takes 3 seconds, which is 1/20 of a minute
+        assert_close(inverse_feed(5.0, 100.0), 20.0);
+    }
+
+    #[test]
+    fn inverse_feed_of_a_pure_rotary_move() {
+        // A quarter turn of a 40mm part moves its surface 10*pi mm past the tool
+        let len = rotary_surface_distance(90.0, 40.0);
+        assert_close(len, 10.0 * PI);
+        assert_close(rotary_surface_distance(-90.0, 40.0), len);
+        assert_close(inverse_feed(len, 100.0), 10.0 / PI);
+    }
+
+    #[test]
+    fn inverse_feed_of_a_combined_move() {
+        // 30mm along X while a 40mm part turns 360/pi degrees, which moves its surface 40mm: a 50mm path at 100mm/min
+        // takes half a minute
+        let len = 30.0f64.hypot(rotary_surface_distance(360.0 / PI, 40.0));
+        assert_close(len, 50.0);
+        assert_close(inverse_feed(len, 100.0), 2.0);
+    }
+
+    #[test]
+    fn estimate_time_of_a_square() {
+        let u = Units::Metric;
+        let path = [
+            Move::Rapid(xyz(0.0, 0.0, 0.0).units(u)),
+            Move::Rapid(a(0.0).units(u)),
+            Move::Feed(xyf(10.0, 0.0, 100.0).units(u)),
+            Move::Feed(y(10.0).units(u)),
+            Move::Feed(x(0.0).units(u)),
+            Move::Feed(y(0.0).units(u)),
+        ];
+        // 40mm at 100mm/min
+        assert_close(estimate_time(&path, 600.0).as_secs_f64(), 24.0);
+
+        // A quarter turn of A at the rapid rate, a dwell, and a tool change add their own time
+        let mut path = path.to_vec();
+        path.push(Move::Rapid(a(90.0).units(u)));
+        path.push(Move::Dwell(2.0));
+        path.push(Move::ToolChange(2));
+        assert_close(
+            estimate_time(&path, 600.0).as_secs_f64(),
+            24.0 + 9.0 + 2.0 + TOOL_CHANGE_TIME.as_secs_f64(),
+        );
+    }
+
+    #[test]
+    fn parse_moves_follows_subprogram_calls() {
+        let called =
+            "G0 X0 Y0 Z0\nM98 P1000\nG0 A90\nM98 P1000\nM30\nO1000\nG1 X10 F100\nG0 X0\nM99\n";
+        let inline = "G0 X0 Y0 Z0\nG1 X10 F100\nG0 X0\nG0 A90\nG1 X10 F100\nG0 X0\nM30\n";
+        let (called, inline) = (parse_moves(called), parse_moves(inline));
+        let feeds = |moves: &[Move]| moves.iter().filter(|m| matches!(m, Move::Feed(_))).count();
+        assert_eq!(feeds(&called), 2);
+        assert_eq!(feeds(&inline), 2);
+        assert_eq!(called.len(), inline.len());
+        assert_eq!(estimate_time(&called, 600.0), estimate_time(&inline, 600.0));
+    }
+
+    fn g_text(v: f64, units: Units) -> String {
+        let mut text = vec![];
+        g_val(&mut text, "X", Some(v), units).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn g_val_minimal_form() {
+        let m = Units::Metric;
+        // Trig noise either side of an integer still prints minimally
+        assert_eq!(g_text(5.0, m), " X5.");
+        assert_eq!(g_text(4.99999999998, m), " X5.");
+        assert_eq!(g_text(5.00000000002, m), " X5.");
+        assert_eq!(g_text(-3.0 - 1e-11, m), " X-3.");
+        assert_eq!(g_text(-3.0 + 1e-11, m), " X-3.");
+        // Tiny values either side of zero are zero, without a sign
+        assert_eq!(g_text(1e-12, m), " X0.");
+        assert_eq!(g_text(-1e-12, m), " X0.");
+        assert_eq!(g_text(-0.0, m), " X0.");
+        // Outside the tolerance, the value is written out in full
+        assert_eq!(g_text(5.0 + 2.0 * MINIMAL_FORM_TOLERANCE, m), " X5.0000");
+        assert_eq!(g_text(4.99996, m), " X5.0000");
+        assert_eq!(g_text(-2.5, m), " X-2.5000");
+        assert_eq!(g_text(1e-6, m), " X0.0000");
+        assert_eq!(g_text(-2.5, Units::Imperial), " X-2.50000");
+        assert_eq!(g_text(0.12345678, Units::Imperial), " X0.12346");
+    }
+
+    #[test]
+    fn index_angles_close_the_circle() {
+        let angles = index_angles(17, 0.2, 42);
+        assert_eq!(angles.len(), 17);
+        assert_eq!(angles[0], 0.0);
+        // Each space, and the last one back round to the first tooth, is varied from the even spacing, but by no more
+        // than the variation allows, even after scaling them all to fit
+        let spaces: Vec<f64> = angles
+            .windows(2)
+            .map(|w| w[1] - w[0])
+            .chain([360.0 - angles[16]])
+            .collect();
+        let even = 360.0 / 17.0;
+        assert!(spaces
+            .iter()
+            .all(|s| s / even >= 0.8 / 1.2 && s / even <= 1.2 / 0.8));
+        assert!(spaces.iter().any(|s| (s - even).abs() > 0.01));
+        assert_close(spaces.iter().sum(), 360.0);
+    }
+
+    #[test]
+    fn index_angles_from_the_same_seed() {
+        assert_eq!(index_angles(12, 0.1, 7), index_angles(12, 0.1, 7));
+        assert_ne!(index_angles(12, 0.1, 7), index_angles(12, 0.1, 8));
+    }
+
+    #[test]
+    fn index_angles_with_no_variation() {
+        let angles = index_angles(8, 0.0, 7);
+        assert_eq!(angles, [0.0, 45.0, 90.0, 135.0, 180.0, 225.0, 270.0, 315.0]);
+    }
+
+    #[test]
+    fn emit_writes_the_dialect() {
+        let path = [Move::Comment("Flute 1".to_string()), Move::ToolChange(3)];
+        let text = |dialect| {
+            let mut text = vec![];
+            emit(&path, &mut text, dialect).unwrap();
+            String::from_utf8(text).unwrap()
+        };
+        assert_eq!(text(Dialect::LinuxCnc), "(Flute 1)\nT3 G43 H3 M6\n");
+        assert_eq!(
+            text(Dialect::Grbl),
+            "; Flute 1\nM0 ; Load T3, and set its Z0\n"
+        );
+    }
+
+    #[test]
+    fn parse_moves_reads_a_canned_cycle() {
+        let mut program = vec![];
+        g0(&mut program, z(5.0)).unwrap();
+        let hole = PosAndFeed {
+            feed: Some(50.0),
+            ..xy(1.0, 2.0)
+        };
+        peck_drill_g83(&mut program, hole, 1.0, -2.5, 1.0).unwrap();
+        let moves = parse_moves(&String::from_utf8(program).unwrap());
+        // Pecking down a millimeter at a time, and back out to the retract plane after each
+        let feeds: Vec<Option<f64>> = moves
+            .iter()
+            .filter_map(|m| match m {
+                Move::Feed(p) => Some(p.z),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(feeds, [Some(0.0), Some(-1.0), Some(-2.0), Some(-2.5)]);
+        let (min, max) = bounding_box(&moves);
+        assert_eq!((min[0], min[1], min[2]), (Some(1.0), Some(2.0), Some(-2.5)));
+        assert_eq!(max[2], Some(5.0));
+        match moves.last() {
+            Some(Move::Rapid(p)) => assert_eq!(p.z, Some(1.0)),
+            m => panic!("ended with {:?}", m),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Checks on a finished program, read back from its text, for motion that would be unsafe on the machine even though
+//! every line of it is well formed: the kind of thing a slip in a generator's geometry produces.
+//! Only the words this crate writes are understood, the same as `parse_moves`.
+use std::fmt;
+
+/// Kinds of problem to check for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// A feed move with no feed rate set since the feed mode last changed, or with none of its own in inverse time
+    /// (G93) mode, which needs one on every move
+    NoFeed,
+    /// A rapid to below Z0, in absolute mode and work coordinates
+    RapidBelowZ0,
+    /// A G93 with inverse time already on, or a program that ends with it on, rather than each G93 matched by a G94
+    UnmatchedG93,
+    /// A tool change (M6) with the spindle still running
+    SpindleOnToolChange,
+}
+
+/// A problem found in a program, on its `line` (counting from 1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub line: usize,
+    pub kind: LintKind,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Check `gcode` for every `LintKind` of problem
+pub fn lint(gcode: &str) -> Vec<Lint> {
+    let mut lints = vec![];
+    let mut inverse_time = false;
+    let mut feed_set = false;
+    let mut spindle_on = false;
+    let mut absolute = true;
+    let mut motion = None;
+    let mut ended = false;
+    let mut last_line = 0;
+
+    for (i, line) in gcode.lines().enumerate() {
+        let n = i + 1;
+        let mut line = line.trim();
+        // Drop any block number
+        if line.starts_with('N') {
+            line = line.split_once(' ').map_or("", |(_, rest)| rest);
+        }
+        let code = line.split(['(', ';']).next().unwrap_or("");
+        let words: Vec<&str> = code.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        last_line = n;
+        let value = |letter: char| -> Option<f64> {
+            words
+                .iter()
+                .find(|w| w.starts_with(letter))
+                .and_then(|w| w[1..].parse().ok())
+        };
+        let mut lint = |kind, message: String| {
+            lints.push(Lint {
+                line: n,
+                kind,
+                message,
+            })
+        };
+
+        let mut line_motion = None;
+        let mut machine_coords = false;
+        for &word in &words {
+            match word {
+                "G0" | "G1" | "G2" | "G3" | "G38.2" | "G81" | "G83" => line_motion = Some(word),
+                "G80" => motion = None,
+                "G28" | "G30" | "G53" => machine_coords = true,
+                "G90" => absolute = true,
+                "G91" => absolute = false,
+                "G93" => {
+                    if inverse_time {
+                        lint(LintKind::UnmatchedG93, "G93 with inverse time feed already on, so the last G93 has no G94 to match it".into());
+                    } else {
+                        feed_set = false;
+                    }
+                    inverse_time = true;
+                }
+                "G94" => {
+                    if inverse_time {
+                        feed_set = false;
+                    }
+                    inverse_time = false;
+                }
+                "M3" | "M4" => spindle_on = true,
+                "M5" => spindle_on = false,
+                "M6" => {
+                    if spindle_on {
+                        lint(
+                            LintKind::SpindleOnToolChange,
+                            "Tool change (M6) with the spindle still running, and no M5 before it"
+                                .into(),
+                        );
+                    }
+                    // The new tool's spindle only starts when it's told to
+                    spindle_on = false;
+                }
+                "M30" => {
+                    if inverse_time {
+                        lint(
+                            LintKind::UnmatchedG93,
+                            "Program ends with inverse time feed (G93) still on".into(),
+                        );
+                    }
+                    // Only subprograms follow the end of the program, and they're called in the main program's modes
+                    ended = true;
+                }
+                _ => (),
+            }
+        }
+        if line_motion.is_some() {
+            motion = line_motion;
+        }
+        let feed = value('F');
+        if feed.is_some() {
+            feed_set = true;
+        }
+        let has_axes = ['X', 'Y', 'Z', 'A']
+            .iter()
+            .any(|&axis| value(axis).is_some());
+        if machine_coords || !has_axes {
+            continue;
+        }
+
+        match motion {
+            Some("G0") => {
+                if let Some(z) = value('Z').filter(|&z| absolute && z < 0.0) {
+                    lint(
+                        LintKind::RapidBelowZ0,
+                        format!("Rapid (G0) to Z{}, below Z0", z),
+                    );
+                }
+            }
+            Some(_) if inverse_time && feed.is_none() => lint(
+                LintKind::NoFeed,
+                "Feed move in inverse time (G93) mode with no F of its own".into(),
+            ),
+            Some(_) if !feed_set => lint(
+                LintKind::NoFeed,
+                "Feed move before any feed rate (F) is set".into(),
+            ),
+            _ => (),
+        }
+    }
+    if inverse_time && !ended {
+        lints.push(Lint {
+            line: last_line,
+            kind: LintKind::UnmatchedG93,
+            message: "Program ends with inverse time feed (G93) still on".into(),
+        });
+    }
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Where each lint is, and what kind it is
+    fn found(gcode: &str) -> Vec<(usize, LintKind)> {
+        lint(gcode).iter().map(|l| (l.line, l.kind)).collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        let gcode =
+            "(Job)\nG90 G94\nG21\nT1 G43 H1 M6\nS1000 M3\nG0 X0 Y0 Z5\nG1 Z-1 F100\nG1 X10\n\
+            G93\nG1 X20 A90 F2\nG94\nG1 X0 F100\nG0 Z5\nG91 G0 Z-10\nG90\nG53 G0 Z-20\nM5\nM30\n";
+        assert_eq!(found(gcode), vec![]);
+    }
+
+    #[test]
+    fn feed_with_no_feed_rate() {
+        assert_eq!(found("G0 X0 Z1\nG1 Z-1\n"), vec![(2, LintKind::NoFeed)]);
+        // Changing the feed mode drops the feed rate, and every G93 move needs its own
+        let gcode = "G1 X1 F100\nG93\nG1 X2 F2\nG1 X3\nG94\nG1 X4\n";
+        assert_eq!(
+            found(gcode),
+            vec![(4, LintKind::NoFeed), (6, LintKind::NoFeed)]
+        );
+    }
+
+    #[test]
+    fn rapid_below_z0() {
+        assert_eq!(
+            found("G0 X0 Y0\nN20 G0 Z-0.5\n"),
+            vec![(2, LintKind::RapidBelowZ0)]
+        );
+    }
+
+    #[test]
+    fn unmatched_g93() {
+        assert_eq!(
+            found("G93\nG1 X1 F2\nG93\nG94\n"),
+            vec![(3, LintKind::UnmatchedG93)]
+        );
+        assert_eq!(
+            found("G93\nG1 X1 F2\nM30\n"),
+            vec![(3, LintKind::UnmatchedG93)]
+        );
+        assert_eq!(found("G93\nG1 X1 F2\n"), vec![(2, LintKind::UnmatchedG93)]);
+    }
+
+    #[test]
+    fn spindle_on_tool_change() {
+        assert_eq!(
+            found("S1000 M3\nT2 G43 H2 M6\nS500 M3\nM5\nT3 G43 H3 M6\n"),
+            vec![(2, LintKind::SpindleOnToolChange)]
+        );
+    }
+}
@@ -0,0 +1,2000 @@
+//! Toolpath patterns for flat work in the X-Y plane, that aren't tied to any one kind of part.
+use crate::{g0, g1, g2r, g3r, xy, xyf, xyrf, z, zf, Move, Units};
+use std::f64::consts::PI;
+use std::io::{Result, Write};
+
+/// Which way a spiral runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpiralDirection {
+    /// From the center out to the edge
+    Outward,
+    /// From the edge in to the center
+    Inward,
+}
+
+/// How to clear a circular pocket with an Archimedean spiral, all in the job's units
+#[derive(Debug, Clone, Copy)]
+pub struct SpiralPocket {
+    pub center: (f64, f64),
+    /// Radius of the path of the tool's center at the edge of the pocket: the pocket's radius, less the tool's
+    pub radius: f64,
+    /// Distance between laps of the spiral
+    pub step_over: f64,
+    /// Depth of the pocket's floor
+    pub z_cut: f64,
+    /// Height to rapid at, clear of the work, before and after the cut
+    pub z_safe: f64,
+    pub feed: f64,
+    pub direction: SpiralDirection,
+    /// Go once round the edge at full radius at the end, to clean up the wall
+    pub cleanup: bool,
+    pub units: Units,
+}
+
+/// Points along the spiral, relative to its center, from the center out to `radius`, growing `step_over` each lap.
+/// The points are close enough together that straight moves between them stay within about 0.5% of `step_over` of
+/// the true spiral. Both `radius` and `step_over` must be more than 0.
+pub fn spiral_points(radius: f64, step_over: f64) -> Vec<(f64, f64)> {
+    assert!(step_over > 0.0 && radius > 0.0);
+    let end = 2.0 * PI * radius / step_over;
+    let mut points = vec![(0.0, 0.0)];
+    let mut angle: f64 = 0.0;
+    while angle < end {
+        let r = step_over * angle / (2.0 * PI);
+        // At least 36 moves a lap, and short enough to follow the curve out at the edge
+        angle = (angle + (PI / 18.0).min(0.2 * (step_over / r.max(step_over)).sqrt())).min(end);
+        let r = (step_over * angle / (2.0 * PI)).min(radius);
+        points.push((r * angle.cos(), r * angle.sin()));
+    }
+    points
+}
+
+/// How to cut a straight slot with a trochoidal path, in light arcing bites rather than the full width of the tool at
+/// once, all in the job's units
+#[derive(Debug, Clone, Copy)]
+pub struct TrochoidalSlot {
+    /// Center of the round end of the slot where the cut starts
+    pub start: (f64, f64),
+    /// Center of the round end of the slot where the cut finishes
+    pub end: (f64, f64),
+    /// Width of the slot, which must be more than `tool_dia`
+    pub width: f64,
+    pub tool_dia: f64,
+    /// Distance the loops advance along the slot, each one taking a bite this thick at its widest
+    pub step: f64,
+    /// Depth of the slot's floor
+    pub z_cut: f64,
+    /// Height to rapid at, clear of the work, before and after the cut
+    pub z_safe: f64,
+    pub feed: f64,
+    pub units: Units,
+}
+
+/// Go once round the circle of `radius` about `center`, counter-clockwise, starting and ending at `from`, in quarter
+/// circle arcs (a whole circle is ambiguous in radius form)
+fn full_circle(
+    file: &mut dyn Write,
+    center: (f64, f64),
+    radius: f64,
+    from: (f64, f64),
+    feed: f64,
+    units: Units,
+) -> Result<()> {
+    let start = (from.1 - center.1).atan2(from.0 - center.0);
+    let mut at = from;
+    for i in 1..=4 {
+        let angle = start + i as f64 * PI / 2.0;
+        let to = (
+            center.0 + radius * angle.cos(),
+            center.1 + radius * angle.sin(),
+        );
+        g3r(file, at, xyrf(to.0, to.1, radius, feed).units(units))?;
+        at = to;
+    }
+    Ok(())
+}
+
+/// Clear a circular pocket down to `z_cut` in a single layer, with a spiral cut counter-clockwise (climb milling, with
+/// a clockwise spindle), plunging at its start and raising to `z_safe` at the end
+pub fn spiral_pocket(file: &mut dyn Write, cut: &SpiralPocket) -> Result<()> {
+    let u = cut.units;
+    let mut points: Vec<(f64, f64)> = spiral_points(cut.radius, cut.step_over)
+        .into_iter()
+        .map(|(x, y)| (cut.center.0 + x, cut.center.1 + y))
+        .collect();
+    if cut.direction == SpiralDirection::Inward {
+        // Follow the same spiral backwards, so it still turns counter-clockwise
+        points = points
+            .iter()
+            .map(|&(x, y)| (x, 2.0 * cut.center.1 - y))
+            .rev()
+            .collect();
+    }
+
+    let start = points[0];
+    g0(file, z(cut.z_safe).units(u))?;
+    g0(file, xy(start.0, start.1).units(u))?;
+    g1(file, zf(cut.z_cut, cut.feed).units(u))?;
+    for &(x, y) in &points[1..] {
+        g1(file, xyf(x, y, cut.feed).units(u))?;
+    }
+
+    if cut.cleanup {
+        let edge = match cut.direction {
+            SpiralDirection::Outward => points[points.len() - 1],
+            SpiralDirection::Inward => {
+                // Feed back out to where the spiral started, across the cleared floor
+                g1(file, xyf(start.0, start.1, cut.feed).units(u))?;
+                start
+            }
+        };
+        full_circle(file, cut.center, cut.radius, edge, cut.feed, u)?;
+    }
+    g0(file, z(cut.z_safe).units(u))
+}
+
+/// Centers of the loops of a trochoidal slot, from `start` to `end`, evenly spaced no more than `step` apart
+pub fn trochoid_centers(start: (f64, f64), end: (f64, f64), step: f64) -> Vec<(f64, f64)> {
+    assert!(step > 0.0);
+    let len = (end.0 - start.0).hypot(end.1 - start.1);
+    let loops = (len / step).ceil().max(1.0) as usize;
+    (0..=loops)
+        .map(|i| {
+            let along = i as f64 / loops as f64;
+            (
+                start.0 + (end.0 - start.0) * along,
+                start.1 + (end.1 - start.1) * along,
+            )
+        })
+        .collect()
+}
+
+/// Cut a straight slot down to `z_cut` in a single layer, plunging at the start and raising to `z_safe` at the end.
+/// Each loop goes once round a circle that just touches both walls, counter-clockwise (climb milling, with a clockwise
+/// spindle), and then feeds along the right-hand wall to the start of the next loop.
+pub fn trochoidal_slot(file: &mut dyn Write, cut: &TrochoidalSlot) -> Result<()> {
+    assert!(cut.width > cut.tool_dia);
+    let u = cut.units;
+    let radius = (cut.width - cut.tool_dia) / 2.0;
+    let angle = (cut.end.1 - cut.start.1).atan2(cut.end.0 - cut.start.0);
+    // Offset from the center of each loop to where it starts and ends, on the wall to the right of the direction of cut
+    let right = (radius * angle.sin(), -radius * angle.cos());
+
+    let centers = trochoid_centers(cut.start, cut.end, cut.step);
+    let first = (centers[0].0 + right.0, centers[0].1 + right.1);
+    g0(file, z(cut.z_safe).units(u))?;
+    g0(file, xy(first.0, first.1).units(u))?;
+    g1(file, zf(cut.z_cut, cut.feed).units(u))?;
+    for (i, &center) in centers.iter().enumerate() {
+        let from = (center.0 + right.0, center.1 + right.1);
+        if i > 0 {
+            g1(file, xyf(from.0, from.1, cut.feed).units(u))?;
+        }
+        full_circle(file, center, radius, from, cut.feed, u)?;
+    }
+    g0(file, z(cut.z_safe).units(u))
+}
+
+/// How to clear a convex pocket with rings that follow its outline, each one `step_over` inside the last, all in the
+/// job's units
+#[derive(Debug, Clone, Copy)]
+pub struct ContourPocket<'a> {
+    /// Corners of the pocket's outline, in order either way round. The outline must be convex.
+    pub boundary: &'a [(f64, f64)],
+    pub tool_dia: f64,
+    /// Distance between rings, which must be no more than the tool's radius for the innermost ring to clear the middle
+    pub step_over: f64,
+    /// Depth of the pocket's floor
+    pub z_cut: f64,
+    /// Height to rapid at, clear of the work, before and after the cut
+    pub z_safe: f64,
+    pub feed: f64,
+    pub units: Units,
+}
+
+/// Twice the area of `polygon`, positive if its corners run counter-clockwise
+fn double_area(polygon: &[(f64, f64)]) -> f64 {
+    let n = polygon.len();
+    (0..n)
+        .map(|i| {
+            let (p, q) = (polygon[i], polygon[(i + 1) % n]);
+            p.0 * q.1 - q.0 * p.1
+        })
+        .sum()
+}
+
+/// Whether `polygon` is convex, turning the same way (or not at all) at every corner
+fn is_convex(polygon: &[(f64, f64)]) -> bool {
+    let n = polygon.len();
+    let turns: Vec<f64> = (0..n)
+        .map(|i| {
+            let (p, q, r) = (polygon[i], polygon[(i + 1) % n], polygon[(i + 2) % n]);
+            (q.0 - p.0) * (r.1 - q.1) - (q.1 - p.1) * (r.0 - q.0)
+        })
+        .collect();
+    turns.iter().all(|&t| t >= 0.0) || turns.iter().all(|&t| t <= 0.0)
+}
+
+/// Clip `polygon` to the half plane to the left of the line through `p` along `dir`, moved `distance` to its left
+fn clip_left(
+    polygon: &[(f64, f64)],
+    p: (f64, f64),
+    dir: (f64, f64),
+    distance: f64,
+) -> Vec<(f64, f64)> {
+    let len = dir.0.hypot(dir.1);
+    // How far each point is inside the half plane
+    let inside = |q: (f64, f64)| (dir.0 * (q.1 - p.1) - dir.1 * (q.0 - p.0)) / len - distance;
+    let mut clipped = vec![];
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+        let (da, db) = (inside(a), inside(b));
+        if da >= 0.0 {
+            clipped.push(a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            let t = da / (da - db);
+            clipped.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+        }
+    }
+    clipped
+}
+
+/// The convex `boundary` moved `distance` inwards, counter-clockwise, or `None` if nothing of it is left. Each side
+/// moves in along its normal, so sharp corners stay sharp, and sides that shrink away to nothing drop out.
+pub fn offset_convex(boundary: &[(f64, f64)], distance: f64) -> Option<Vec<(f64, f64)>> {
+    let mut outline = boundary.to_vec();
+    if double_area(&outline) < 0.0 {
+        outline.reverse();
+    }
+    let mut offset = outline.clone();
+    for i in 0..outline.len() {
+        let (p, q) = (outline[i], outline[(i + 1) % outline.len()]);
+        offset = clip_left(&offset, p, (q.0 - p.0, q.1 - p.1), distance);
+    }
+    // Drop the corners that clipping leaves on top of each other, including the last on top of the first
+    let same = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1) < 1e-9;
+    offset.dedup_by(|a, b| same(*a, *b));
+    while offset.len() > 1 && same(offset[0], offset[offset.len() - 1]) {
+        offset.pop();
+    }
+    if offset.len() >= 3 && double_area(&offset) > 1e-9 {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+/// How to join the sides of an offset path round the outside of a corner, where they move apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Join {
+    /// Carry both sides on until they meet, in a sharp corner. One that would reach out more than `MITER_LIMIT` times
+    /// the offset is cut off square across its tip instead.
+    Miter,
+    /// Go round the corner in an arc about it, like the edge of the tool does
+    Round,
+}
+
+/// Furthest out a mitered corner reaches, as a multiple of the offset
+const MITER_LIMIT: f64 = 4.0;
+
+/// Where the lines through `a` and `b` cross, or `None` if they're parallel
+fn line_intersection(
+    a: ((f64, f64), (f64, f64)),
+    b: ((f64, f64), (f64, f64)),
+) -> Option<(f64, f64)> {
+    crossing(a, b).map(|(t, _)| {
+        (
+            a.0 .0 + (a.1 .0 - a.0 .0) * t,
+            a.0 .1 + (a.1 .1 - a.0 .1) * t,
+        )
+    })
+}
+
+/// Points joining the offset side `from` to the offset side `to`, which follows it round the `corner`, for a path
+/// moved `distance` to its left
+fn offset_join(
+    from: ((f64, f64), (f64, f64)),
+    to: ((f64, f64), (f64, f64)),
+    corner: (f64, f64),
+    distance: f64,
+    join: Join,
+) -> Vec<(f64, f64)> {
+    let d1 = (from.1 .0 - from.0 .0, from.1 .1 - from.0 .1);
+    let d2 = (to.1 .0 - to.0 .0, to.1 .1 - to.0 .1);
+    let turn = d1.0 * d2.1 - d1.1 * d2.0;
+    let meet = match line_intersection(from, to) {
+        // Carrying straight on
+        None => return vec![from.1],
+        Some(meet) => meet,
+    };
+    if turn * distance > 0.0 {
+        // The path turns towards the offset, so the sides overlap, and are cut back to where they cross. If they're too
+        // short to reach each other, they're carried on to where they would, and the loop that leaves is clipped off
+        // afterwards.
+        return vec![segment_intersection(from, to).unwrap_or(meet)];
+    }
+    match join {
+        Join::Miter => {
+            if (meet.0 - corner.0).hypot(meet.1 - corner.1) <= MITER_LIMIT * distance.abs() {
+                vec![meet]
+            } else {
+                vec![from.1, to.0]
+            }
+        }
+        Join::Round => {
+            let angle = |p: (f64, f64)| (p.1 - corner.1).atan2(p.0 - corner.0);
+            let start = angle(from.1);
+            // Round the outside of the corner, which is the short way
+            let mut sweep = (angle(to.0) - start).rem_euclid(2.0 * PI);
+            if sweep > PI {
+                sweep -= 2.0 * PI;
+            }
+            // At least 36 steps to a whole circle, as for a spiral
+            let steps = (sweep.abs() / (PI / 18.0)).ceil().max(1.0) as usize;
+            let r = distance.abs();
+            let mut points: Vec<(f64, f64)> = (0..steps)
+                .map(|i| {
+                    let a = start + sweep * i as f64 / steps as f64;
+                    (corner.0 + r * a.cos(), corner.1 + r * a.sin())
+                })
+                .collect();
+            points.push(to.0);
+            points
+        }
+    }
+}
+
+/// Cut the loops out of `path`, wherever one of its sides crosses another further along. A closed path keeps the
+/// bigger of the two parts each crossing splits it into.
+fn clip_loops(mut path: Vec<(f64, f64)>, closed: bool) -> Vec<(f64, f64)> {
+    'search: loop {
+        let n = path.len();
+        let sides = if closed { n } else { n.saturating_sub(1) };
+        for i in 0..sides {
+            // Neighboring sides always meet at their shared corner, so only check those further along
+            for j in i + 2..sides {
+                if closed && i == 0 && j == n - 1 {
+                    continue;
+                }
+                let a = (path[i], path[(i + 1) % n]);
+                let b = (path[j], path[(j + 1) % n]);
+                if let Some(p) = segment_intersection(a, b) {
+                    let mut outside: Vec<(f64, f64)> = path[..=i].to_vec();
+                    outside.push(p);
+                    outside.extend_from_slice(&path[j + 1..]);
+                    path = if closed {
+                        let mut inside = vec![p];
+                        inside.extend_from_slice(&path[i + 1..=j]);
+                        if double_area(&inside).abs() > double_area(&outside).abs() {
+                            inside
+                        } else {
+                            outside
+                        }
+                    } else {
+                        outside
+                    };
+                    continue 'search;
+                }
+            }
+        }
+        return path;
+    }
+}
+
+/// The polyline through `points` moved `distance` to its left (or, if negative, to its right), going the same way.
+/// A `closed` path joins its last point back to its first. Sides that move apart round the outside of a corner are
+/// joined as `join` says, and sides that overlap round the inside of one are cut back to where they cross. Where a
+/// side is too short for that, so the offset path would loop back over itself, the loop is clipped off.
+/// A closed convex outline moved inwards is `offset_convex`'s, as whole sides can vanish, and an empty path comes back
+/// if nothing of it is left.
+pub fn offset_polyline(
+    points: &[(f64, f64)],
+    distance: f64,
+    join: Join,
+    closed: bool,
+) -> Vec<(f64, f64)> {
+    let mut points = points.to_vec();
+    points.dedup_by(|a, b| (a.0 - b.0).hypot(a.1 - b.1) < 1e-9);
+    if closed {
+        while points.len() > 1 && points[0] == points[points.len() - 1] {
+            points.pop();
+        }
+    }
+    assert!(
+        points.len() >= if closed { 3 } else { 2 },
+        "Refusing to offset a path with too few points"
+    );
+    if distance == 0.0 {
+        return points;
+    }
+    if closed && is_convex(&points) && distance * double_area(&points) > 0.0 {
+        let mut offset = offset_convex(&points, distance.abs()).unwrap_or_default();
+        if double_area(&points) < 0.0 {
+            offset.reverse();
+        }
+        return offset;
+    }
+
+    let n = points.len();
+    let sides: Vec<((f64, f64), (f64, f64))> = (0..if closed { n } else { n - 1 })
+        .map(|i| {
+            let (p, q) = (points[i], points[(i + 1) % n]);
+            let len = (q.0 - p.0).hypot(q.1 - p.1);
+            // Left of the direction of travel
+            let normal = (-(q.1 - p.1) / len * distance, (q.0 - p.0) / len * distance);
+            (
+                (p.0 + normal.0, p.1 + normal.1),
+                (q.0 + normal.0, q.1 + normal.1),
+            )
+        })
+        .collect();
+    let mut offset = vec![];
+    if closed {
+        for i in 0..sides.len() {
+            let last = sides[(i + sides.len() - 1) % sides.len()];
+            offset.extend(offset_join(last, sides[i], points[i], distance, join));
+        }
+    } else {
+        offset.push(sides[0].0);
+        for i in 1..sides.len() {
+            offset.extend(offset_join(
+                sides[i - 1],
+                sides[i],
+                points[i],
+                distance,
+                join,
+            ));
+        }
+        offset.push(sides[sides.len() - 1].1);
+    }
+    clip_loops(offset, closed)
+}
+
+/// Rings for the tool's center to follow to clear the convex `boundary`, from the innermost out to the one that
+/// finishes the wall. The outermost is half of `tool_dia` inside the boundary, and each is `step_over` inside the last,
+/// until there's no room for another. The `step_over` must be more than 0, and no more than the tool's radius.
+pub fn contour_rings(
+    boundary: &[(f64, f64)],
+    tool_dia: f64,
+    step_over: f64,
+) -> Vec<Vec<(f64, f64)>> {
+    assert!(is_convex(boundary));
+    assert!(
+        step_over > 0.0 && step_over <= tool_dia / 2.0,
+        "Refusing to clear a pocket with a step over of {}, which must be more than 0 and no more than the tool's radius",
+        step_over
+    );
+    let mut rings = vec![];
+    while let Some(ring) = offset_convex(boundary, tool_dia / 2.0 + rings.len() as f64 * step_over)
+    {
+        rings.push(ring);
+    }
+    rings.reverse();
+    rings
+}
+
+/// Clear a convex pocket down to `z_cut` in a single layer, plunging in the middle and working out a ring at a time,
+/// each cut counter-clockwise (climb milling, with a clockwise spindle) and joined to the next by a straight feed. It
+/// ends at the wall, and raises to `z_safe`.
+pub fn contour_pocket(file: &mut dyn Write, cut: &ContourPocket) -> Result<()> {
+    let u = cut.units;
+    let rings = contour_rings(cut.boundary, cut.tool_dia, cut.step_over);
+    if rings.is_empty() {
+        return Ok(());
+    }
+
+    g0(file, z(cut.z_safe).units(u))?;
+    let mut at = rings[0][0];
+    g0(file, xy(at.0, at.1).units(u))?;
+    g1(file, zf(cut.z_cut, cut.feed).units(u))?;
+    for (i, ring) in rings.iter().enumerate() {
+        // Start each ring at its corner closest to where the last one finished, to keep the step across short
+        let start = (0..ring.len())
+            .min_by(|&a, &b| {
+                let distance = |p: (f64, f64)| (p.0 - at.0).hypot(p.1 - at.1);
+                distance(ring[a]).total_cmp(&distance(ring[b]))
+            })
+            .unwrap_or(0);
+        if i > 0 {
+            g1(file, xyf(ring[start].0, ring[start].1, cut.feed).units(u))?;
+        }
+        for j in 1..=ring.len() {
+            let (x, y) = ring[(start + j) % ring.len()];
+            g1(file, xyf(x, y, cut.feed).units(u))?;
+        }
+        at = ring[start];
+    }
+    g0(file, z(cut.z_safe).units(u))
+}
+
+/// Holding tabs left standing along a contour, so the part stays attached to the stock until it's cut free by hand
+#[derive(Debug, Clone, Copy)]
+pub struct Tabs<'a> {
+    /// Middle of each tab, as a fraction (0 to 1) of the way round the contour from its first corner
+    pub positions: &'a [f64],
+    /// Length of each tab, along the contour
+    pub width: f64,
+    /// Height of each tab, above the floor of the cut
+    pub height: f64,
+}
+
+/// Distance along `contour` (closed back to its first corner) of each corner, ending with the full length round
+fn corner_distances(contour: &[(f64, f64)]) -> Vec<f64> {
+    let mut distances = vec![0.0];
+    for i in 0..contour.len() {
+        let (p, q) = (contour[i], contour[(i + 1) % contour.len()]);
+        distances.push(distances[i] + (q.0 - p.0).hypot(q.1 - p.1));
+    }
+    distances
+}
+
+/// The point `s` along `contour`, given its `corner_distances`
+fn point_along(contour: &[(f64, f64)], corners: &[f64], s: f64) -> (f64, f64) {
+    let i = (1..corners.len())
+        .find(|&i| s <= corners[i])
+        .unwrap_or(corners.len() - 1)
+        - 1;
+    let (p, q) = (contour[i], contour[(i + 1) % contour.len()]);
+    let len = corners[i + 1] - corners[i];
+    let t = if len > 0.0 {
+        (s - corners[i]) / len
+    } else {
+        0.0
+    };
+    (p.0 + (q.0 - p.0) * t, p.1 + (q.1 - p.1) * t)
+}
+
+/// Moves following the closed `contour` once round at `z_cut`, rising over each of the `tabs` and back down after it.
+/// The path starts by feeding down to depth at the first corner, so the tool should already be just above it, and ends
+/// back there. A tab can reach past a corner, or over several sides, or across the start, and tabs that overlap lift
+/// the tool just once.
+pub fn tabbed_contour(
+    contour: &[(f64, f64)],
+    tabs: &Tabs,
+    z_cut: f64,
+    feed: f64,
+    units: Units,
+) -> Vec<Move> {
+    let corners = corner_distances(contour);
+    let len = corners[corners.len() - 1];
+    let in_tab = |s: f64| {
+        tabs.positions.iter().any(|position| {
+            let from_middle = (s - position * len).rem_euclid(len);
+            from_middle.min(len - from_middle) < tabs.width / 2.0
+        })
+    };
+
+    // Stop at every corner, and at each end of every tab
+    let mut stops = corners.clone();
+    for position in tabs.positions {
+        for edge in [-0.5, 0.5] {
+            stops.push((position * len + edge * tabs.width).rem_euclid(len));
+        }
+    }
+    stops.sort_by(f64::total_cmp);
+    stops.dedup_by(|a, b| (*a - *b).abs() < 1e-9);
+
+    let mut moves = vec![];
+    let mut z_at = None;
+    for pair in stops.windows(2) {
+        // Each stretch between stops is either all on a tab or all off them
+        let z_next = if in_tab((pair[0] + pair[1]) / 2.0) {
+            z_cut + tabs.height
+        } else {
+            z_cut
+        };
+        if z_at != Some(z_next) {
+            moves.push(Move::Feed(zf(z_next, feed).units(units)));
+            z_at = Some(z_next);
+        }
+        let (x, y) = point_along(contour, &corners, pair[1]);
+        moves.push(Move::Feed(xyf(x, y, feed).units(units)));
+    }
+    moves
+}
+
+/// How long a scale mark is, by what it marks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickSize {
+    /// A long mark: each whole number, 1 to 10, in every decade of a log scale, or every so many marks round a dial
+    Major,
+    /// Each subdivision between them
+    Minor,
+}
+
+/// Marks on a logarithmic scale of `decades` decades, as their fraction of the way along it and their size.
+/// The subdivisions follow the usual 1-2-5 density: tenths from 1 to 2, fifths from 2 to 5, and halves from 5 to 10.
+pub fn log_scale_ticks(decades: u32) -> Vec<(f64, TickSize)> {
+    let mut ticks = vec![];
+    for decade in 0..decades {
+        // Work in tenths, so the values are exact
+        let mut tenths = 10;
+        while tenths < 100 {
+            let size = if tenths % 10 == 0 {
+                TickSize::Major
+            } else {
+                TickSize::Minor
+            };
+            let fraction = (decade as f64 + (tenths as f64 / 10.0).log10()) / decades as f64;
+            ticks.push((fraction, size));
+            tenths += match tenths {
+                10..=19 => 1,
+                20..=49 => 2,
+                _ => 5,
+            };
+        }
+    }
+    ticks.push((1.0, TickSize::Major));
+    ticks
+}
+
+/// How to engrave a straight logarithmic scale along +X, with its marks standing up in +Y, all in the job's units
+#[derive(Debug, Clone, Copy)]
+pub struct LogScale {
+    /// Bottom of the mark for 1, at the start of the scale
+    pub origin: (f64, f64),
+    /// Length of the whole scale, along X
+    pub length: f64,
+    pub decades: u32,
+    pub major_len: f64,
+    pub minor_len: f64,
+    /// Depth of the marks
+    pub z_cut: f64,
+    /// Height to rapid at between marks, clear of the work
+    pub z_safe: f64,
+    pub feed: f64,
+    pub units: Units,
+}
+
+/// Engrave every mark of a logarithmic scale, each one cut up from its bottom end
+pub fn log_scale(file: &mut dyn Write, scale: &LogScale) -> Result<()> {
+    let u = scale.units;
+    g0(file, z(scale.z_safe).units(u))?;
+    for (fraction, size) in log_scale_ticks(scale.decades) {
+        let x = scale.origin.0 + fraction * scale.length;
+        let len = match size {
+            TickSize::Major => scale.major_len,
+            TickSize::Minor => scale.minor_len,
+        };
+        g0(file, xy(x, scale.origin.1).units(u))?;
+        g1(file, zf(scale.z_cut, scale.feed).units(u))?;
+        g1(file, xyf(x, scale.origin.1 + len, scale.feed).units(u))?;
+        g0(file, z(scale.z_safe).units(u))?;
+    }
+    Ok(())
+}
+
+/// How to engrave evenly spaced marks round a dial, each pointing in towards its center, all in the job's units
+#[derive(Debug, Clone, Copy)]
+pub struct RadialTicks {
+    pub center: (f64, f64),
+    /// Number of marks, going clockwise round the dial from the first, straight up (+Y)
+    pub count: u32,
+    /// Radius of the outer end of every mark
+    pub outer_radius: f64,
+    /// Radius the minor marks reach in to
+    pub inner_radius: f64,
+    /// Every this many marks is a major mark, starting with the first
+    pub major_every: u32,
+    /// Radius the major marks reach in to, which is less than `inner_radius` to make them longer
+    pub major_inner_radius: f64,
+    /// Depth of the marks
+    pub z_cut: f64,
+    /// Height to rapid at between marks, clear of the work
+    pub z_safe: f64,
+    pub feed: f64,
+    pub units: Units,
+}
+
+/// The size of mark `index` round a dial, with a major mark every `major_every` marks from the first
+pub fn radial_tick_size(index: u32, major_every: u32) -> TickSize {
+    assert!(
+        major_every > 0,
+        "Refusing to make every 0th mark a major one"
+    );
+    if index.is_multiple_of(major_every) {
+        TickSize::Major
+    } else {
+        TickSize::Minor
+    }
+}
+
+/// One mark round a dial, from its outer end in to its inner one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RadialTick {
+    pub outer: (f64, f64),
+    pub inner: (f64, f64),
+    pub size: TickSize,
+}
+
+/// The marks round a dial, in order
+pub fn radial_tick_marks(ticks: &RadialTicks) -> Vec<RadialTick> {
+    (0..ticks.count)
+        .map(|i| {
+            let (sin, cos) = (PI / 2.0 - 2.0 * PI * i as f64 / ticks.count as f64).sin_cos();
+            let at = |r: f64| (ticks.center.0 + r * cos, ticks.center.1 + r * sin);
+            let size = radial_tick_size(i, ticks.major_every);
+            let inner = match size {
+                TickSize::Major => ticks.major_inner_radius,
+                TickSize::Minor => ticks.inner_radius,
+            };
+            RadialTick {
+                outer: at(ticks.outer_radius),
+                inner: at(inner),
+                size,
+            }
+        })
+        .collect()
+}
+
+/// Engrave every mark round a dial, each one cut in from its outer end
+pub fn radial_ticks(file: &mut dyn Write, ticks: &RadialTicks) -> Result<()> {
+    let u = ticks.units;
+    g0(file, z(ticks.z_safe).units(u))?;
+    for RadialTick { outer, inner, .. } in radial_tick_marks(ticks) {
+        g0(file, xy(outer.0, outer.1).units(u))?;
+        g1(file, zf(ticks.z_cut, ticks.feed).units(u))?;
+        g1(file, xyf(inner.0, inner.1, ticks.feed).units(u))?;
+        g0(file, z(ticks.z_safe).units(u))?;
+    }
+    Ok(())
+}
+
+/// How to engrave an {n/k} star polygon, or a plain regular polygon with a `step` of 1, all in the job's units
+#[derive(Debug, Clone, Copy)]
+pub struct StarPolygon<'a> {
+    pub center: (f64, f64),
+    /// Radius of the circle through the points
+    pub radius: f64,
+    /// Number of points, evenly spaced round the circle with the first straight up (+Y)
+    pub points: u32,
+    /// How many points along each line skips to: 1 for a polygon, 2 for a pentagram from 5 points
+    pub step: u32,
+    /// Only cut the lines inside this region, if there is one
+    pub clip: Option<Region<'a>>,
+    /// Depth of the lines
+    pub z_cut: f64,
+    /// Height to rapid at between the separate parts of a star, clear of the work
+    pub z_safe: f64,
+    pub feed: f64,
+    pub units: Units,
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The closed outlines of an {n/k} star, each as its corners in the order they're joined, from `points` round a
+/// circle of `radius` about `center`. Where `points` and `step` share a factor the star falls apart into that many
+/// separate polygons, like the two triangles of a {6/2} hexagram. There must be at least 3 `points`, and a `step` that
+/// isn't a whole number of turns.
+pub fn star_polygon_points(
+    center: (f64, f64),
+    radius: f64,
+    points: u32,
+    step: u32,
+) -> Vec<Vec<(f64, f64)>> {
+    assert!(
+        points >= 3,
+        "Refusing to draw a star with fewer than 3 points"
+    );
+    assert!(
+        !step.is_multiple_of(points),
+        "Refusing to draw a star whose lines go all the way round to where they started"
+    );
+    let parts = gcd(points, step);
+    let corner = |i: u32| {
+        let angle = PI / 2.0 + 2.0 * PI * (i % points) as f64 / points as f64;
+        (
+            center.0 + radius * angle.cos(),
+            center.1 + radius * angle.sin(),
+        )
+    };
+    (0..parts)
+        .map(|part| {
+            (0..points / parts)
+                .map(|i| corner(part + i * step))
+                .collect()
+        })
+        .collect()
+}
+
+/// Engrave a star polygon, going round each of its parts in turn and back to where that part started. Clipped, each
+/// piece left inside the region is cut on its own, raising between them.
+pub fn star_polygon(file: &mut dyn Write, star: &StarPolygon) -> Result<()> {
+    let u = star.units;
+    for part in star_polygon_points(star.center, star.radius, star.points, star.step) {
+        let pieces = match &star.clip {
+            Some(region) => clip_path(&part, true, region),
+            None => vec![part.iter().chain(&part[..1]).copied().collect()],
+        };
+        for piece in pieces {
+            g0(file, z(star.z_safe).units(u))?;
+            g0(file, xy(piece[0].0, piece[0].1).units(u))?;
+            g1(file, zf(star.z_cut, star.feed).units(u))?;
+            for &(x, y) in &piece[1..] {
+                g1(file, xyf(x, y, star.feed).units(u))?;
+            }
+        }
+    }
+    g0(file, z(star.z_safe).units(u))
+}
+
+/// Which way the circle that carries the pen turns, and so the shape of a rosette's lobes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RosetteKind {
+    /// Turning the same way as the pattern, like a circle rolling round the outside of another: rounded lobes
+    /// bulging out
+    Epicycloid,
+    /// Turning against the pattern, like a circle rolling round the inside of another: lobes pointing out, with their
+    /// sides curving in
+    Hypocycloid,
+}
+
+/// How to engrave a rose engine rosette, all in the job's units. The pen goes round a circle of `amplitude`, whose
+/// center goes round the pattern's `center` at `radius`, so its path is an epicycloid or hypocycloid.
+#[derive(Debug, Clone, Copy)]
+pub struct Rosette<'a> {
+    pub center: (f64, f64),
+    /// Radius of the path of the center of the pen's circle
+    pub radius: f64,
+    /// Radius of the pen's circle, which sets how deep the lobes are. Making it `radius / (lobes + 1)` for an
+    /// epicycloid, or `radius / (lobes - 1)` for a hypocycloid, draws the true rolling-circle curve, with cusps.
+    pub amplitude: f64,
+    /// Number of lobes. The pen's circle turns `lobes` times more (an epicycloid) or less (a hypocycloid) than once for
+    /// each turn round the pattern, so no lobes, a 1:1 ratio, draws a plain circle.
+    pub lobes: u32,
+    pub kind: RosetteKind,
+    /// Angle (degrees) the pen starts round its circle from +X, which turns the lobes round the pattern
+    pub phase: f64,
+    /// Number of straight moves all the way round
+    pub resolution: u32,
+    /// Only engrave the parts of the rosette inside this region, like the face of a dial
+    pub clip: Option<Region<'a>>,
+    /// Depth of the line
+    pub z_cut: f64,
+    /// Height to rapid at before and after the cut, clear of the work
+    pub z_safe: f64,
+    pub feed: f64,
+    pub units: Units,
+}
+
+/// Points evenly spaced round a rosette, starting from +X of its `center`, without repeating the first at the end
+pub fn rosette_points(rosette: &Rosette) -> Vec<(f64, f64)> {
+    assert!(
+        rosette.resolution >= 3,
+        "Refusing to draw a rosette with fewer than 3 moves"
+    );
+    let ratio = match rosette.kind {
+        RosetteKind::Epicycloid => 1.0 + rosette.lobes as f64,
+        RosetteKind::Hypocycloid => 1.0 - rosette.lobes as f64,
+    };
+    let phase = rosette.phase.to_radians();
+    (0..rosette.resolution)
+        .map(|i| {
+            let t = 2.0 * PI * i as f64 / rosette.resolution as f64;
+            let pen = ratio * t + phase;
+            (
+                rosette.center.0 + rosette.radius * t.cos() + rosette.amplitude * pen.cos(),
+                rosette.center.1 + rosette.radius * t.sin() + rosette.amplitude * pen.sin(),
+            )
+        })
+        .collect()
+}
+
+/// Engrave a rosette, once round and back to where it started. Clipped, each piece left inside the region is cut on
+/// its own, raising between them.
+pub fn rosette(file: &mut dyn Write, rosette: &Rosette) -> Result<()> {
+    let u = rosette.units;
+    let points = rosette_points(rosette);
+    let pieces = match &rosette.clip {
+        Some(region) => clip_path(&points, true, region),
+        None => vec![points.iter().chain(&points[..1]).copied().collect()],
+    };
+    for piece in pieces {
+        g0(file, z(rosette.z_safe).units(u))?;
+        g0(file, xy(piece[0].0, piece[0].1).units(u))?;
+        g1(file, zf(rosette.z_cut, rosette.feed).units(u))?;
+        for &(x, y) in &piece[1..] {
+            g1(file, xyf(x, y, rosette.feed).units(u))?;
+        }
+    }
+    g0(file, z(rosette.z_safe).units(u))
+}
+
+/// How to engrave rings about a common center, all in the job's units
+#[derive(Debug, Clone, Copy)]
+pub struct ConcentricCircles {
+    pub center: (f64, f64),
+    /// Radius of the innermost ring
+    pub r_min: f64,
+    /// Radius of the outermost ring
+    pub r_max: f64,
+    /// Greatest distance between rings. They're spread evenly from `r_min` to `r_max`, so may be a little closer.
+    pub step: f64,
+    /// Depth of the rings
+    pub z_cut: f64,
+    /// Height to rapid at between rings, clear of the work
+    pub z_safe: f64,
+    pub feed: f64,
+    pub units: Units,
+}
+
+/// Radii of the rings, from `r_min` out to `r_max`, evenly spaced no more than `step` apart. A ring of no radius
+/// isn't a ring, so one at the center is left out. The `step` must be more than 0.
+pub fn ring_radii(r_min: f64, r_max: f64, step: f64) -> Vec<f64> {
+    assert!(step > 0.0);
+    let gaps = ((r_max - r_min) / step).ceil().max(0.0) as usize;
+    (0..=gaps)
+        .map(|i| {
+            if gaps == 0 {
+                r_min
+            } else {
+                r_min + (r_max - r_min) * i as f64 / gaps as f64
+            }
+        })
+        .filter(|&r| r > 0.0)
+        .collect()
+}
+
+/// Engrave each ring counter-clockwise from its +X side, raising between them
+pub fn concentric_circles(file: &mut dyn Write, rings: &ConcentricCircles) -> Result<()> {
+    let u = rings.units;
+    let (cx, cy) = rings.center;
+    for r in ring_radii(rings.r_min, rings.r_max, rings.step) {
+        g0(file, z(rings.z_safe).units(u))?;
+        g0(file, xy(cx + r, cy).units(u))?;
+        g1(file, zf(rings.z_cut, rings.feed).units(u))?;
+        full_circle(file, rings.center, r, (cx + r, cy), rings.feed, u)?;
+    }
+    g0(file, z(rings.z_safe).units(u))
+}
+
+/// How close to parallel two segments can be, as the sine of the angle between them, and how far past its ends a
+/// crossing can be and still count as on a segment, as a fraction of its length
+const INTERSECTION_TOLERANCE: f64 = 1e-9;
+
+/// Where the segments `a` and `b` cross, each given as its two ends, or `None` if they don't.
+/// Segments that only touch, at an end of either, count as crossing there. Parallel segments never cross, even where
+/// they lie along the same line and overlap, as they have no single point in common to give. Nor does a segment of no
+/// length cross anything.
+pub fn segment_intersection(
+    a: ((f64, f64), (f64, f64)),
+    b: ((f64, f64), (f64, f64)),
+) -> Option<(f64, f64)> {
+    let on = |f: f64| (-INTERSECTION_TOLERANCE..=1.0 + INTERSECTION_TOLERANCE).contains(&f);
+    match crossing(a, b) {
+        Some((t, u)) if on(t) && on(u) => line_intersection(a, b),
+        _ => None,
+    }
+}
+
+/// How far along the lines through `a` and `b` they cross, from 0 at the first end of each to 1 at its second, or
+/// `None` if they're parallel (or either has no length)
+fn crossing(a: ((f64, f64), (f64, f64)), b: ((f64, f64), (f64, f64))) -> Option<(f64, f64)> {
+    let cross = |u: (f64, f64), v: (f64, f64)| u.0 * v.1 - u.1 * v.0;
+    let r = (a.1 .0 - a.0 .0, a.1 .1 - a.0 .1);
+    let s = (b.1 .0 - b.0 .0, b.1 .1 - b.0 .1);
+    let denom = cross(r, s);
+    if denom.abs() <= INTERSECTION_TOLERANCE * r.0.hypot(r.1) * s.0.hypot(s.1) {
+        return None;
+    }
+    let qp = (b.0 .0 - a.0 .0, b.0 .1 - a.0 .1);
+    Some((cross(qp, s) / denom, cross(qp, r) / denom))
+}
+
+/// Distance from `p` to the nearest point of the segment from `a` to `b`
+fn distance_to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let d = (b.0 - a.0, b.1 - a.1);
+    let len2 = d.0 * d.0 + d.1 * d.1;
+    let t = if len2 > 0.0 {
+        (((p.0 - a.0) * d.0 + (p.1 - a.1) * d.1) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (p.0 - a.0 - d.0 * t).hypot(p.1 - a.1 - d.1 * t)
+}
+
+/// The points of the polyline through `points` that it needs to stay within `tolerance` of where it was, by
+/// Douglas-Peucker: the ends stay, and so does the point furthest from the line between them, if that's more than
+/// `tolerance` away, and then the same again on either side of it. A nearly straight run of short moves comes down to
+/// one long one. The `tolerance` is in the same units as the points, and can't be negative.
+pub fn simplify(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    assert!(tolerance >= 0.0);
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    let mut spans = vec![(0, points.len() - 1)];
+    while let Some((from, to)) = spans.pop() {
+        let furthest = (from + 1..to)
+            .map(|i| (i, distance_to_segment(points[i], points[from], points[to])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, distance)) = furthest {
+            if distance > tolerance {
+                keep[i] = true;
+                spans.push((from, i));
+                spans.push((i, to));
+            }
+        }
+    }
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(&p, keep)| keep.then_some(p))
+        .collect()
+}
+
+/// A move along a polyline after fitting arcs to it, from where the last one ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fitted {
+    /// A straight move to this point
+    Line((f64, f64)),
+    /// An arc round `center` to `to`
+    Arc {
+        to: (f64, f64),
+        center: (f64, f64),
+        radius: f64,
+        ccw: bool,
+    },
+}
+
+/// Center of the circle through `a`, `b`, and `c`, or `None` if they're in a line
+fn circle_through(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> Option<(f64, f64)> {
+    let (b, c) = ((b.0 - a.0, b.1 - a.1), (c.0 - a.0, c.1 - a.1));
+    let d = 2.0 * (b.0 * c.1 - b.1 * c.0);
+    let (b2, c2) = (b.0 * b.0 + b.1 * b.1, c.0 * c.0 + c.1 * c.1);
+    if d.abs() <= INTERSECTION_TOLERANCE * b2.sqrt() * c2.sqrt() {
+        return None;
+    }
+    Some((
+        a.0 + (c.1 * b2 - b.1 * c2) / d,
+        a.1 + (b.0 * c2 - c.0 * b2) / d,
+    ))
+}
+
+/// The arc through `points`, as its center, radius, and direction, if every one of them is within `tolerance` of it
+/// and so is the line between each pair of them. The arc has to go the one way round, and no more than a quarter turn,
+/// as radius form is poorly conditioned near a half turn.
+fn fit_arc(points: &[(f64, f64)], tolerance: f64) -> Option<((f64, f64), f64, bool)> {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let center = circle_through(first, points[points.len() / 2], last)?;
+    let radius = (first.0 - center.0).hypot(first.1 - center.1);
+    let angle = |p: (f64, f64)| (p.1 - center.1).atan2(p.0 - center.0);
+    let turn = |p: (f64, f64), q: (f64, f64)| (angle(q) - angle(p) + PI).rem_euclid(2.0 * PI) - PI;
+    let ccw = turn(first, points[1]) > 0.0;
+    let mut sweep = 0.0;
+    for pair in points.windows(2) {
+        let step = turn(pair[0], pair[1]);
+        // Each line bulges in from the arc by its sagitta
+        let sagitta = radius * (1.0 - (step / 2.0).cos());
+        if (step > 0.0) != ccw || sagitta > tolerance {
+            return None;
+        }
+        sweep += step.abs();
+    }
+    let on_arc = points
+        .iter()
+        .all(|p| ((p.0 - center.0).hypot(p.1 - center.1) - radius).abs() <= tolerance);
+    (on_arc && sweep <= PI / 2.0 + 1e-9).then_some((center, radius, ccw))
+}
+
+/// The polyline through `points` as arcs where runs of three or more of them lie on one, within `tolerance`, and
+/// straight moves between the rest. Each arc is as long as it can be, up to a quarter turn. The moves start from the
+/// first point, so each one only gives where it goes to.
+pub fn fit_arcs(points: &[(f64, f64)], tolerance: f64) -> Vec<Fitted> {
+    let mut moves = vec![];
+    let mut i = 0;
+    while i + 1 < points.len() {
+        let mut arc = None;
+        for j in i + 2..points.len() {
+            match fit_arc(&points[i..=j], tolerance) {
+                Some(fit) => arc = Some((j, fit)),
+                None => break,
+            }
+        }
+        match arc {
+            Some((j, (center, radius, ccw))) => {
+                moves.push(Fitted::Arc {
+                    to: points[j],
+                    center,
+                    radius,
+                    ccw,
+                });
+                i = j;
+            }
+            None => {
+                moves.push(Fitted::Line(points[i + 1]));
+                i += 1;
+            }
+        }
+    }
+    moves
+}
+
+/// Feed along `moves` (from `fit_arcs`), starting from `from`, with G1 for the lines and G2 or G3 for the arcs
+pub fn fitted_path(
+    file: &mut dyn Write,
+    from: (f64, f64),
+    moves: &[Fitted],
+    feed: f64,
+    units: Units,
+) -> Result<()> {
+    let mut at = from;
+    for m in moves {
+        match *m {
+            Fitted::Line(to) => {
+                g1(file, xyf(to.0, to.1, feed).units(units))?;
+                at = to;
+            }
+            Fitted::Arc {
+                to, radius, ccw, ..
+            } => {
+                let p = xyrf(to.0, to.1, radius, feed).units(units);
+                if ccw {
+                    g3r(file, at, p)?;
+                } else {
+                    g2r(file, at, p)?;
+                }
+                at = to;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// An area to fill
+#[derive(Debug, Clone, Copy)]
+pub enum Region<'a> {
+    Circle {
+        center: (f64, f64),
+        radius: f64,
+    },
+    /// Corners of a closed outline, which needn't be convex
+    Polygon(&'a [(f64, f64)]),
+}
+
+/// How to fill a region with parallel lines, all in the job's units
+#[derive(Debug, Clone, Copy)]
+pub struct Hatch<'a> {
+    pub region: Region<'a>,
+    /// Direction of the lines, in degrees counter-clockwise from +X
+    pub angle: f64,
+    /// Distance between the lines
+    pub spacing: f64,
+    /// Depth of the lines
+    pub z_cut: f64,
+    /// Height to rapid at between lines, clear of the work
+    pub z_safe: f64,
+    pub feed: f64,
+    pub units: Units,
+}
+
+/// Lines at `angle` degrees, `spacing` apart, clipped to `region`, as the ends of each piece inside it.
+/// The first line is half of `spacing` in from the edge, so across a region W wide there are W / `spacing` lines.
+/// Where a line crosses a concave outline more than twice, it's cut into a piece for each stretch inside. Every other
+/// line runs backwards, so the lines are cut back and forth. The `spacing` must be more than 0.
+pub fn hatch_lines(region: &Region, angle: f64, spacing: f64) -> Vec<((f64, f64), (f64, f64))> {
+    assert!(spacing > 0.0);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    // Turn everything so the lines run along X, clip them, then turn them back
+    let to_lines = |p: (f64, f64)| (p.0 * cos + p.1 * sin, p.1 * cos - p.0 * sin);
+    let from_lines = |p: (f64, f64)| (p.0 * cos - p.1 * sin, p.0 * sin + p.1 * cos);
+
+    let ((u_min, v_min), (u_max, v_max)) = match region {
+        Region::Circle { center, radius } => {
+            let c = to_lines(*center);
+            ((c.0 - radius, c.1 - radius), (c.0 + radius, c.1 + radius))
+        }
+        Region::Polygon(corners) => corners.iter().map(|&p| to_lines(p)).fold(
+            ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN)),
+            |(lo, hi), p| {
+                (
+                    (lo.0.min(p.0), lo.1.min(p.1)),
+                    (hi.0.max(p.0), hi.1.max(p.1)),
+                )
+            },
+        ),
+    };
+
+    let mut lines = vec![];
+    let mut v = v_min + spacing / 2.0;
+    let mut backwards = false;
+    while v < v_max {
+        // Where this line goes in and out of the region, along it
+        let mut crossings = match region {
+            Region::Circle { center, radius } => {
+                let c = to_lines(*center);
+                let half = (radius * radius - (v - c.1) * (v - c.1)).max(0.0).sqrt();
+                vec![c.0 - half, c.0 + half]
+            }
+            Region::Polygon(corners) => {
+                let corners: Vec<(f64, f64)> = corners.iter().map(|&p| to_lines(p)).collect();
+                // The whole line across the region, reaching a little past it on both sides
+                let line = ((u_min - 1.0, v), (u_max + 1.0, v));
+                (0..corners.len())
+                    .filter_map(|i| {
+                        let (a, b) = (corners[i], corners[(i + 1) % corners.len()]);
+                        // Count each side from its lower end, so a line through a corner crosses once
+                        if (a.1 <= v) != (b.1 <= v) {
+                            segment_intersection(line, (a, b)).map(|p| p.0)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect()
+            }
+        };
+        crossings.sort_by(f64::total_cmp);
+        if backwards {
+            crossings.reverse();
+        }
+        backwards = !backwards;
+        let mut pieces: Vec<_> = crossings
+            .chunks_exact(2)
+            .map(|pair| (from_lines((pair[0], v)), from_lines((pair[1], v))))
+            .collect();
+        lines.append(&mut pieces);
+        v += spacing;
+    }
+    lines
+}
+
+/// Fill a region with parallel lines, raising between each piece
+pub fn hatch_fill(file: &mut dyn Write, hatch: &Hatch) -> Result<()> {
+    let u = hatch.units;
+    for (from, to) in hatch_lines(&hatch.region, hatch.angle, hatch.spacing) {
+        g0(file, z(hatch.z_safe).units(u))?;
+        g0(file, xy(from.0, from.1).units(u))?;
+        g1(file, zf(hatch.z_cut, hatch.feed).units(u))?;
+        g1(file, xyf(to.0, to.1, hatch.feed).units(u))?;
+    }
+    g0(file, z(hatch.z_safe).units(u))
+}
+
+/// Whether `p` is inside `region`. Points right on its edge may go either way.
+fn inside(region: &Region, p: (f64, f64)) -> bool {
+    match region {
+        Region::Circle { center, radius } => (p.0 - center.0).hypot(p.1 - center.1) < *radius,
+        Region::Polygon(corners) => {
+            // Count the sides crossed going out from `p` along +X, each counted from its lower end like hatch_lines
+            let mut inside = false;
+            for i in 0..corners.len() {
+                let (a, b) = (corners[i], corners[(i + 1) % corners.len()]);
+                if (a.1 <= p.1) != (b.1 <= p.1)
+                    && p.0 < a.0 + (b.0 - a.0) * (p.1 - a.1) / (b.1 - a.1)
+                {
+                    inside = !inside;
+                }
+            }
+            inside
+        }
+    }
+}
+
+/// How far along the segment from `p` to `q` it crosses the edge of `region`, from 0 at `p` to 1 at `q`, in order.
+/// Only crossings between its ends count.
+fn edge_crossings(region: &Region, p: (f64, f64), q: (f64, f64)) -> Vec<f64> {
+    let d = (q.0 - p.0, q.1 - p.1);
+    let mut along = match region {
+        Region::Circle { center, radius } => {
+            // Where |p + t d - center| = radius
+            let f = (p.0 - center.0, p.1 - center.1);
+            let a = d.0 * d.0 + d.1 * d.1;
+            let b = 2.0 * (f.0 * d.0 + f.1 * d.1);
+            let c = f.0 * f.0 + f.1 * f.1 - radius * radius;
+            let discriminant = b * b - 4.0 * a * c;
+            if a == 0.0 || discriminant < 0.0 {
+                vec![]
+            } else {
+                let root = discriminant.sqrt();
+                vec![(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]
+            }
+        }
+        Region::Polygon(corners) => (0..corners.len())
+            .filter_map(|i| crossing((p, q), (corners[i], corners[(i + 1) % corners.len()])))
+            .filter(|&(_, u)| (0.0..=1.0).contains(&u))
+            .map(|(t, _)| t)
+            .collect(),
+    };
+    along.retain(|&t| t > 0.0 && t < 1.0);
+    along.sort_by(f64::total_cmp);
+    along.dedup_by(|a, b| (*a - *b).abs() < INTERSECTION_TOLERANCE);
+    along
+}
+
+/// The pieces of the path through `points` that are inside `region`, each as the points along it in order. A
+/// `closed` path carries on from its last point back to its first, and if it's inside there, the piece through its
+/// first point is kept whole. All the pieces are open: a closed path that's inside all the way round comes back as one
+/// piece, ending where it started.
+pub fn clip_path(points: &[(f64, f64)], closed: bool, region: &Region) -> Vec<Vec<(f64, f64)>> {
+    let sides = if closed {
+        points.len()
+    } else {
+        points.len().saturating_sub(1)
+    };
+    let mut pieces = vec![];
+    let mut piece: Option<Vec<(f64, f64)>> = None;
+    let mut starts_inside = false;
+    for i in 0..sides {
+        let (p, q) = (points[i], points[(i + 1) % points.len()]);
+        let at = |t: f64| (p.0 + (q.0 - p.0) * t, p.1 + (q.1 - p.1) * t);
+        let mut stops = vec![0.0];
+        stops.append(&mut edge_crossings(region, p, q));
+        stops.push(1.0);
+        for (j, span) in stops.windows(2).enumerate() {
+            // Each stretch between crossings is all inside, or all outside
+            if inside(region, at((span[0] + span[1]) / 2.0)) {
+                starts_inside |= i == 0 && j == 0;
+                piece
+                    .get_or_insert_with(|| vec![at(span[0])])
+                    .push(at(span[1]));
+            } else if let Some(done) = piece.take() {
+                pieces.push(done);
+            }
+        }
+    }
+    if let Some(mut last) = piece {
+        if closed && starts_inside && !pieces.is_empty() {
+            // The last piece runs on into the first
+            let first: Vec<(f64, f64)> = pieces.remove(0);
+            last.extend(&first[1..]);
+        }
+        pieces.push(last);
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).hypot(a.1 - b.1) < 1e-9
+    }
+
+    #[test]
+    fn segments_crossing_in_an_x() {
+        let p = segment_intersection(((0.0, 0.0), (2.0, 2.0)), ((0.0, 2.0), (2.0, 0.0)));
+        assert!(close(p.unwrap(), (1.0, 1.0)));
+    }
+
+    #[test]
+    fn segments_touching_at_their_ends() {
+        let p = segment_intersection(((0.0, 0.0), (1.0, 1.0)), ((1.0, 1.0), (2.0, 0.0)));
+        assert!(close(p.unwrap(), (1.0, 1.0)));
+        // One end of a segment on the middle of the other
+        let p = segment_intersection(((0.0, 0.0), (2.0, 0.0)), ((1.0, 0.0), (1.0, 3.0)));
+        assert!(close(p.unwrap(), (1.0, 0.0)));
+    }
+
+    #[test]
+    fn segments_that_miss() {
+        // They would cross if the first went on past its end
+        let p = segment_intersection(((0.0, 0.0), (0.9, 0.9)), ((0.0, 2.0), (2.0, 0.0)));
+        assert!(p.is_none());
+    }
+
+    #[test]
+    fn parallel_segments() {
+        let p = segment_intersection(((0.0, 0.0), (2.0, 1.0)), ((0.0, 1.0), (2.0, 2.0)));
+        assert!(p.is_none());
+    }
+
+    #[test]
+    fn collinear_overlapping_segments() {
+        let p = segment_intersection(((0.0, 0.0), (2.0, 0.0)), ((1.0, 0.0), (3.0, 0.0)));
+        assert!(p.is_none());
+    }
+
+    fn same_path(a: &[(f64, f64)], b: &[(f64, f64)]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(&p, &q)| close(p, q))
+    }
+
+    #[test]
+    fn offset_square_outwards() {
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        // Counter-clockwise, so right is outwards
+        let offset = offset_polyline(&square, -1.0, Join::Miter, true);
+        assert!(same_path(
+            &offset,
+            &[(-1.0, -1.0), (11.0, -1.0), (11.0, 11.0), (-1.0, 11.0)]
+        ));
+        // Rounded corners stay the offset away from the square's corners
+        let offset = offset_polyline(&square, -1.0, Join::Round, true);
+        assert_eq!(offset.len(), 4 * 10);
+        for p in offset {
+            let nearest = (p.0.clamp(0.0, 10.0), p.1.clamp(0.0, 10.0));
+            assert!(((p.0 - nearest.0).hypot(p.1 - nearest.1) - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn offset_square_inwards() {
+        // Clockwise this time, so right is inwards
+        let square = [(0.0, 0.0), (0.0, 10.0), (10.0, 10.0), (10.0, 0.0)];
+        let offset = offset_polyline(&square, -1.0, Join::Miter, true);
+        assert!(same_path(
+            &offset,
+            &[(1.0, 1.0), (1.0, 9.0), (9.0, 9.0), (9.0, 1.0)]
+        ));
+        assert!(offset_polyline(&square, -5.0, Join::Miter, true).is_empty());
+    }
+
+    #[test]
+    fn offset_triangle() {
+        // A 3-4-5 triangle, whose incircle has radius 1 about (1, 1), so offsets scale it about there
+        let triangle = [(0.0, 0.0), (4.0, 0.0), (0.0, 3.0)];
+        let inwards = offset_polyline(&triangle, 0.5, Join::Miter, true);
+        assert!(same_path(&inwards, &[(0.5, 0.5), (2.5, 0.5), (0.5, 2.0)]));
+        let outwards = offset_polyline(&triangle, -1.0, Join::Miter, true);
+        assert!(same_path(
+            &outwards,
+            &[(-1.0, -1.0), (7.0, -1.0), (-1.0, 5.0)]
+        ));
+    }
+
+    #[test]
+    fn offset_clips_a_loop() {
+        // A corner with a short chamfer across it, which an offset of 2 on the inside swallows
+        let path = [(10.0, 0.0), (10.0, 9.5), (9.5, 10.0), (0.0, 10.0)];
+        let offset = offset_polyline(&path, 2.0, Join::Miter, false);
+        assert!(same_path(&offset, &[(8.0, 0.0), (8.0, 8.0), (0.0, 8.0)]));
+    }
+
+    #[test]
+    fn offset_l_shape() {
+        // The inside corner of the L sticks out from the offset, and is mitered
+        let l = [
+            (0.0, 0.0),
+            (4.0, 0.0),
+            (4.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 4.0),
+            (0.0, 4.0),
+        ];
+        let offset = offset_polyline(&l, 0.25, Join::Miter, true);
+        assert!(same_path(
+            &offset,
+            &[
+                (0.25, 0.25),
+                (3.75, 0.25),
+                (3.75, 0.75),
+                (0.75, 0.75),
+                (0.75, 3.75),
+                (0.25, 3.75),
+            ]
+        ));
+    }
+
+    fn rosette_with(lobes: u32, kind: RosetteKind, amplitude: f64, phase: f64) -> Rosette<'static> {
+        Rosette {
+            center: (1.0, 2.0),
+            radius: 10.0,
+            amplitude,
+            lobes,
+            kind,
+            phase,
+            resolution: 360,
+            clip: None,
+            z_cut: -0.1,
+            z_safe: 1.0,
+            feed: 100.0,
+            units: Units::Metric,
+        }
+    }
+
+    #[test]
+    fn rosette_of_no_lobes_is_a_circle() {
+        // The pen's circle turns with the pattern, so it stays at the same point of it, 3 along the phase of 90 degrees
+        let points = rosette_points(&rosette_with(0, RosetteKind::Epicycloid, 3.0, 90.0));
+        for p in points {
+            assert!(((p.0 - 1.0).hypot(p.1 - 2.0) - 10.0f64.hypot(3.0)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn rosette_lobes() {
+        // Count the points furthest from the center, one at the tip of each lobe
+        for kind in [RosetteKind::Epicycloid, RosetteKind::Hypocycloid] {
+            let points = rosette_points(&rosette_with(5, kind, 2.0, 0.0));
+            let r: Vec<f64> = points
+                .iter()
+                .map(|p| (p.0 - 1.0).hypot(p.1 - 2.0))
+                .collect();
+            assert!(r.iter().all(|&r| r > 8.0 - 1e-9 && r < 12.0 + 1e-9));
+            let tips = r.iter().filter(|&&r| (r - 12.0).abs() < 1e-9).count();
+            assert_eq!(tips, 5);
+        }
+    }
+
+    #[test]
+    fn rosette_clipped_to_a_dial() {
+        // The lobes reach out to 12, so a dial of radius 11 cuts off each tip, leaving one piece between each two
+        let dial = Region::Circle {
+            center: (1.0, 2.0),
+            radius: 11.0,
+        };
+        let mut clipped = rosette_with(5, RosetteKind::Epicycloid, 2.0, 0.0);
+        clipped.clip = Some(dial);
+        let mut file = vec![];
+        rosette(&mut file, &clipped).unwrap();
+        let text = String::from_utf8(file).unwrap();
+        assert_eq!(text.matches("G1 Z-0.1000").count(), 5);
+
+        let pieces = clip_path(&rosette_points(&clipped), true, &dial);
+        assert_eq!(pieces.len(), 5);
+        for p in pieces.iter().flatten() {
+            assert!((p.0 - 1.0).hypot(p.1 - 2.0) < 11.0 + 1e-9);
+        }
+
+        // Inside the dial all the way round, it's cut in one go, just as it is without clipping
+        clipped.clip = Some(Region::Circle {
+            center: (1.0, 2.0),
+            radius: 13.0,
+        });
+        let mut inside = vec![];
+        rosette(&mut inside, &clipped).unwrap();
+        let mut whole = vec![];
+        rosette(
+            &mut whole,
+            &rosette_with(5, RosetteKind::Epicycloid, 2.0, 0.0),
+        )
+        .unwrap();
+        assert_eq!(inside, whole);
+    }
+
+    #[test]
+    fn hatch_lines_across_a_square() {
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let lines = hatch_lines(&Region::Polygon(&square), 0.0, 1.0);
+        assert_eq!(lines.len(), 10);
+        for (i, &(from, to)) in lines.iter().enumerate() {
+            let y = 0.5 + i as f64;
+            let (start, end) = if i % 2 == 0 { (0.0, 10.0) } else { (10.0, 0.0) };
+            assert!(close(from, (start, y)) && close(to, (end, y)));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn trochoid_with_no_step() {
+        trochoid_centers((0.0, 0.0), (10.0, 0.0), 0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn spiral_with_no_step_over() {
+        spiral_points(10.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rings_with_no_step() {
+        ring_radii(1.0, 10.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn hatch_with_no_spacing() {
+        let circle = Region::Circle {
+            center: (0.0, 0.0),
+            radius: 10.0,
+        };
+        hatch_lines(&circle, 0.0, 0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn star_with_too_few_points() {
+        star_polygon_points((0.0, 0.0), 10.0, 2, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn star_that_steps_a_whole_turn() {
+        star_polygon_points((0.0, 0.0), 10.0, 5, 5);
+    }
+
+    #[test]
+    fn contour_rings_of_a_square() {
+        let square = [(0.0, 0.0), (20.0, 0.0), (20.0, 20.0), (0.0, 20.0)];
+        // 8 to clear from the tool's radius in from each wall to the middle, 2 at a time
+        let rings = contour_rings(&square, 4.0, 2.0);
+        assert_eq!(rings.len(), 4);
+        for (ring, inset) in rings.iter().zip([8.0, 6.0, 4.0, 2.0]) {
+            let (lo, hi) = (inset, 20.0 - inset);
+            assert!(same_path(ring, &[(lo, lo), (hi, lo), (hi, hi), (lo, hi)]));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn contour_rings_with_no_step_over() {
+        let square = [(0.0, 0.0), (20.0, 0.0), (20.0, 20.0), (0.0, 20.0)];
+        contour_rings(&square, 4.0, 0.0);
+    }
+
+    #[test]
+    fn spiral_grows_out_to_its_radius() {
+        for (radius, step_over) in [(10.0, 1.0), (10.0, 3.0), (0.5, 2.0)] {
+            let points = spiral_points(radius, step_over);
+            assert!(close(points[0], (0.0, 0.0)));
+            let r: Vec<f64> = points.iter().map(|p| p.0.hypot(p.1)).collect();
+            assert!(r.windows(2).all(|pair| pair[1] >= pair[0]));
+            assert!(r.iter().all(|&r| r <= radius + 1e-9));
+            assert!((r[r.len() - 1] - radius).abs() < 1e-9);
+            // On the spiral, a step over further out each lap
+            let mut turned = 0.0;
+            for (pair, &r) in points.windows(2).zip(&r[1..]) {
+                let (from, to) = (pair[0].1.atan2(pair[0].0), pair[1].1.atan2(pair[1].0));
+                turned += (to - from).rem_euclid(2.0 * PI);
+                assert!((r - (step_over * turned / (2.0 * PI)).min(radius)).abs() < 1e-9);
+            }
+        }
+    }
+
+    /// The distance along the path at which each move of a `tabbed_contour` changes Z, and the Z it goes to
+    fn z_changes(moves: &[Move], start: (f64, f64)) -> Vec<(f64, f64)> {
+        let (mut at, mut along) = (start, 0.0);
+        let mut changes = vec![];
+        for m in moves {
+            let Move::Feed(p) = m else {
+                panic!("Expected only feeds, not {:?}", m)
+            };
+            match (p.x, p.y, p.z) {
+                (Some(x), Some(y), None) => {
+                    along += (x - at.0).hypot(y - at.1);
+                    at = (x, y);
+                }
+                (None, None, Some(z)) => changes.push((along, z)),
+                _ => panic!("Expected moves in X-Y or Z, not {:?}", p),
+            }
+        }
+        assert!(close(at, start));
+        changes
+    }
+
+    #[test]
+    fn tabs_lift_over_their_spans() {
+        // 40 round, with a tab in the middle of the first side, and another across the far corner
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let tabs = Tabs {
+            positions: &[0.125, 0.5],
+            width: 2.0,
+            height: 1.5,
+        };
+        let moves = tabbed_contour(&square, &tabs, -2.0, 100.0, Units::Metric);
+        let changes = z_changes(&moves, square[0]);
+        let expected = [
+            (0.0, -2.0),
+            (4.0, -0.5),
+            (6.0, -2.0),
+            (19.0, -0.5),
+            (21.0, -2.0),
+        ];
+        assert_eq!(changes.len(), expected.len());
+        for (&(along, z), &(want_along, want_z)) in changes.iter().zip(&expected) {
+            assert!(
+                (along - want_along).abs() < 1e-9 && z == want_z,
+                "{:?}",
+                changes
+            );
+        }
+        // The second tab goes round the corner, rather than cutting across it
+        assert!(moves
+            .iter()
+            .any(|m| matches!(m, Move::Feed(p) if p.x == Some(10.0) && p.y == Some(10.0))));
+    }
+
+    #[test]
+    fn tabs_across_the_start() {
+        // A tab wider than a side, centered on the first corner: the cut starts on the tab, comes down after it,
+        // and goes back up for the end of it
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let tabs = Tabs {
+            positions: &[0.0],
+            width: 24.0,
+            height: 1.0,
+        };
+        let moves = tabbed_contour(&square, &tabs, -1.0, 100.0, Units::Metric);
+        let changes = z_changes(&moves, square[0]);
+        assert_eq!(changes, [(0.0, 0.0), (12.0, -1.0), (28.0, 0.0)]);
+    }
+
+    #[test]
+    fn log_scale_marks() {
+        let ticks = log_scale_ticks(1);
+        // Tenths from 1 to 2, fifths from 2 to 5, halves from 5 to 10, and the 10 at the end
+        assert_eq!(ticks.len(), 10 + 15 + 10 + 1);
+        let majors: Vec<f64> = ticks
+            .iter()
+            .filter(|t| t.1 == TickSize::Major)
+            .map(|t| t.0)
+            .collect();
+        assert_eq!(majors.len(), 10);
+        for (n, &fraction) in majors.iter().enumerate() {
+            assert!((fraction - ((n + 1) as f64).log10()).abs() < 1e-12);
+        }
+        // The 2 sits log10(2) of the way along, and in the second of two decades it's half that past the middle
+        assert!((majors[1] - 2f64.log10()).abs() < 1e-12);
+        let ticks = log_scale_ticks(2);
+        assert!(ticks.iter().any(|&(fraction, size)| size == TickSize::Major
+            && (fraction - (1.0 + 2f64.log10()) / 2.0).abs() < 1e-12));
+        assert!(ticks.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(ticks[0], (0.0, TickSize::Major));
+        assert_eq!(ticks[ticks.len() - 1], (1.0, TickSize::Major));
+    }
+
+    #[test]
+    fn pentagram_crossings() {
+        // Each of a pentagram's lines crosses the two it isn't joined to, so there are five crossings, at the corners
+        // of the pentagon in the middle. A pentagon's sides only meet where they're joined.
+        for (step, crossings) in [(2, 5), (1, 0)] {
+            let corners = &star_polygon_points((0.0, 0.0), 10.0, 5, step)[0];
+            let lines: Vec<_> = (0..5).map(|i| (corners[i], corners[(i + 1) % 5])).collect();
+            let mut found = vec![];
+            for i in 0..5 {
+                for j in i + 2..5 {
+                    if (i, j) == (0, 4) {
+                        continue;
+                    }
+                    found.extend(segment_intersection(lines[i], lines[j]));
+                }
+            }
+            assert_eq!(found.len(), crossings);
+            let inner = 10.0 * (2.0 * PI / 5.0).cos() / (PI / 5.0).cos();
+            for p in found {
+                assert!((p.0.hypot(p.1) - inner).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn star_clipped_to_a_circle() {
+        // Each line of a pentagram comes within 10 cos 72 = 3.09 of the middle, so a circle of 5 leaves a chord of
+        // each one, and cuts off all the points
+        let corners = &star_polygon_points((0.0, 0.0), 10.0, 5, 2)[0];
+        let circle = Region::Circle {
+            center: (0.0, 0.0),
+            radius: 5.0,
+        };
+        let pieces = clip_path(corners, true, &circle);
+        assert_eq!(pieces.len(), 5);
+        for piece in pieces {
+            assert_eq!(piece.len(), 2);
+            for p in piece {
+                assert!((p.0.hypot(p.1) - 5.0).abs() < 1e-9);
+            }
+        }
+        // A square round the whole star leaves it in one closed piece
+        let square = [(-20.0, -20.0), (20.0, -20.0), (20.0, 20.0), (-20.0, 20.0)];
+        let pieces = clip_path(corners, true, &Region::Polygon(&square));
+        assert_eq!(pieces.len(), 1);
+        assert!(same_path(
+            &pieces[0],
+            &[&corners[..], &corners[..1]].concat()
+        ));
+    }
+
+    #[test]
+    fn clipped_path_starting_inside() {
+        // An open path out of a square and back in is two pieces, and closing it joins the last piece onto the first
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let path = [(5.0, 5.0), (15.0, 5.0), (15.0, 8.0), (5.0, 8.0)];
+        let open = clip_path(&path, false, &Region::Polygon(&square));
+        assert_eq!(open.len(), 2);
+        assert!(same_path(&open[0], &[(5.0, 5.0), (10.0, 5.0)]));
+        assert!(same_path(&open[1], &[(10.0, 8.0), (5.0, 8.0)]));
+        let closed = clip_path(&path, true, &Region::Polygon(&square));
+        assert_eq!(closed.len(), 1);
+        assert!(same_path(
+            &closed[0],
+            &[(10.0, 8.0), (5.0, 8.0), (5.0, 5.0), (10.0, 5.0)]
+        ));
+    }
+
+    #[test]
+    fn ring_counts() {
+        // (r_max - r_min) / step gaps between rings, so one more ring than that
+        let radii = ring_radii(1.0, 10.0, 1.0);
+        assert_eq!(radii.len(), 9 + 1);
+        for (i, r) in radii.iter().enumerate() {
+            assert!((r - (1.0 + i as f64)).abs() < 1e-9);
+        }
+        assert_eq!(
+            ring_radii(2.0, 10.0, 0.5).len(),
+            ((10.0 - 2.0) / 0.5) as usize + 1
+        );
+        // A step that doesn't divide the width closes the rings up to fit, and there's no ring at the center
+        let radii = ring_radii(0.0, 10.0, 3.0);
+        assert_eq!(radii.len(), 4);
+        assert!((radii[0] - 2.5).abs() < 1e-9 && (radii[3] - 10.0).abs() < 1e-9);
+        // And no width is a single ring
+        assert_eq!(ring_radii(5.0, 5.0, 1.0), [5.0]);
+    }
+
+    #[test]
+    fn hatch_line_counts() {
+        // A circle 10 across, and a square turned 45 degrees to the lines, 10 / spacing lines each
+        let circle = Region::Circle {
+            center: (3.0, 4.0),
+            radius: 5.0,
+        };
+        assert_eq!(hatch_lines(&circle, 30.0, 1.0).len(), 10);
+        assert_eq!(hatch_lines(&circle, 0.0, 0.5).len(), 20);
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let diagonal = 10.0 * 2f64.sqrt();
+        let lines = hatch_lines(&Region::Polygon(&square), 45.0, diagonal / 8.0);
+        assert_eq!(lines.len(), 8);
+    }
+
+    #[test]
+    fn simplify_a_straight_line() {
+        let line: Vec<(f64, f64)> = (0..=100)
+            .map(|i| (i as f64 * 0.1, 2.0 + i as f64 * 0.05))
+            .collect();
+        assert!(same_path(
+            &simplify(&line, 0.005),
+            &[(0.0, 2.0), (10.0, 7.0)]
+        ));
+        assert_eq!(simplify(&line, 1e-9).len(), 2);
+        // Short paths have nothing to take out
+        assert_eq!(simplify(&line[..2], 1.0), &line[..2]);
+        assert!(simplify(&[], 1.0).is_empty());
+    }
+
+    #[test]
+    fn simplify_a_semicircle() {
+        // A degree a point round a semicircle of radius 10
+        let arc: Vec<(f64, f64)> = (0..=180)
+            .map(|i| (i as f64).to_radians().sin_cos())
+            .map(|(sin, cos)| (10.0 * cos, 10.0 * sin))
+            .collect();
+        for tolerance in [0.005, 0.05, 0.5] {
+            let simple = simplify(&arc, tolerance);
+            assert!(simple.len() > 2 && simple.len() < arc.len());
+            assert_eq!(simple[0], arc[0]);
+            assert_eq!(simple[simple.len() - 1], arc[arc.len() - 1]);
+            // Every point of the semicircle stays within the tolerance of what's left
+            for &p in &arc {
+                let nearest = simple
+                    .windows(2)
+                    .map(|pair| distance_to_segment(p, pair[0], pair[1]))
+                    .fold(f64::MAX, f64::min);
+                assert!(nearest <= tolerance);
+            }
+        }
+        // Lines no more than 0.05 from the arc span a chord of up to 2 sqrt(2 r 0.05) = 2, so it takes at least 16 of
+        // them to go round the semicircle
+        let lines = simplify(&arc, 0.05).len() - 1;
+        assert!((16..=24).contains(&lines), "{}", lines);
+    }
+
+    #[test]
+    fn fit_arcs_to_a_sampled_circle() {
+        // Every degree round a circle of radius 7.5 about (2, -3), clockwise
+        let circle: Vec<(f64, f64)> = (0..=360)
+            .map(|i| (-(i as f64)).to_radians().sin_cos())
+            .map(|(sin, cos)| (2.0 + 7.5 * cos, -3.0 + 7.5 * sin))
+            .collect();
+        let moves = fit_arcs(&circle, 0.001);
+        // A quarter turn at a time
+        assert_eq!(moves.len(), 4);
+        for (i, m) in moves.iter().enumerate() {
+            let Fitted::Arc {
+                to,
+                center,
+                radius,
+                ccw,
+            } = *m
+            else {
+                panic!("Expected an arc, not {:?}", m)
+            };
+            assert!(close(to, circle[90 * (i + 1)]));
+            assert!(close(center, (2.0, -3.0)));
+            assert!((radius - 7.5).abs() < 1e-9);
+            assert!(!ccw);
+        }
+
+        let mut file = vec![];
+        fitted_path(&mut file, circle[0], &moves, 100.0, Units::Metric).unwrap();
+        let text = String::from_utf8(file).unwrap();
+        assert_eq!(text.lines().next(), Some("G2 X2. Y-10.5000 R7.5000 F100."));
+        assert_eq!(text.lines().count(), 4);
+    }
+
+    #[test]
+    fn fit_arcs_leaves_corners_and_lines() {
+        // Three points always lie on some circle, but one through a square corner bulges a long way from it
+        let corner = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+        assert_eq!(
+            fit_arcs(&corner, 0.01),
+            [Fitted::Line((10.0, 0.0)), Fitted::Line((10.0, 10.0))]
+        );
+        // A straight run, then a quarter circle of radius 5 counter-clockwise round (10, 5), then straight on up
+        let mut path: Vec<(f64, f64)> = (0..=10).map(|i| (i as f64, 0.0)).collect();
+        path.extend((1..=30).map(|i| {
+            let angle = -PI / 2.0 + PI / 2.0 * i as f64 / 30.0;
+            (10.0 + 5.0 * angle.cos(), 5.0 + 5.0 * angle.sin())
+        }));
+        path.push((15.0, 10.0));
+        // The lines of the quarter circle bulge in 0.0017 from it, and a circle through the end of the straight and the
+        // start of the arc bulges 0.005 out from the straight
+        let moves = fit_arcs(&path, 0.002);
+        assert_eq!(moves.len(), 10 + 1 + 1);
+        assert!(moves[..10].iter().all(|m| matches!(m, Fitted::Line(_))));
+        let Fitted::Arc {
+            to,
+            center,
+            radius,
+            ccw,
+        } = moves[10]
+        else {
+            panic!("Expected an arc, not {:?}", moves[10])
+        };
+        assert!(close(to, (15.0, 5.0)) && close(center, (10.0, 5.0)));
+        assert!((radius - 5.0).abs() < 1e-9 && ccw);
+        assert_eq!(moves[11], Fitted::Line((15.0, 10.0)));
+    }
+
+    #[test]
+    fn radial_ticks_with_long_majors() {
+        // A 60 mark dial with a long mark every 5, like a clock's minutes
+        let ticks = RadialTicks {
+            center: (1.0, 2.0),
+            count: 60,
+            outer_radius: 20.0,
+            inner_radius: 18.0,
+            major_every: 5,
+            major_inner_radius: 16.0,
+            z_cut: -0.1,
+            z_safe: 1.0,
+            feed: 100.0,
+            units: Units::Metric,
+        };
+        let marks = radial_tick_marks(&ticks);
+        assert_eq!(marks.len(), 60);
+        for (i, &RadialTick { outer, inner, size }) in marks.iter().enumerate() {
+            let length = (outer.0 - inner.0).hypot(outer.1 - inner.1);
+            if i % 5 == 0 {
+                assert_eq!(size, TickSize::Major);
+                assert!((length - 4.0).abs() < 1e-9);
+            } else {
+                assert_eq!(size, TickSize::Minor);
+                assert!((length - 2.0).abs() < 1e-9);
+            }
+            assert!(((outer.0 - 1.0).hypot(outer.1 - 2.0) - 20.0).abs() < 1e-9);
+        }
+        // Starting straight up, and going clockwise
+        assert!(close(marks[0].outer, (1.0, 22.0)));
+        assert!(close(marks[15].outer, (21.0, 2.0)));
+
+        let mut file = vec![];
+        radial_ticks(&mut file, &ticks).unwrap();
+        let text = String::from_utf8(file).unwrap();
+        assert_eq!(text.matches("G1 Z-0.1000").count(), 60);
+    }
+
+    #[test]
+    fn tick_sizes() {
+        let majors: Vec<u32> = (0..25)
+            .filter(|&i| radial_tick_size(i, 10) == TickSize::Major)
+            .collect();
+        assert_eq!(majors, [0, 10, 20]);
+        assert!((0..5).all(|i| radial_tick_size(i, 1) == TickSize::Major));
+    }
+
+    #[test]
+    #[should_panic]
+    fn ticks_with_no_major_spacing() {
+        radial_tick_size(3, 0);
+    }
+}
@@ -0,0 +1,230 @@
+//! Tooth gap profiles, and cutting them with a ball end mill on a 4th axis.
+//! The part's axis lies along X, with each gap turned to the top (+Z) and cut by passes of the ball along X, the way
+//! gear_gen cuts with a form cutter. Profiles are in mm; cuts are in the job's units.
+use crate::{g0, g1, gcode_comment, x, xf, y, z, Dialect, Units};
+use std::io::{Result, Write};
+
+/// A point on the side of a tooth gap.
+/// The part's axis is at the origin, with the gap centered on the +Z axis.
+#[derive(Debug, Clone, Copy)]
+pub struct ProfilePoint {
+    pub y: f64,
+    pub z: f64,
+    /// Unit normal to the profile, pointing out of the tooth into the gap, as (y, z)
+    pub normal: (f64, f64),
+}
+
+/// A piece of a tooth gap's profile, in the same frame as `ProfilePoint`
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Segment {
+    /// An arc around `center`, from angle `from` to angle `to` (in radians). It's concave (the gap side is towards
+    /// the center) or convex.
+    Arc {
+        center: (f64, f64),
+        radius: f64,
+        from: f64,
+        to: f64,
+        concave: bool,
+    },
+    /// A straight line from `start`, `len` long in (unit) direction `dir`, with the gap on its left
+    Line {
+        start: (f64, f64),
+        dir: (f64, f64),
+        len: f64,
+    },
+}
+
+impl Segment {
+    fn len(self) -> f64 {
+        match self {
+            Segment::Arc {
+                radius, from, to, ..
+            } => radius * (to - from).abs(),
+            Segment::Line { len, .. } => len,
+        }
+    }
+
+    /// The point at fraction `t` of the way along
+    fn at(self, t: f64) -> ProfilePoint {
+        let ((y, z), normal) = match self {
+            Segment::Arc {
+                center,
+                radius,
+                from,
+                to,
+                concave,
+            } => {
+                let angle = from + t * (to - from);
+                let (sin, cos) = angle.sin_cos();
+                let point = (center.0 + radius * cos, center.1 + radius * sin);
+                if concave {
+                    (point, (-cos, -sin))
+                } else {
+                    (point, (cos, sin))
+                }
+            }
+            Segment::Line { start, dir, len } => (
+                (start.0 + t * len * dir.0, start.1 + t * len * dir.1),
+                (-dir.1, dir.0),
+            ),
+        };
+        ProfilePoint { y, z, normal }
+    }
+}
+
+/// Points about `step` apart along the +Y flank made of `segments`, from the bottom of the gap out to where it meets
+/// the outside diameter (or the middle of the tooth, `half_tooth` radians from the middle of the gap, if that comes
+/// first).
+pub(crate) fn sample(
+    segments: &[Segment],
+    step: f64,
+    outside_radius: f64,
+    half_tooth: f64,
+) -> Vec<ProfilePoint> {
+    // Past the outside diameter, or over the middle of the tooth into the next gap
+    let outside = |p: ProfilePoint| p.y.hypot(p.z) > outside_radius || p.y.atan2(p.z) > half_tooth;
+
+    let mut points = vec![];
+    for &segment in segments {
+        let steps = (segment.len() / step).ceil().max(1.0) as u32;
+        // Skip the start of each segment after the first, it's the end of the last one
+        let first = if points.is_empty() { 0 } else { 1 };
+        for i in first..=steps {
+            let t = i as f64 / steps as f64;
+            let p = segment.at(t);
+            if outside(p) {
+                // Find where we cross the edge, and stop there
+                let (mut lo, mut hi) = (i.saturating_sub(1) as f64 / steps as f64, t);
+                for _ in 0..50 {
+                    let mid = (lo + hi) / 2.0;
+                    if outside(segment.at(mid)) {
+                        hi = mid;
+                    } else {
+                        lo = mid;
+                    }
+                }
+                points.push(segment.at(lo));
+                return points;
+            }
+            points.push(p);
+        }
+    }
+    points
+}
+
+/// How to cut a gap with a ball end mill, all in the job's units
+#[derive(Debug, Clone, Copy)]
+pub struct BallCut {
+    /// Diameter of the ball end mill, which is measured to its tip
+    pub tool_dia: f64,
+    /// Width of the part, cut from X0 to -width
+    pub width: f64,
+    /// Most to take off in each roughing layer
+    pub max_depth: f64,
+    pub feed: f64,
+    /// Outside diameter of the stock
+    pub stock_dia: f64,
+    pub units: Units,
+    pub dialect: Dialect,
+}
+
+/// Positions of the center of the ball, for it to touch each point along `flank`, in the job's units
+pub fn ball_path(flank: &[ProfilePoint], tool_dia: f64, units: Units) -> Vec<(f64, f64)> {
+    let ball_radius = tool_dia / 2.0;
+    flank
+        .iter()
+        .map(|p| {
+            (
+                units.from_mm(p.y) + ball_radius * p.normal.0,
+                units.from_mm(p.z) + ball_radius * p.normal.1,
+            )
+        })
+        .collect()
+}
+
+/// Half-width of the path of the ball's center across the gap, at height `z`
+fn half_width_at(path: &[(f64, f64)], z: f64) -> f64 {
+    for pair in path.windows(2) {
+        let ((y0, z0), (y1, z1)) = (pair[0], pair[1]);
+        if z0 <= z && z <= z1 && z1 > z0 {
+            return y0 + (y1 - y0) * (z - z0) / (z1 - z0);
+        }
+    }
+    // Above the end of the flank, so stay inside where it finished at the top
+    path.iter()
+        .fold((0.0, f64::MIN), |top, &p| if p.1 > top.1 { p } else { top })
+        .0
+}
+
+/// One cut along the full width, with the center of the ball at (`y_pos`, `z_pos`)
+fn pass(cut: &BallCut, file: &mut dyn Write, y_pos: f64, z_pos: f64) -> Result<()> {
+    let u = cut.units;
+    // Clearance (4mm) away from the stock where we move at rapid
+    let clearance = u.from_mm(4.0);
+    let x_clearance = cut.tool_dia / 2.0 + clearance;
+    let safe_z = cut.stock_dia / 2.0 + clearance;
+
+    // Rapid to our starting point, to the right of the stock, then down to depth. The tool is measured to its tip,
+    // which is a ball radius below the ball's center.
+    g0(file, x(x_clearance).units(u))?;
+    g0(file, y(y_pos).units(u))?;
+    g0(file, z(z_pos - cut.tool_dia / 2.0).units(u))?;
+
+    // Feed into the stock, cutting as we go
+    g1(file, xf(-cut.width, cut.feed).units(u))?;
+
+    // Then lift straight out of the gap, which is already clear all the way up
+    g0(file, z(safe_z).units(u))
+}
+
+/// Cut the gap that's turned to the top, with the ball's center following `path` (from `ball_path`) on the +Y flank,
+/// and its mirror image on the -Y flank.
+pub fn cut_gap(file: &mut dyn Write, cut: &BallCut, path: &[(f64, f64)]) -> Result<()> {
+    let ball_radius = cut.tool_dia / 2.0;
+    let top = cut.stock_dia / 2.0;
+    let bottom = path[0].1;
+
+    // Rough out the gap in layers, with passes across each one no more than a ball radius apart
+    let mut level = top + ball_radius;
+    while level > bottom {
+        level = (level - cut.max_depth).max(bottom);
+        gcode_comment(
+            file,
+            &format!(
+                "Roughing at depth {:.4}{}",
+                top - (level - ball_radius),
+                cut.units.abbrev()
+            ),
+            cut.dialect,
+        )?;
+        let half_width = half_width_at(path, level);
+        let passes = (2.0 * half_width / ball_radius).ceil() as u32;
+        for i in 0..=passes {
+            let y_pos = if passes == 0 {
+                0.0
+            } else {
+                -half_width + 2.0 * half_width * i as f64 / passes as f64
+            };
+            pass(cut, file, y_pos, level)?;
+        }
+    }
+
+    // Then finish each flank, from the top down, so each pass takes off no more than the scallops between roughing
+    // passes
+    for &side in &[1.0, -1.0] {
+        gcode_comment(
+            file,
+            if side > 0.0 {
+                "Finishing +Y flank"
+            } else {
+                "Finishing -Y flank"
+            },
+            cut.dialect,
+        )?;
+        for &(y_pos, z_pos) in path.iter().rev() {
+            pass(cut, file, side * y_pos, z_pos)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,187 @@
+//! Geometry of timing belt pulleys.
+//! Lengths are in mm. Each groove is the usual nominal arc description of the belt's tooth: a circular groove
+//! blended into the outside diameter by a fillet on each side. That's close to, but not quite, the manufacturers'
+//! production profiles.
+use crate::profile::{self, ProfilePoint, Segment};
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// Timing belt tooth profiles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Belt {
+    /// GT2, with a 2mm pitch
+    Gt2,
+    /// HTD, with a 5mm pitch
+    Htd5,
+}
+
+impl Belt {
+    /// Distance between teeth, along the belt's pitch line
+    pub fn pitch(self) -> f64 {
+        match self {
+            Belt::Gt2 => 2.0,
+            Belt::Htd5 => 5.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Belt::Gt2 => "GT2 2mm",
+            Belt::Htd5 => "HTD 5mm",
+        }
+    }
+
+    /// How far the belt's pitch line (in its tension cords) sits outside the pulley's outside diameter
+    fn pitch_line_offset(self) -> f64 {
+        match self {
+            Belt::Gt2 => 0.254,
+            Belt::Htd5 => 0.5715,
+        }
+    }
+
+    /// Depth of the groove, from the outside diameter
+    fn groove_depth(self) -> f64 {
+        match self {
+            Belt::Gt2 => 0.75,
+            Belt::Htd5 => 2.06,
+        }
+    }
+
+    /// Radius of the arc at the bottom of the groove
+    pub fn groove_radius(self) -> f64 {
+        match self {
+            Belt::Gt2 => 0.555,
+            Belt::Htd5 => 1.49,
+        }
+    }
+
+    /// Radius of the fillets where the groove meets the outside diameter
+    fn fillet_radius(self) -> f64 {
+        match self {
+            Belt::Gt2 => 0.15,
+            Belt::Htd5 => 0.43,
+        }
+    }
+}
+
+impl FromStr for Belt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gt2" | "2gt" | "gt2-2mm" => Ok(Belt::Gt2),
+            "htd5" | "5m" | "htd-5m" => Ok(Belt::Htd5),
+            _ => Err(format!("Unknown belt {}, expected gt2 or htd5", s)),
+        }
+    }
+}
+
+/// Diameter of the belt's pitch line, wrapped around the pulley
+pub fn pitch_diameter(belt: Belt, teeth: u32) -> f64 {
+    teeth as f64 * belt.pitch() / PI
+}
+
+/// Outside diameter of the pulley, which is also the diameter to turn the stock to
+pub fn outside_diameter(belt: Belt, teeth: u32) -> f64 {
+    pitch_diameter(belt, teeth) - 2.0 * belt.pitch_line_offset()
+}
+
+/// The +Y flank of a groove, from the bottom of the groove out to the outside diameter, with points about `step`
+/// apart. The -Y flank is its mirror image.
+///
+/// Fails if there are too few teeth to fit the fillets between the grooves.
+pub fn gap_flank(belt: Belt, teeth: u32, step: f64) -> Result<Vec<ProfilePoint>, String> {
+    let outside_radius = outside_diameter(belt, teeth) / 2.0;
+    let groove_radius = belt.groove_radius();
+    let fillet_radius = belt.fillet_radius();
+    let groove_center = outside_radius - belt.groove_depth() + groove_radius;
+
+    // The fillet touches the outside of the groove's arc, and the inside of the outside diameter, so its center is
+    // groove_radius + fillet_radius from the groove's center, and outside_radius - fillet_radius from the axis
+    let to_axis = outside_radius - fillet_radius;
+    let to_groove = groove_radius + fillet_radius;
+    let fillet_z = (to_axis * to_axis - to_groove * to_groove + groove_center * groove_center)
+        / (2.0 * groove_center);
+    let fillet_y = (to_axis * to_axis - fillet_z * fillet_z).sqrt();
+    let half_tooth = PI / teeth as f64;
+    if fillet_y.is_nan() || fillet_y.atan2(fillet_z) > half_tooth {
+        return Err(format!(
+            "{} teeth is too few for a {} pulley",
+            teeth,
+            belt.name()
+        ));
+    }
+
+    // The groove turns until it's facing the fillet's center, then the fillet blends it out to the outside diameter
+    let tangent = (fillet_z - groove_center).atan2(fillet_y);
+    let segments = [
+        Segment::Arc {
+            center: (0.0, groove_center),
+            radius: groove_radius,
+            from: -PI / 2.0,
+            to: tangent,
+            concave: true,
+        },
+        Segment::Arc {
+            center: (fillet_y, fillet_z),
+            radius: fillet_radius,
+            from: tangent + PI,
+            to: fillet_z.atan2(fillet_y),
+            concave: false,
+        },
+    ];
+    Ok(profile::sample(&segments, step, outside_radius, half_tooth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(got: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (got - expected).abs() <= tolerance,
+            "got {}, expected {}",
+            got,
+            expected
+        );
+    }
+
+    #[test]
+    fn pitch_and_outside_diameters() {
+        // The usual 20 tooth pulleys: 12.73mm pitch and 12.22mm outside diameter for GT2, and 31.83mm and 30.69mm for
+        // HTD 5mm
+        assert_close(pitch_diameter(Belt::Gt2, 20), 12.732, 1e-3);
+        assert_close(outside_diameter(Belt::Gt2, 20), 12.224, 1e-3);
+        assert_close(pitch_diameter(Belt::Htd5, 20), 31.831, 1e-3);
+        assert_close(outside_diameter(Belt::Htd5, 20), 30.688, 1e-3);
+        // The belt's pitch line, wrapped round the pulley, fits a whole number of teeth
+        for belt in [Belt::Gt2, Belt::Htd5] {
+            for teeth in [12, 20, 60] {
+                let around = PI * pitch_diameter(belt, teeth) / belt.pitch();
+                assert_close(around, teeth as f64, 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn groove_flank() {
+        for belt in [Belt::Gt2, Belt::Htd5] {
+            let flank = gap_flank(belt, 20, 0.02).unwrap();
+            let outside_radius = outside_diameter(belt, 20) / 2.0;
+            // From the bottom of the groove, groove depth under the outside diameter, out to the outside diameter
+            let (first, last) = (flank[0], *flank.last().unwrap());
+            assert_close(first.y, 0.0, 1e-9);
+            assert_close(first.z, outside_radius - belt.groove_depth(), 1e-9);
+            assert_close(last.y.hypot(last.z), outside_radius, 1e-6);
+        }
+        // Too few teeth to fit the fillets between the grooves
+        assert!(gap_flank(Belt::Htd5, 3, 0.02).is_err());
+    }
+
+    #[test]
+    fn belt_names() {
+        assert_eq!("GT2".parse(), Ok(Belt::Gt2));
+        assert_eq!("5m".parse(), Ok(Belt::Htd5));
+        assert!("xl".parse::<Belt>().is_err());
+    }
+}
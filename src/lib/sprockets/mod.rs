@@ -0,0 +1,172 @@
+//! Geometry of sprockets for standard (ANSI) roller chain.
+//! Lengths are in mm, unless noted otherwise. The tooth form is the ANSI standard one from the Machinery's Handbook,
+//! 31st Edition, "Sprockets for Roller Chain", whose formulas are in inches: the small fixed clearances in them are
+//! converted here.
+use crate::profile::{self, ProfilePoint, Segment};
+use std::f64::consts::PI;
+
+/// One inch, in mm
+const INCH: f64 = 25.4;
+
+/// Fewest teeth the standard tooth form works for. Below this, the straight part of the flank has negative length.
+pub const MIN_TEETH: u32 = 5;
+
+/// Diameter of the pitch circle, which passes through the centers of the rollers
+pub fn pitch_diameter(pitch: f64, teeth: u32) -> f64 {
+    pitch / (PI / teeth as f64).sin()
+}
+
+/// Diameter of the seating curve, at the bottom of each tooth gap, which is a little bigger than the roller
+pub fn seating_diameter(roller_dia: f64) -> f64 {
+    1.005 * roller_dia + 0.003 * INCH
+}
+
+/// Diameter of the circle through the bottoms of the tooth gaps
+pub fn bottom_diameter(pitch: f64, roller_dia: f64, teeth: u32) -> f64 {
+    pitch_diameter(pitch, teeth) - seating_diameter(roller_dia)
+}
+
+/// Outside diameter to turn the blank to. The standard gives this as an approximation, with the tooth tips left
+/// wherever they meet it.
+pub fn outside_diameter(pitch: f64, teeth: u32) -> f64 {
+    pitch * (0.6 + 1.0 / (PI / teeth as f64).tan())
+}
+
+/// The pieces of the +Y flank of a tooth gap, from the bottom of the gap outwards.
+/// They're worked out around the center of the roller seated in the gap, with x along the pitch line towards the next
+/// gap and y outwards, then moved out to the pitch circle.
+///
+/// The seating curve (radius R) is centered on the roller, and runs out to angle A below the pitch line. From there
+/// the working curve (radius E) turns through angle B, around a center 0.8 Dr from the roller's, then a straight
+/// line runs tangent to it, and the topping curve (radius F) rounds the tooth off around a center 1.4 Dr along the
+/// pitch line towards the next roller.
+fn flank_segments(pitch: f64, roller_dia: f64, teeth: u32) -> Vec<Segment> {
+    let n = teeth as f64;
+    let dr = roller_dia;
+    let a = (35.0 + 60.0 / n).to_radians();
+    let b = (18.0 - 56.0 / n).to_radians();
+    let (sin_phi, cos_phi) = (180.0 / n).to_radians().sin_cos();
+
+    let pitch_radius = pitch_diameter(pitch, teeth) / 2.0;
+    let to_gap = |(x, y): (f64, f64)| (x, pitch_radius + y);
+
+    let r = seating_diameter(dr) / 2.0;
+    let e = 1.3025 * dr + 0.0015 * INCH;
+    let working_center = (-0.8 * dr * a.cos(), 0.8 * dr * a.sin());
+    let topping_center = (1.4 * dr * cos_phi, -1.4 * dr * sin_phi);
+    let angle_17 = (17.0 - 64.0 / n).to_radians();
+    let f = dr * (0.8 * b.cos() + 1.4 * angle_17.cos() - 1.3025) - 0.0015 * INCH;
+    let line_len = dr * (1.4 * angle_17.sin() - 0.8 * b.sin());
+
+    // The working curve ends at angle B - A around its center, and the line carries on along the tangent there
+    let end = b - a;
+    let line_start = (
+        working_center.0 + e * end.cos(),
+        working_center.1 + e * end.sin(),
+    );
+    let dir = (-end.sin(), end.cos());
+    // The topping curve starts where the line touches it, opposite the line's normal into the tooth, and can turn up
+    // to half a circle before it's back on itself
+    let topping_start = end + PI;
+    vec![
+        Segment::Arc {
+            center: to_gap((0.0, 0.0)),
+            radius: r,
+            from: -PI / 2.0,
+            to: -a,
+            concave: true,
+        },
+        Segment::Arc {
+            center: to_gap(working_center),
+            radius: e,
+            from: -a,
+            to: end,
+            concave: true,
+        },
+        Segment::Line {
+            start: to_gap(line_start),
+            dir,
+            len: line_len,
+        },
+        Segment::Arc {
+            center: to_gap(topping_center),
+            radius: f,
+            from: topping_start,
+            to: topping_start - PI,
+            concave: false,
+        },
+    ]
+}
+
+/// The +Y flank of a tooth gap, from the bottom of the gap out to where it meets the outside diameter (or the middle
+/// of the tooth, if that comes first), with points about `step` apart. The -Y flank is its mirror image.
+///
+/// Fails if there are too few teeth for the standard tooth form.
+pub fn gap_flank(
+    pitch: f64,
+    roller_dia: f64,
+    teeth: u32,
+    step: f64,
+) -> Result<Vec<ProfilePoint>, String> {
+    if teeth < MIN_TEETH {
+        return Err(format!(
+            "Standard sprockets need at least {} teeth, not {}",
+            MIN_TEETH, teeth
+        ));
+    }
+    Ok(profile::sample(
+        &flank_segments(pitch, roller_dia, teeth),
+        step,
+        outside_diameter(pitch, teeth) / 2.0,
+        PI / teeth as f64,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(got: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (got - expected).abs() <= tolerance,
+            "got {}, expected {}",
+            got,
+            expected
+        );
+    }
+
+    #[test]
+    fn number_35_chain_sprocket() {
+        // #35 chain has a 3/8in pitch and 0.200in rollers. For 20 teeth the handbook gives a 2.3972in pitch
+        // diameter and a 2.59in outside diameter. The bottom diameter is the pitch diameter less the 0.204in seating
+        // curve.
+        let (pitch, roller) = (0.375 * INCH, 0.200 * INCH);
+        assert_close(pitch_diameter(pitch, 20) / INCH, 2.3972, 1e-4);
+        assert_close(outside_diameter(pitch, 20) / INCH, 2.5927, 1e-4);
+        assert_close(seating_diameter(roller) / INCH, 0.204, 1e-12);
+        assert_close(bottom_diameter(pitch, roller, 20) / INCH, 2.1932, 1e-4);
+        // Adjacent rollers, seated round the pitch circle, are a pitch apart
+        for teeth in [MIN_TEETH, 11, 20, 60] {
+            let radius = pitch_diameter(pitch, teeth) / 2.0;
+            let angle = 2.0 * PI / teeth as f64;
+            assert_close(2.0 * radius * (angle / 2.0).sin(), pitch, 1e-9);
+        }
+    }
+
+    #[test]
+    fn number_35_tooth_gap() {
+        let (pitch, roller) = (0.375 * INCH, 0.200 * INCH);
+        let flank = gap_flank(pitch, roller, 20, 0.05).unwrap();
+        // It starts at the bottom of the gap, and goes no further out than the outside diameter or the middle of the
+        // tooth
+        let first = flank[0];
+        assert_close(first.y, 0.0, 1e-9);
+        assert_close(first.z, bottom_diameter(pitch, roller, 20) / 2.0, 1e-9);
+        for p in &flank {
+            assert!(p.y.hypot(p.z) <= outside_diameter(pitch, 20) / 2.0 + 1e-6);
+            assert!(p.y.atan2(p.z) <= PI / 20.0 + 1e-6);
+            assert_close(p.normal.0.hypot(p.normal.1), 1.0, 1e-9);
+        }
+        assert!(gap_flank(pitch, roller, MIN_TEETH - 1, 0.05).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! SVG previews of toolpaths, looking down the Z axis onto the X-Y plane.
+//! Rapids are drawn as thin grey lines and feeds as black ones, over an outline of the stock. Z and A are ignored, so
+//! a 4th axis job shows each pass along X, but not how far round the part has turned.
+use crate::{xy_segments, Move, XySegment as Segment};
+use std::io::{Result, Write};
+
+/// Outline of the stock, in the same units and X-Y frame as the toolpath
+#[derive(Debug, Clone, Copy)]
+pub enum Stock {
+    Rect { min: (f64, f64), max: (f64, f64) },
+    Circle { center: (f64, f64), radius: f64 },
+}
+
+/// Path data for `segments`, only moving the pen where they don't join up
+fn path_data(segments: &[Segment]) -> String {
+    let mut d = String::new();
+    let mut pen = None;
+    for segment in segments {
+        let (from, to) = match segment {
+            Segment::Line(from, to) | Segment::Arc(from, to, _, _) => (*from, *to),
+        };
+        if pen != Some(from) {
+            d.push_str(&format!("M{:.4} {:.4} ", from.0, from.1));
+        }
+        match segment {
+            Segment::Line(..) => d.push_str(&format!("L{:.4} {:.4} ", to.0, to.1)),
+            // With Y up, SVG's positive-angle sweep is counter-clockwise
+            Segment::Arc(_, _, r, ccw) => d.push_str(&format!(
+                "A{r:.4} {r:.4} 0 {} {} {:.4} {:.4} ",
+                (*r < 0.0) as u8,
+                *ccw as u8,
+                to.0,
+                to.1,
+                r = r.abs()
+            )),
+        }
+        pen = Some(to);
+    }
+    d
+}
+
+/// Write an SVG of `moves`, and the `stock` outline if there is one
+pub fn render(moves: &[Move], stock: Option<Stock>, file: &mut dyn Write) -> Result<()> {
+    let (rapids, feeds) = xy_segments(moves);
+
+    // Fit everything in the view, with a margin around it
+    let mut points: Vec<(f64, f64)> = rapids
+        .iter()
+        .chain(feeds.iter())
+        .flat_map(|s| match s {
+            Segment::Line(from, to) | Segment::Arc(from, to, _, _) => vec![*from, *to],
+        })
+        .collect();
+    match stock {
+        Some(Stock::Rect { min, max }) => points.extend(&[min, max]),
+        Some(Stock::Circle { center, radius }) => points.extend(&[
+            (center.0 - radius, center.1 - radius),
+            (center.0 + radius, center.1 + radius),
+        ]),
+        None => (),
+    }
+    let (mut min, mut max) = ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN));
+    for (x, y) in points {
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+    }
+    if min.0 > max.0 {
+        min = (0.0, 0.0);
+        max = (0.0, 0.0);
+    }
+    let size = (max.0 - min.0).max(max.1 - min.1).max(1.0);
+    let margin = size * 0.05;
+    let stroke = size * 0.002;
+
+    // SVG's Y axis points down, so flip everything over to put +Y up like the machine
+    writeln!(
+        file,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{:.4} {:.4} {:.4} {:.4}">"#,
+        min.0 - margin,
+        -max.1 - margin,
+        max.0 - min.0 + 2.0 * margin,
+        max.1 - min.1 + 2.0 * margin
+    )?;
+    writeln!(file, r#"<g transform="scale(1,-1)" fill="none">"#)?;
+    match stock {
+        Some(Stock::Rect { min, max }) => writeln!(
+            file,
+            r#"<rect x="{:.4}" y="{:.4}" width="{:.4}" height="{:.4}" stroke="blue" stroke-width="{:.4}"/>"#,
+            min.0,
+            min.1,
+            max.0 - min.0,
+            max.1 - min.1,
+            stroke
+        )?,
+        Some(Stock::Circle { center, radius }) => writeln!(
+            file,
+            r#"<circle cx="{:.4}" cy="{:.4}" r="{:.4}" stroke="blue" stroke-width="{:.4}"/>"#,
+            center.0, center.1, radius, stroke
+        )?,
+        None => (),
+    }
+    writeln!(
+        file,
+        r#"<path d="{}" stroke="grey" stroke-width="{:.4}"/>"#,
+        path_data(&rapids).trim_end(),
+        stroke / 2.0
+    )?;
+    writeln!(
+        file,
+        r#"<path d="{}" stroke="black" stroke-width="{:.4}"/>"#,
+        path_data(&feeds).trim_end(),
+        stroke
+    )?;
+    writeln!(file, "</g>")?;
+    writeln!(file, "</svg>")
+}